@@ -0,0 +1,68 @@
+//! How long reading a count takes with `rdpmc` through
+//! [`UserCounter::read`], compared with a group read through
+//! [`EventSet::get_counts`] and with the enable, read and disable of
+//! each sample of the X11 plot.
+//!
+//! `cargo bench --bench read_overhead`
+
+use std::{
+    hint::black_box,
+    io,
+    time::{Duration, Instant},
+};
+
+use cpu_perf::perf_events::{EventIOState, EventSet, EventSpec, EventType, UserCounter};
+
+const READS: u32 = 100_000;
+
+fn main() -> io::Result<()> {
+    // Software events cannot be read with rdpmc, so show the fallback
+    // where there is no PMU, as in most virtual machines
+    let event = match UserCounter::new(EventSpec::new(EventType::Instructions)) {
+        Ok(_) => EventType::Instructions,
+        Err(_) => EventType::TaskClock,
+    };
+    let tid = unsafe { libc::gettid() } as u32;
+
+    let counter = UserCounter::new(EventSpec::new(event))?;
+    counter.enable()?;
+    let rdpmc = time_reads(|| counter.read())?;
+
+    let event_set = EventSet::new(&[event], None, Some(tid))?;
+    event_set.update_file_state(EventIOState::Enable)?;
+    let get_counts = time_reads(|| event_set.get_counts())?;
+
+    let plot_sample = time_reads(|| {
+        event_set.update_file_state(EventIOState::Disable)?;
+        let counts = event_set.get_counts()?;
+        event_set.update_file_state(EventIOState::Enable)?;
+        Ok(counts)
+    })?;
+
+    println!("Reading {:?} {} times", event, READS);
+    let read = match counter.has_rdpmc() {
+        true => "rdpmc",
+        false => "read, as rdpmc is unavailable",
+    };
+    print_time(&format!("UserCounter::read ({})", read), rdpmc);
+    print_time("EventSet::get_counts", get_counts);
+    print_time("disable, get_counts, enable", plot_sample);
+    Ok(())
+}
+
+/// The average time of `read` over [`READS`] calls.
+fn time_reads<T>(mut read: impl FnMut() -> io::Result<T>) -> io::Result<Duration> {
+    // Warm up the caches and the branch predictors
+    for _ in 0..READS / 10 {
+        black_box(read()?);
+    }
+    let started = Instant::now();
+    for _ in 0..READS {
+        black_box(read()?);
+    }
+    Ok(started.elapsed() / READS)
+}
+
+fn print_time(name: &str, time: Duration) {
+    println!("  {:<50}  {:>8.1} ns", name, time.as_secs_f64() * 1e9);
+}
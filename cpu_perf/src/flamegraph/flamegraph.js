@@ -0,0 +1,157 @@
+// Hover, zoom and search for flamegraphs rendered by cpu_perf. Expects
+// xpad, fontsize and fontwidth to be defined before it, and each frame
+// to carry its position in weight units as data-start, data-value and
+// data-depth, so zooming needs no rounding.
+var svg, frames, details, unzoombtn, searchbtn, matchedtxt;
+var searching = false;
+
+function init(evt) {
+	svg = document.documentElement;
+	frames = document.getElementById("frames").children;
+	details = document.getElementById("details").firstChild;
+	unzoombtn = document.getElementById("unzoom");
+	searchbtn = document.getElementById("search");
+	matchedtxt = document.getElementById("matched").firstChild;
+	for (var i = 0; i < frames.length; i++) {
+		var g = frames[i];
+		g.onmouseover = function () { details.nodeValue = this.querySelector("title").textContent; };
+		g.onmouseout = function () { details.nodeValue = " "; };
+		g.onclick = function () { zoom(this); };
+	}
+	unzoombtn.onclick = unzoom;
+	searchbtn.onclick = search_prompt;
+	window.addEventListener("keydown", function (e) {
+		if (e.keyCode === 114 || ((e.ctrlKey || e.metaKey) && e.keyCode === 70)) {
+			e.preventDefault();
+			search_prompt();
+		}
+	});
+}
+
+function attr(g, name) {
+	return Number(g.getAttribute("data-" + name));
+}
+
+function frame_name(g) {
+	var title = g.querySelector("title").textContent;
+	return title.substring(0, title.lastIndexOf(" ("));
+}
+
+function total_width() {
+	return svg.width.baseVal.value - 2 * xpad;
+}
+
+// Place a frame covering [start, start + value) of a view starting at
+// view_start and value wide, refitting its label
+function place(g, view_start, view_value) {
+	var scale = total_width() / view_value;
+	var x = xpad + (attr(g, "start") - view_start) * scale;
+	var w = attr(g, "value") * scale;
+	var rect = g.querySelector("rect");
+	rect.setAttribute("x", x.toFixed(2));
+	rect.setAttribute("width", w.toFixed(2));
+	var text = g.querySelector("text");
+	text.setAttribute("x", (x + 3).toFixed(2));
+	var chars = Math.floor((w - 6) / (fontsize * fontwidth));
+	var name = frame_name(g);
+	if (chars < 3) {
+		text.textContent = "";
+	} else if (name.length <= chars) {
+		text.textContent = name;
+	} else {
+		text.textContent = name.substring(0, chars - 2) + "..";
+	}
+}
+
+function zoom(node) {
+	var start = attr(node, "start");
+	var value = attr(node, "value");
+	var depth = attr(node, "depth");
+	unzoombtn.classList.remove("hide");
+	for (var i = 0; i < frames.length; i++) {
+		var g = frames[i];
+		var g_start = attr(g, "start");
+		var g_end = g_start + attr(g, "value");
+		g.classList.remove("hide");
+		g.classList.remove("parent");
+		if (attr(g, "depth") < depth) {
+			if (g_start <= start && g_end >= start + value) {
+				// Ancestors span the whole width, faded
+				g.classList.add("parent");
+				place(g, g_start, g_end - g_start);
+			} else {
+				g.classList.add("hide");
+			}
+		} else if (g_start >= start && g_end <= start + value) {
+			place(g, start, value);
+		} else {
+			g.classList.add("hide");
+		}
+	}
+}
+
+function unzoom() {
+	unzoombtn.classList.add("hide");
+	var root = frames[0];
+	for (var i = 0; i < frames.length; i++) {
+		frames[i].classList.remove("hide");
+		frames[i].classList.remove("parent");
+		place(frames[i], attr(root, "start"), attr(root, "value"));
+	}
+}
+
+function search_prompt() {
+	if (searching) {
+		reset_search();
+		return;
+	}
+	var term = prompt("Enter a search term (regexp allowed)", "");
+	if (term) {
+		search(term);
+	}
+}
+
+function reset_search() {
+	searching = false;
+	searchbtn.firstChild.nodeValue = "Search";
+	matchedtxt.nodeValue = " ";
+	for (var i = 0; i < frames.length; i++) {
+		var rect = frames[i].querySelector("rect");
+		if (rect.hasAttribute("data-fill")) {
+			rect.setAttribute("fill", rect.getAttribute("data-fill"));
+			rect.removeAttribute("data-fill");
+		}
+	}
+}
+
+function search(term) {
+	var re = new RegExp(term);
+	var matches = [];
+	for (var i = 0; i < frames.length; i++) {
+		var g = frames[i];
+		if (!re.test(frame_name(g))) {
+			continue;
+		}
+		var rect = g.querySelector("rect");
+		rect.setAttribute("data-fill", rect.getAttribute("fill"));
+		rect.setAttribute("fill", "rgb(230,0,230)");
+		matches.push([attr(g, "start"), attr(g, "start") + attr(g, "value")]);
+	}
+	searching = true;
+	searchbtn.firstChild.nodeValue = "Reset Search";
+	// Matches nested in other matches are only counted once
+	matches.sort(function (a, b) { return a[0] - b[0] || b[1] - a[1]; });
+	var covered = 0;
+	var end = -1;
+	for (var j = 0; j < matches.length; j++) {
+		if (matches[j][0] >= end) {
+			covered += matches[j][1] - matches[j][0];
+			end = matches[j][1];
+		} else if (matches[j][1] > end) {
+			covered += matches[j][1] - end;
+			end = matches[j][1];
+		}
+	}
+	var total = attr(frames[0], "value");
+	matchedtxt.nodeValue = "Matched: " + (total > 0 ? (100 * covered / total).toFixed(1) : "0.0") + "%";
+}
@@ -0,0 +1,127 @@
+use std::{collections::HashMap, fmt, io};
+
+/// Weights of whole stacks, written in the folded format read by
+/// Brendan Gregg's `flamegraph.pl`: one line per stack, with frames
+/// from the root to the leaf joined by `;`, then a space and the
+/// weight.
+///
+/// The weight can be anything added up per stack, such as the number
+/// of samples or the sample periods of an event like cache misses.
+///
+/// ```
+/// use cpu_perf::flamegraph::FoldedStacks;
+///
+/// let mut stacks = FoldedStacks::default();
+/// stacks.add(["app", "main", "parse"], 3);
+/// stacks.add(["app", "main", "render"], 1);
+/// stacks.add(["app", "main", "parse"], 4);
+///
+/// assert_eq!(stacks.total(), 8);
+/// assert_eq!(stacks.to_string(), "app;main;parse 7\napp;main;render 1\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoldedStacks {
+    weights: HashMap<String, u64>,
+    total: u64,
+}
+
+impl FoldedStacks {
+    /// Add `weight` to the stack of `frames`, given root first. A `;`
+    /// in a frame would split it in two, so it is replaced by `:`.
+    pub fn add<S: AsRef<str>>(&mut self, frames: impl IntoIterator<Item = S>, weight: u64) {
+        let mut stack = String::new();
+        for frame in frames {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            stack.push_str(&frame.as_ref().replace(';', ":"));
+        }
+        *self.weights.entry(stack).or_default() += weight;
+        self.total += weight;
+    }
+
+    /// The total weight of every stack.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The number of distinct stacks.
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Parse stacks in the folded format, adding the weights of
+    /// repeated stacks. Blank lines are skipped.
+    ///
+    /// ```
+    /// use cpu_perf::flamegraph::FoldedStacks;
+    ///
+    /// let stacks = FoldedStacks::parse("main;work 2\nmain;work 3\nmain 1\n").unwrap();
+    /// assert_eq!(stacks.stacks(), [("main", 1), ("main;work", 5)]);
+    ///
+    /// assert!(FoldedStacks::parse("main;work\n").is_err());
+    /// ```
+    pub fn parse(folded: &str) -> Result<Self, FoldedParseError> {
+        let mut stacks = Self::default();
+        for (i, line) in folded.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let (stack, weight) = line
+                .rsplit_once(' ')
+                .and_then(|(stack, weight)| Some((stack, weight.parse::<u64>().ok()?)))
+                .ok_or(FoldedParseError { line: i + 1 })?;
+            *stacks.weights.entry(stack.to_string()).or_default() += weight;
+            stacks.total += weight;
+        }
+        Ok(stacks)
+    }
+
+    /// Each stack and its weight, sorted by stack.
+    pub fn stacks(&self) -> Vec<(&str, u64)> {
+        let mut stacks: Vec<(&str, u64)> = self
+            .weights
+            .iter()
+            .map(|(stack, &weight)| (stack.as_str(), weight))
+            .collect();
+        stacks.sort();
+        stacks
+    }
+
+    /// Write the stacks in the folded format, sorted by stack.
+    pub fn write(&self, mut out: impl io::Write) -> io::Result<()> {
+        for (stack, weight) in self.stacks() {
+            writeln!(out, "{} {}", stack, weight)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FoldedStacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stack, weight) in self.stacks() {
+            writeln!(f, "{} {}", stack, weight)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line of folded stacks without a stack and a weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldedParseError {
+    /// Counting from 1.
+    pub line: usize,
+}
+
+impl std::error::Error for FoldedParseError {}
+
+impl fmt::Display for FoldedParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed folded stack on line {}", self.line)
+    }
+}
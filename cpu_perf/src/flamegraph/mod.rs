@@ -0,0 +1,5 @@
+mod folded;
+mod svg;
+
+pub use folded::*;
+pub use svg::*;
@@ -0,0 +1,385 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::FoldedStacks;
+
+const SCRIPT: &str = include_str!("flamegraph.js");
+
+/// Gap at the left and right of the frames.
+const XPAD: f64 = 10.0;
+/// Average width of a character relative to the font size.
+const FONT_WIDTH: f64 = 0.59;
+
+/// Renders folded stacks as an interactive SVG flamegraph, with the
+/// root at the bottom and each function as wide as its share of the
+/// total weight. Hovering shows a frame's weight, clicking zooms into
+/// it and Search (or Ctrl-F) highlights matching functions.
+///
+/// Colours are derived from function names, so the same function has
+/// the same colour in every graph.
+///
+/// ```
+/// use cpu_perf::flamegraph::{FlameGraph, FoldedStacks};
+///
+/// let stacks = FoldedStacks::parse("main;parse 3\nmain;render 1\n").unwrap();
+/// let svg = FlameGraph::new().with_title("Example").render(&stacks);
+/// assert!(svg.starts_with("<?xml"));
+/// assert!(svg.contains("<title>parse (3 samples, 75.00%)</title>"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlameGraph {
+    title: String,
+    width: u32,
+    frame_height: u32,
+    font_size: u32,
+    /// Frames narrower than this, in pixels, are left out.
+    min_width: f64,
+    count_name: String,
+}
+
+impl Default for FlameGraph {
+    fn default() -> Self {
+        Self {
+            title: "Flame Graph".to_string(),
+            width: 1200,
+            frame_height: 16,
+            font_size: 12,
+            min_width: 0.1,
+            count_name: "samples".to_string(),
+        }
+    }
+}
+
+/// A function in a particular stack, with the total weight of the
+/// stacks passing through it.
+#[derive(Debug, Default)]
+struct Node {
+    value: u64,
+    /// The weight in the profile compared against, for differential
+    /// graphs.
+    before: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn add(&mut self, stack: &str, value: u64, before: u64) {
+        let mut node = self;
+        node.value += value;
+        node.before += before;
+        for frame in stack.split(';') {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.value += value;
+            node.before += before;
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.children
+            .values()
+            .map(|child| child.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl FlameGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_title(self, title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ..self
+        }
+    }
+
+    /// The width of the image in pixels.
+    pub fn with_width(self, width: u32) -> Self {
+        Self { width, ..self }
+    }
+
+    /// What the weights count, `samples` by default, shown when
+    /// hovering over a frame.
+    pub fn with_count_name(self, count_name: impl Into<String>) -> Self {
+        Self {
+            count_name: count_name.into(),
+            ..self
+        }
+    }
+
+    /// Render `stacks` as an SVG document.
+    ///
+    /// ```
+    /// use cpu_perf::flamegraph::{FlameGraph, FoldedStacks};
+    ///
+    /// let stacks = FoldedStacks::parse("main;parse 2\nmain;render 2\n").unwrap();
+    /// let svg = FlameGraph::new().with_title("Parsing").render(&stacks);
+    /// assert!(svg.starts_with("<?xml"));
+    /// assert!(svg.contains("<title>parse (2 samples, 50.00%)</title>"));
+    /// ```
+    pub fn render(&self, stacks: &FoldedStacks) -> String {
+        let mut root = Node::default();
+        for (stack, weight) in stacks.stacks() {
+            root.add(stack, weight, 0);
+        }
+        self.render_tree(&root, None)
+    }
+
+    /// Render a differential flamegraph showing how `after` changed
+    /// from `before`. Frames are sized by `after`, and coloured red
+    /// where their share of the total grew and blue where it shrank,
+    /// more strongly the larger the change. Stacks only in `before`
+    /// have no width, so are left out.
+    ///
+    /// ```
+    /// use cpu_perf::flamegraph::{FlameGraph, FoldedStacks};
+    ///
+    /// let before = FoldedStacks::parse("main;parse 2\nmain;render 2\n").unwrap();
+    /// let after = FoldedStacks::parse("main;parse 3\nmain;render 1\n").unwrap();
+    /// let svg = FlameGraph::new().render_diff(&before, &after);
+    /// assert!(svg.contains("<title>parse (3 samples, 75.00%, +25.00%)</title>"));
+    /// assert!(svg.contains("<title>render (1 samples, 25.00%, -25.00%)</title>"));
+    /// ```
+    pub fn render_diff(&self, before: &FoldedStacks, after: &FoldedStacks) -> String {
+        let mut root = Node::default();
+        for (stack, weight) in after.stacks() {
+            root.add(stack, weight, 0);
+        }
+        for (stack, weight) in before.stacks() {
+            root.add(stack, 0, weight);
+        }
+        self.render_tree(&root, Some(before.total()))
+    }
+
+    fn render_tree(&self, root: &Node, before_total: Option<u64>) -> String {
+        let frame_height = self.frame_height as f64;
+        let font_size = self.font_size as f64;
+        let top = font_size * 3.0;
+        let bottom = font_size * 2.0 + 10.0;
+        let width = self.width as f64;
+        let depth = if root.value > 0 { root.depth() + 1 } else { 0 };
+        let height = top + depth as f64 * frame_height + bottom;
+
+        // Yellow tinted for plain graphs, blue for differential ones
+        let background_end = match before_total {
+            Some(_) => "#e0e0ff",
+            None => "#eeeeb0",
+        };
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r##"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{width}" height="{height}" onload="init(evt)" viewBox="0 0 {width} {height}" xmlns="http://www.w3.org/2000/svg">
+<defs>
+	<linearGradient id="background" y1="0" y2="1" x1="0" x2="0">
+		<stop stop-color="#eeeeee" offset="5%"/>
+		<stop stop-color="{background_end}" offset="95%"/>
+	</linearGradient>
+</defs>
+<style type="text/css">
+	text {{ font-family: Verdana, sans-serif; font-size: {font_size}px; fill: rgb(0,0,0); }}
+	#title {{ text-anchor: middle; font-size: {title_size}px; }}
+	#search, #unzoom {{ cursor: pointer; }}
+	#frames > g:hover {{ stroke: black; stroke-width: 0.5; cursor: pointer; }}
+	.hide {{ display: none; }}
+	.parent {{ opacity: 0.5; }}
+</style>
+<script type="text/ecmascript"><![CDATA[
+var xpad = {XPAD}, fontsize = {font_size}, fontwidth = {FONT_WIDTH};
+{SCRIPT}]]></script>
+<rect x="0" y="0" width="{width}" height="{height}" fill="url(#background)"/>
+<text id="title" x="{title_x}" y="{title_y}">{title}</text>
+<text id="details" x="{XPAD}" y="{footer_y}"> </text>
+<text id="unzoom" class="hide" x="{XPAD}" y="{title_y}">Reset Zoom</text>
+<text id="search" x="{search_x}" y="{title_y}">Search</text>
+<text id="matched" x="{search_x}" y="{footer_y}"> </text>
+<g id="frames">
+"##,
+            title_size = self.font_size + 5,
+            title_x = width / 2.0,
+            title_y = font_size * 2.0,
+            title = escape(&self.title),
+            footer_y = height - font_size - 5.0,
+            search_x = width - XPAD - 100.0,
+        );
+        if root.value > 0 {
+            let diff = before_total.map(|before_total| {
+                let mut max_delta = 0.0f64;
+                max_abs_delta(root, root.value, before_total, &mut max_delta);
+                (before_total, max_delta)
+            });
+            let frame = Frame {
+                name: "all",
+                node: root,
+                start: 0,
+                depth: 0,
+            };
+            self.render_frame(&mut svg, &frame, root.value, height - bottom, diff);
+        }
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+
+    /// Append `frame` and, depth first, the frames above it.
+    fn render_frame(
+        &self,
+        svg: &mut String,
+        frame: &Frame,
+        total: u64,
+        base: f64,
+        diff: Option<(u64, f64)>,
+    ) {
+        let scale = (self.width as f64 - 2.0 * XPAD) / total as f64;
+        let width = frame.node.value as f64 * scale;
+        if width < self.min_width {
+            return;
+        }
+        let x = XPAD + frame.start as f64 * scale;
+        let y = base - (frame.depth + 1) as f64 * self.frame_height as f64;
+        let percent = frame.node.value as f64 * 100.0 / total as f64;
+        let (details, fill) = match diff {
+            Some((before_total, max_delta)) => {
+                let delta = share_delta(frame.node, total, before_total);
+                (
+                    format!(
+                        "{} ({} {}, {:.2}%, {:+.2}%)",
+                        frame.name, frame.node.value, self.count_name, percent, delta
+                    ),
+                    diff_colour(delta, max_delta),
+                )
+            }
+            None => (
+                format!(
+                    "{} ({} {}, {:.2}%)",
+                    frame.name, frame.node.value, self.count_name, percent
+                ),
+                name_colour(frame.name),
+            ),
+        };
+        let _ = writeln!(
+            svg,
+            r#"<g data-start="{}" data-value="{}" data-depth="{}"><title>{}</title><rect x="{:.2}" y="{}" width="{:.2}" height="{}" fill="{}" rx="2" ry="2"/><text x="{:.2}" y="{}">{}</text></g>"#,
+            frame.start,
+            frame.node.value,
+            frame.depth,
+            escape(&details),
+            x,
+            y,
+            width,
+            self.frame_height - 1,
+            fill,
+            x + 3.0,
+            y + self.frame_height as f64 - 5.0,
+            escape(&self.label(frame.name, width)),
+        );
+        let mut start = frame.start;
+        for (name, node) in &frame.node.children {
+            let child = Frame {
+                name,
+                node,
+                start,
+                depth: frame.depth + 1,
+            };
+            self.render_frame(svg, &child, total, base, diff);
+            start += node.value;
+        }
+    }
+
+    /// As much of `name` as fits in a frame `width` pixels wide, the
+    /// same as the script does after zooming.
+    fn label(&self, name: &str, width: f64) -> String {
+        let chars = ((width - 6.0) / (self.font_size as f64 * FONT_WIDTH)).floor();
+        let count = name.chars().count();
+        if chars < 3.0 {
+            String::new()
+        } else if count as f64 <= chars {
+            name.to_string()
+        } else {
+            let mut label: String = name.chars().take(chars as usize - 2).collect();
+            label.push_str("..");
+            label
+        }
+    }
+}
+
+struct Frame<'a> {
+    name: &'a str,
+    node: &'a Node,
+    /// Offset of the frame in weight units from the left of the graph.
+    start: u64,
+    depth: usize,
+}
+
+/// The change in percentage points of the share of the total weight
+/// through `node`.
+fn share_delta(node: &Node, total: u64, before_total: u64) -> f64 {
+    let after = node.value as f64 * 100.0 / total as f64;
+    let before = match before_total {
+        0 => 0.0,
+        before_total => node.before as f64 * 100.0 / before_total as f64,
+    };
+    after - before
+}
+
+fn max_abs_delta(node: &Node, total: u64, before_total: u64, max: &mut f64) {
+    *max = max.max(share_delta(node, total, before_total).abs());
+    for child in node.children.values() {
+        max_abs_delta(child, total, before_total, max);
+    }
+}
+
+/// Red for growth, blue for shrinkage, fading to grey for no change.
+fn diff_colour(delta: f64, max_delta: f64) -> String {
+    let strength = if max_delta > 0.0 {
+        delta.abs() / max_delta
+    } else {
+        0.0
+    };
+    let faded = (210.0 * (1.0 - strength)).round() as u8;
+    if delta > 0.0 {
+        format!("rgb(255,{},{})", faded, faded)
+    } else if delta < 0.0 {
+        format!("rgb({},{},255)", faded, faded)
+    } else {
+        "rgb(210,210,210)".to_string()
+    }
+}
+
+/// A warm colour picked by hashing `name`, orange for kernel functions
+/// as marked by a `_[k]` suffix.
+fn name_colour(name: &str) -> String {
+    // FNV-1a, for colours which are the same on every run
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    let [v1, v2, v3, ..] = hash.to_le_bytes().map(|byte| byte as f64 / 255.0);
+    if name.ends_with("_[k]") {
+        format!(
+            "rgb({},{},{})",
+            (200.0 + 55.0 * v1).round(),
+            (110.0 + 60.0 * v2).round(),
+            (20.0 * v3).round()
+        )
+    } else {
+        format!(
+            "rgb({},{},{})",
+            (205.0 + 50.0 * v3).round(),
+            (230.0 * v1).round(),
+            (55.0 * v2).round()
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,4 +1,7 @@
+pub mod flamegraph;
+pub mod perf_data;
 pub mod perf_events;
 pub mod plot;
 pub mod sliding_window;
+pub mod symbols;
 pub mod window;
@@ -1,11 +1,27 @@
+use std::os::unix::process::ExitStatusExt;
 use std::thread::sleep;
-use std::time::Duration;
-use std::{io, time::SystemTime};
+use std::time::{Duration, Instant};
+use std::{
+    io::{self, Write},
+    time::SystemTime,
+};
 
 use cpu_perf::{
-    perf_events::{EventCounts, EventIOState, EventSet, EventType},
-    plot::{decorate_plot, plot_data_from_buffer},
+    flamegraph::{FlameGraph, FoldedStacks},
+    perf_data::{Features, PerfData, PerfDataEvent, PerfDataWriter, comm_record, mmap2_record},
+    perf_events::{
+        BranchProfile, Cgroup, CpuMode, EventCounts, EventIOState, EventParser, EventSpec,
+        EventType, ExecError, PausedCommand, ProcessEventSet, Record, Sample, SampleRate, Sampler,
+        SamplingConfig, SystemEventSet, branch_filter_from_names, event_name,
+        flags::{PerfEventFlags, SampleType},
+        measure_command, thread_ids, thread_name,
+    },
+    plot::{
+        DTLB_READ_ACCESS, DTLB_READ_MISS, L1D_READ_ACCESS, L1D_READ_MISS, colours::Colour,
+        decorate_plot, plot_data_from_buffer,
+    },
     sliding_window::SlidingBuffer,
+    symbols::{FunctionProfile, Resolver, read_maps},
     window::X11Window,
 };
 use two_dim_array::TwoDimensionalArray;
@@ -23,6 +39,819 @@ const NUM_TIME_SLICES: usize = PLOT_BUFFER_WIDTH;
 const PLOT_TIME_EXTENT: f64 = SLEEP_TIME * NUM_TIME_SLICES as f64;
 
 fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("stat") => run_stat(&args[1..]),
+        Some("profile") => run_profile(&args[1..]),
+        Some("report") => run_report(&args[1..]),
+        Some("flamegraph") => run_flamegraph(&args[1..]),
+        _ => run_ui(),
+    };
+    // Exit as a shell would for a command which cannot be executed
+    if let Err(err) = &result
+        && let Some(code) = ExecError::exit_code_of(err)
+    {
+        eprintln!("{}", err);
+        std::process::exit(code);
+    }
+    result
+}
+
+/// `cpu_perf stat [-e <events>] [--] <command> [args...]`
+/// `cpu_perf stat [-e <events>] -p <pid> [--per-thread] [--duration <seconds>]`
+/// `cpu_perf stat [-e <events>] -G <cgroup> [--duration <seconds>]`
+///
+/// Run a command and print counts over its lifetime, like `perf stat`,
+/// attach to every thread of a running process until it exits or the
+/// duration passes, or count a cgroup or container on every CPU for
+/// the duration. Events are given in `perf` syntax. As with perf, each
+/// `{...}` group is counted as one group and every other event on its
+/// own, so only the events of a group need to fit on the PMU together.
+fn run_stat(args: &[String]) -> io::Result<()> {
+    let mut event_lists = Vec::new();
+    let mut pid = None;
+    let mut cgroup = None;
+    let mut per_thread = false;
+    let mut duration = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, events, rest @ ..] if flag == "-e" => {
+                event_lists.push(events.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-p" => {
+                pid = Some(value.parse::<u32>().map_err(io::Error::other)?);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-G" => {
+                cgroup = Some(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--duration" => {
+                let seconds = value.parse::<f64>().map_err(io::Error::other)?;
+                duration = Some(Duration::try_from_secs_f64(seconds).map_err(io::Error::other)?);
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--per-thread" => {
+                per_thread = true;
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--" => {
+                args = rest;
+                break;
+            }
+            _ => break,
+        }
+    }
+    let targets = [!args.is_empty(), pid.is_some(), cgroup.is_some()];
+    if targets.iter().filter(|&&target| target).count() != 1 {
+        eprintln!("Usage: cpu_perf stat [-e <events>] [--] <command> [args...]");
+        eprintln!(
+            "       cpu_perf stat [-e <events>] -p <pid> [--per-thread] [--duration <seconds>]"
+        );
+        eprintln!("       cpu_perf stat [-e <events>] -G <cgroup> [--duration <seconds>]");
+        std::process::exit(2);
+    }
+
+    let groups: Vec<Vec<EventSpec>> = if event_lists.is_empty() {
+        [
+            EventSpec::new(EventType::TaskClock),
+            EventSpec::new(EventType::ContextSwitches),
+            EventSpec::new(EventType::CpuMigrations),
+            EventSpec::new(EventType::PageFaults),
+            EventSpec::new(EventType::CpuCycles).optional(),
+            EventSpec::new(EventType::Instructions).optional(),
+            EventSpec::new(EventType::BranchInstructions).optional(),
+            EventSpec::new(EventType::BranchMisses).optional(),
+        ]
+        .into_iter()
+        .map(|spec| vec![spec])
+        .collect()
+    } else {
+        let parser = EventParser::discover()?;
+        let mut groups = Vec::new();
+        for list in event_lists {
+            groups.extend(parser.parse_list(list).map_err(io::Error::other)?);
+        }
+        groups
+    };
+
+    if let Some(pid) = pid {
+        return stat_process(pid, &groups, per_thread, duration);
+    }
+    if let Some(cgroup) = cgroup {
+        return stat_cgroup(cgroup, &groups, duration.unwrap_or(Duration::from_secs(1)));
+    }
+
+    let stats = measure_command(args, &groups)?;
+
+    eprintln!();
+    eprintln!(" Performance counter stats for '{}':", args.join(" "));
+    eprintln!();
+    print_counts(&stats.counts, &stats.unavailable);
+    eprintln!();
+    eprintln!(
+        "{:>20.9} seconds time elapsed",
+        stats.wall_time.as_secs_f64()
+    );
+    eprintln!();
+
+    let code = match (stats.exit_status.code(), stats.exit_status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => {
+            eprintln!("Command terminated by signal {}", signal);
+            128 + signal
+        }
+        (None, None) => 1,
+    };
+    std::process::exit(code);
+}
+
+/// Count every thread of process `pid` until it exits or `duration`
+/// passes, checking for new threads as it runs.
+fn stat_process(
+    pid: u32,
+    groups: &[Vec<EventSpec>],
+    per_thread: bool,
+    duration: Option<Duration>,
+) -> io::Result<()> {
+    const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+    let mut process_set = ProcessEventSet::new(pid, groups)?;
+    let started = Instant::now();
+    process_set.update_file_state(EventIOState::Enable)?;
+    loop {
+        let remaining = duration.map(|duration| duration.saturating_sub(started.elapsed()));
+        if remaining == Some(Duration::ZERO) || unsafe { libc::kill(pid as libc::pid_t, 0) } != 0 {
+            break;
+        }
+        sleep(remaining.map_or(REFRESH_INTERVAL, |remaining| {
+            remaining.min(REFRESH_INTERVAL)
+        }));
+        // The process may exit between checks
+        if let Err(err) = process_set.refresh()
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            return Err(err);
+        }
+    }
+    process_set.update_file_state(EventIOState::Disable)?;
+    let elapsed = started.elapsed();
+    let counts = process_set.get_counts()?;
+    let unavailable = process_set.unavailable();
+
+    eprintln!();
+    eprintln!(" Performance counter stats for process id '{}':", pid);
+    eprintln!();
+    if per_thread {
+        for thread in &counts.per_thread {
+            eprintln!(
+                " Thread {} ({}){}:",
+                thread.tid,
+                thread.name,
+                if thread.exited { ", exited" } else { "" }
+            );
+            print_counts(&thread.counts, &unavailable);
+            eprintln!();
+        }
+        eprintln!(" Total over {} threads:", counts.per_thread.len());
+    }
+    print_counts(&counts.total, &unavailable);
+    eprintln!();
+    eprintln!("{:>20.9} seconds time elapsed", elapsed.as_secs_f64());
+    eprintln!();
+    eprintln!(
+        " Note: threads are found every {}ms, so those which exit sooner may be missed",
+        REFRESH_INTERVAL.as_millis()
+    );
+    eprintln!();
+    Ok(())
+}
+
+/// Count the tasks of a cgroup, given as a path, container id or name, on
+/// every CPU for `duration`, with each group opened as its own group of a
+/// [`SystemEventSet`].
+fn stat_cgroup(name: &str, groups: &[Vec<EventSpec>], duration: Duration) -> io::Result<()> {
+    let cgroup = Cgroup::resolve(name)?;
+    let event_set = match SystemEventSet::for_cgroup(groups, &cgroup) {
+        Ok(event_set) => Some(event_set),
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => None,
+        Err(err) => return Err(err),
+    };
+    let started = Instant::now();
+    if let Some(event_set) = &event_set {
+        event_set.update_file_state(EventIOState::Enable)?;
+    }
+    sleep(duration);
+    if let Some(event_set) = &event_set {
+        event_set.update_file_state(EventIOState::Disable)?;
+    }
+    let elapsed = started.elapsed();
+    let (counts, unavailable) = match &event_set {
+        Some(event_set) => (vec![event_set.get_counts()?.total], event_set.unavailable()),
+        None => (Vec::new(), groups.concat()),
+    };
+
+    eprintln!();
+    eprintln!(
+        " Performance counter stats for cgroup '{}':",
+        cgroup.path().display()
+    );
+    eprintln!();
+    print_counts(&counts, &unavailable);
+    eprintln!();
+    eprintln!("{:>20.9} seconds time elapsed", elapsed.as_secs_f64());
+    eprintln!();
+    Ok(())
+}
+
+/// Print the counts of each group in the style of `perf stat`,
+/// labelled with the events as they were given.
+fn print_counts(groups: &[EventCounts], unavailable: &[EventSpec]) {
+    for counts in groups {
+        for (event, count) in counts.iter_scaled() {
+            match counts.running_ratio_of(event.clone()) {
+                Some(ratio) if ratio < 1.0 => {
+                    eprintln!(
+                        "{:>20}      {:<30} ({:.2}%)",
+                        count,
+                        event.name,
+                        ratio * 100.0
+                    )
+                }
+                _ => eprintln!("{:>20}      {}", count, event.name),
+            }
+        }
+    }
+    for event in unavailable {
+        eprintln!("{:>20}      {}", "<not supported>", event.name);
+    }
+}
+
+/// `cpu_perf profile [-e <event>] [-F <frequency>] [--top <n>] [--folded <file>] [-j <branches>] [-o <perf.data>] [--] <command> [args...]`
+/// `cpu_perf profile [-e <event>] [-F <frequency>] [--top <n>] [--folded <file>] [-j <branches>] [-o <perf.data>] -p <pid> [--duration <seconds>]`
+///
+/// Sample a command, or the threads of a running process until it exits
+/// or the duration passes, and print the functions with the most
+/// samples. Samples `cpu-clock` by default, which works without
+/// hardware counters. With `-j` the branch stack of each sample is
+/// recorded too, and the branches mispredicted most often are printed.
+fn run_profile(args: &[String]) -> io::Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    let mut event = None;
+    let mut frequency = SamplingConfig::DEFAULT_FREQUENCY;
+    let mut top = 20;
+    let mut pid = None;
+    let mut duration = None;
+    let mut folded_path = None;
+    let mut branch_filter = None;
+    let mut output_path = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, value, rest @ ..] if flag == "-e" => {
+                event = Some(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--folded" => {
+                folded_path = Some(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-o" => {
+                output_path = Some(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-j" => {
+                branch_filter =
+                    Some(branch_filter_from_names(value).ok_or_else(|| {
+                        io::Error::other(format!("Unknown branch filter {}", value))
+                    })?);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-F" => {
+                frequency = value.parse().map_err(io::Error::other)?;
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--top" => {
+                top = value.parse().map_err(io::Error::other)?;
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "-p" => {
+                pid = Some(value.parse::<u32>().map_err(io::Error::other)?);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--duration" => {
+                let seconds = value.parse::<f64>().map_err(io::Error::other)?;
+                duration = Some(Duration::try_from_secs_f64(seconds).map_err(io::Error::other)?);
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--" => {
+                args = rest;
+                break;
+            }
+            _ => break,
+        }
+    }
+    if args.is_empty() == pid.is_none() {
+        eprintln!(
+            "Usage: cpu_perf profile [-e <event>] [-F <frequency>] [--top <n>] [--folded <file>] [-j <branches>] [-o <perf.data>] [--] <command> [args...]"
+        );
+        eprintln!(
+            "       cpu_perf profile [-e <event>] [-F <frequency>] [--top <n>] [--folded <file>] [-j <branches>] [-o <perf.data>] -p <pid> [--duration <seconds>]"
+        );
+        std::process::exit(2);
+    }
+
+    let spec = match event {
+        Some(event) => EventParser::discover()?
+            .parse_event(event)
+            .map_err(io::Error::other)?,
+        // Branch stacks need a hardware event
+        None if branch_filter.is_some() => EventSpec::new(EventType::CpuCycles),
+        None => EventSpec::new(EventType::CpuClock),
+    };
+    let mut config = SamplingConfig::new(spec.clone()).with_rate(SampleRate::Frequency(frequency));
+    let mut profile = Profile::default();
+    if folded_path.is_some() {
+        config = config.with_callchain();
+        profile.folded = Some(FoldedStacks::default());
+    }
+    if let Some(branch_filter) = branch_filter {
+        config = config.with_branch_stack(branch_filter);
+        profile.branches = Some(BranchProfile::default());
+    }
+    if output_path.is_some() {
+        // perf tells apart the events of each PMU of hybrid CPUs by id
+        let sample_type = config.sample_type | SampleType::IDENTIFIER;
+        config = config.with_sample_type(sample_type);
+    }
+
+    let mut resolver = Resolver::new();
+    let mut command = None;
+    let sampler = match pid {
+        Some(pid) => {
+            resolver.add_process(pid)?;
+            Sampler::for_threads(config, &thread_ids(pid)?).and_then(|sampler| {
+                sampler.enable()?;
+                Ok(sampler)
+            })
+        }
+        None => {
+            let flags = spec.flags | PerfEventFlags::ENABLE_ON_EXEC | PerfEventFlags::INHERIT;
+            let config = SamplingConfig {
+                spec: spec.clone().with_flags(flags),
+                ..config
+            };
+            let mut child = PausedCommand::spawn(args)?;
+            let sampler = Sampler::for_process(config, child.pid());
+            if sampler.is_ok() {
+                child.resume()?;
+            }
+            command = Some(child);
+            sampler
+        }
+    };
+    let mut sampler = match sampler {
+        Err(err) if branch_filter.is_some() && err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+            return Err(io::Error::other(
+                "Branch stacks are not supported by this CPU or event",
+            ));
+        }
+        sampler => sampler?,
+    };
+    if let Some(path) = output_path {
+        let name = match (event, branch_filter) {
+            (Some(event), _) => event,
+            (None, Some(_)) => "cycles",
+            (None, None) => "cpu-clock",
+        };
+        profile.recording = Some(start_recording(path, name, &sampler, pid)?);
+    }
+
+    let started = Instant::now();
+    loop {
+        sleep(POLL_INTERVAL);
+        profile.add_records(&mut sampler, &mut resolver)?;
+        let done = match (&mut command, pid) {
+            (Some(command), _) => command.try_wait()?.is_some(),
+            (None, Some(pid)) => {
+                duration.is_some_and(|duration| started.elapsed() >= duration)
+                    || unsafe { libc::kill(pid as libc::pid_t, 0) } != 0
+            }
+            (None, None) => true,
+        };
+        if done {
+            break;
+        }
+    }
+    sampler.disable()?;
+    profile.add_records(&mut sampler, &mut resolver)?;
+    if let (Some(path), Some(recording)) = (output_path, profile.recording.take()) {
+        recording.finish(&Features::current()?)?;
+        eprintln!("Wrote samples to {}", path);
+    }
+
+    if let (Some(path), Some(folded)) = (folded_path, &profile.folded) {
+        if path == "-" {
+            // The stacks are the output, so leave out the table
+            return folded.write(io::stdout().lock());
+        }
+        write_folded(folded, path)?;
+    }
+    print_profile(&profile, &mut resolver, &spec.name, top);
+    Ok(())
+}
+
+/// `cpu_perf report [-i <perf.data>] [--top <n>] [--folded <file>] [--plot]`
+///
+/// Read a `perf.data` file, from `cpu_perf profile -o` or `perf record`,
+/// and print the functions with the most samples as `profile` does.
+/// With `--folded` the callchains of the samples are written as folded
+/// stacks, and with `--plot` the counts of the events over the
+/// recording are plotted in an X11 window, as for live counts.
+fn run_report(args: &[String]) -> io::Result<()> {
+    let mut input = "perf.data";
+    let mut top = 20;
+    let mut folded_path = None;
+    let mut plot = false;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, value, rest @ ..] if flag == "-i" => {
+                input = value.as_str();
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--top" => {
+                top = value.parse().map_err(io::Error::other)?;
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--folded" => {
+                folded_path = Some(value.as_str());
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--plot" => {
+                plot = true;
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    if !args.is_empty() {
+        eprintln!("Usage: cpu_perf report [-i <perf.data>] [--top <n>] [--folded <file>] [--plot]");
+        std::process::exit(2);
+    }
+
+    let perf_data = PerfData::open(input)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", input, err)))?;
+    let recorded = |field| {
+        perf_data
+            .events()
+            .iter()
+            .any(|event| SampleType::from_bits(event.attr.sample_type).contains(field))
+    };
+    let mut profile = Profile::default();
+    if folded_path.is_some() {
+        if !recorded(SampleType::CALLCHAIN) {
+            return Err(io::Error::other(format!(
+                "{} has no callchains, record with -g",
+                input
+            )));
+        }
+        profile.folded = Some(FoldedStacks::default());
+    }
+    if recorded(SampleType::BRANCH_STACK) {
+        profile.branches = Some(BranchProfile::default());
+    }
+
+    let mut records = Vec::new();
+    let mut undecoded = 0;
+    for record in perf_data.records() {
+        match record {
+            Ok(record) => records.push(record),
+            Err(_) => undecoded += 1,
+        }
+    }
+    if undecoded > 0 {
+        eprintln!("Skipped {} records which could not be decoded", undecoded);
+    }
+    let mut resolver = Resolver::for_recording();
+    profile.add(records, &mut resolver);
+
+    if let (Some(path), Some(folded)) = (folded_path, &profile.folded) {
+        if path == "-" {
+            return folded.write(io::stdout().lock());
+        }
+        write_folded(folded, path)?;
+    }
+
+    let features = perf_data.features();
+    if let Some(hostname) = &features.hostname {
+        println!("# Captured on: {}", hostname);
+    }
+    if let Some(os_release) = &features.os_release {
+        println!("# Kernel: {}", os_release);
+    }
+    if let Some(cmdline) = &features.cmdline {
+        println!("# Command: {}", cmdline.join(" "));
+    }
+    let mut names: Vec<String> = perf_data
+        .events()
+        .iter()
+        .map(|event| match event.name.as_str() {
+            "" => event_name(EventType::from_attr(&event.attr)),
+            name => name.to_string(),
+        })
+        .collect();
+    // The PMUs of hybrid CPUs each have an event of the same name
+    names.dedup();
+    print_profile(&profile, &mut resolver, &names.join(", "), top);
+
+    if plot {
+        let counts = perf_data.counts_over_time(NUM_TIME_SLICES);
+        show_plot(
+            &counts.slices,
+            counts.slice.as_secs_f64() * NUM_TIME_SLICES as f64,
+        )?;
+    }
+    Ok(())
+}
+
+/// Write folded stacks to the file at `path`.
+fn write_folded(folded: &FoldedStacks, path: &str) -> io::Result<()> {
+    folded.write(io::BufWriter::new(std::fs::File::create(path)?))?;
+    eprintln!("Wrote {} stacks to {}", folded.len(), path);
+    Ok(())
+}
+
+/// Print the functions with the most samples, in the style of
+/// `perf report`, then any branches mispredicted most often.
+fn print_profile(profile: &Profile, resolver: &mut Resolver, event: &str, top: usize) {
+    let functions = &profile.functions;
+    println!("# Samples: {} of event '{}'", functions.total(), event);
+    if profile.lost > 0 {
+        println!("# Lost: {} samples", profile.lost);
+    }
+    println!("#");
+    println!(
+        "# {:>8}  {:>8}  {:<40}  Object",
+        "Overhead", "Samples", "Symbol"
+    );
+    println!("#");
+    for function in functions.hottest(top) {
+        println!(
+            "  {:>7.2}%  {:>8}  {:<40}  {}",
+            function.percent(functions.total()),
+            function.weight,
+            function.location.symbol_or_unknown(),
+            function.location.object.as_deref().unwrap_or("[unknown]")
+        );
+    }
+    if let Some(branches) = &profile.branches {
+        print_branches(branches, resolver, top);
+    }
+}
+
+/// Everything gathered from the samples of `cpu_perf profile` or
+/// `cpu_perf report`.
+#[derive(Default)]
+struct Profile {
+    functions: FunctionProfile,
+    /// Stacks weighted by sample period, when recording callchains.
+    folded: Option<FoldedStacks>,
+    /// When recording branch stacks.
+    branches: Option<BranchProfile>,
+    /// When writing the records to a `perf.data` file.
+    recording: Option<PerfDataWriter<io::BufWriter<std::fs::File>>>,
+    lost: u64,
+}
+
+impl Profile {
+    /// Read new records from `sampler` and add their samples, writing
+    /// them out first when recording.
+    fn add_records(&mut self, sampler: &mut Sampler, resolver: &mut Resolver) -> io::Result<()> {
+        let format = *sampler.format();
+        let mut records = Vec::new();
+        let mut result = Ok(());
+        sampler.for_each_raw_record(|_, record| {
+            if result.is_ok()
+                && let Some(recording) = &mut self.recording
+            {
+                result = recording.write_record(record);
+            }
+            match format.parse(record) {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    if result.is_ok() {
+                        result = Err(io::Error::other(err));
+                    }
+                }
+            }
+        });
+        result?;
+        self.add(records, resolver);
+        Ok(())
+    }
+
+    /// Add the samples of `records`, after the mappings of all of them,
+    /// as samples in one buffer may need mappings from another.
+    fn add(&mut self, records: Vec<Record>, resolver: &mut Resolver) {
+        for record in &records {
+            resolver.handle_record(record);
+        }
+        for record in records {
+            match record {
+                Record::Sample(sample) => {
+                    let Some(pid) = sample.pid else {
+                        continue;
+                    };
+                    if let Some(ip) = sample.ip {
+                        self.functions
+                            .add(resolver.resolve(pid, ip, sample.cpu_mode()), 1);
+                    }
+                    if let Some(folded) = &mut self.folded {
+                        let stack = sample_stack(resolver, pid, &sample);
+                        folded.add(stack.iter().rev(), sample.period.unwrap_or(1));
+                    }
+                    if let Some(branches) = &mut self.branches {
+                        branches.add(pid, &sample.branch_stack);
+                    }
+                }
+                Record::Lost(record) => self.lost += record.lost,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Start a `perf.data` file at `path` for the events of `sampler`. When
+/// attaching to a running process, its threads and mappings are written
+/// first, as no records of them will come from the kernel.
+fn start_recording(
+    path: &str,
+    name: &str,
+    sampler: &Sampler,
+    pid: Option<u32>,
+) -> io::Result<PerfDataWriter<io::BufWriter<std::fs::File>>> {
+    let events: Vec<PerfDataEvent> = sampler
+        .attrs()?
+        .into_iter()
+        .map(|(attr, ids)| PerfDataEvent {
+            attr,
+            ids,
+            name: name.to_string(),
+        })
+        .collect();
+    // Synthesized records are attributed to the first event, as by perf
+    let id = events
+        .first()
+        .and_then(|event| event.ids.first().copied())
+        .unwrap_or(0);
+    let mut recording = PerfDataWriter::create(path, events)?;
+    if let Some(pid) = pid {
+        let format = sampler.format();
+        for tid in thread_ids(pid)? {
+            if let Ok(name) = thread_name(pid, tid) {
+                recording.write_record(&comm_record(format, id, pid, tid, &name))?;
+            }
+        }
+        for mapping in read_maps(pid)? {
+            recording.write_record(&mmap2_record(format, id, pid, &mapping))?;
+        }
+    }
+    Ok(recording)
+}
+
+/// Print the branches mispredicted most often.
+fn print_branches(branches: &BranchProfile, resolver: &mut Resolver, top: usize) {
+    let percent = match branches.taken() {
+        0 => 0.0,
+        taken => branches.mispredicted() as f64 * 100.0 / taken as f64,
+    };
+    println!();
+    println!(
+        "# Branches: {}, mispredicted: {} ({:.2}%)",
+        branches.taken(),
+        branches.mispredicted(),
+        percent
+    );
+    println!("#");
+    println!(
+        "# {:>11}  {:>8}  {:>7}  {:>6}  {:<40}  To",
+        "Mispredicts", "Taken", "Rate", "Cycles", "From"
+    );
+    println!("#");
+    for branch in branches.hottest_mispredicted(top) {
+        let mut describe = |addr| {
+            let location = resolver.resolve(branch.pid, addr, CpuMode::Unknown);
+            format!("{} ({:#x})", location.symbol_or_unknown(), addr)
+        };
+        let (from, to) = (describe(branch.from), describe(branch.to));
+        let cycles = branch
+            .average_cycles()
+            .map_or("-".to_string(), |cycles| format!("{:.1}", cycles));
+        println!(
+            "  {:>11}  {:>8}  {:>6.2}%  {:>6}  {:<40}  {}",
+            branch.mispredicted,
+            branch.taken,
+            branch.mispredict_percent(),
+            cycles,
+            from,
+            to
+        );
+    }
+}
+
+/// The function names of the callchain of `sample`, leaf first and
+/// ending with the process name. Kernel functions get a `_[k]` suffix,
+/// which `flamegraph.pl` colours differently.
+fn sample_stack(resolver: &mut Resolver, pid: u32, sample: &Sample) -> Vec<String> {
+    let mut stack: Vec<String> = sample
+        .frames()
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            // Callers are return addresses, which may be the first
+            // instruction of the next function
+            let addr = if i == 0 {
+                frame.ip
+            } else {
+                frame.ip.saturating_sub(1)
+            };
+            let location = resolver.resolve(pid, addr, frame.mode);
+            match frame.mode {
+                CpuMode::Kernel => format!("{}_[k]", location.symbol_or_unknown()),
+                _ => location.symbol_or_unknown().to_string(),
+            }
+        })
+        .collect();
+    stack.push(resolver.process_name(pid).to_string());
+    stack
+}
+
+/// `cpu_perf flamegraph [--title <title>] [--width <pixels>] [--count-name <name>] [<folded>]`
+/// `cpu_perf flamegraph [--title <title>] [--width <pixels>] [--count-name <name>] --diff <before> <after>`
+///
+/// Render folded stacks, read from a file or stdin, as an interactive
+/// SVG flamegraph on stdout, or render how the stacks of one profile
+/// changed from another.
+fn run_flamegraph(args: &[String]) -> io::Result<()> {
+    let mut flamegraph = FlameGraph::new();
+    let mut diff = false;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, value, rest @ ..] if flag == "--title" => {
+                flamegraph = flamegraph.with_title(value.as_str());
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--width" => {
+                flamegraph = flamegraph.with_width(value.parse().map_err(io::Error::other)?);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--count-name" => {
+                flamegraph = flamegraph.with_count_name(value.as_str());
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--diff" => {
+                diff = true;
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let svg = match (diff, args) {
+        (false, []) => flamegraph.render(&read_folded("-")?),
+        (false, [path]) => flamegraph.render(&read_folded(path)?),
+        (true, [before, after]) => {
+            flamegraph.render_diff(&read_folded(before)?, &read_folded(after)?)
+        }
+        _ => {
+            eprintln!(
+                "Usage: cpu_perf flamegraph [--title <title>] [--width <pixels>] [--count-name <name>] [<folded>]"
+            );
+            eprintln!(
+                "       cpu_perf flamegraph [--title <title>] [--width <pixels>] [--count-name <name>] --diff <before> <after>"
+            );
+            std::process::exit(2);
+        }
+    };
+    io::stdout().lock().write_all(svg.as_bytes())
+}
+
+/// Read folded stacks from `path`, or stdin for `-`.
+fn read_folded(path: &str) -> io::Result<FoldedStacks> {
+    let folded = match path {
+        "-" => io::read_to_string(io::stdin())?,
+        path => std::fs::read_to_string(path)?,
+    };
+    FoldedStacks::parse(&folded)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err)))
+}
+
+/// Plot live counts for the whole machine in an X11 window.
+fn run_ui() -> io::Result<()> {
     let print_out = false;
     println!("WIDTH = {}, HEIGHT = {}", WIDTH, HEIGHT);
     println!(
@@ -53,21 +882,59 @@ fn main() -> io::Result<()> {
     x11_window.show();
     x11_window.wait_map_notify();
 
-    let cpu_id = 6;
-
-    let mut event_set = EventSet::new(Some(cpu_id), None)?;
+    // Each ratio is counted in its own group, so both sides of it are
+    // counted at the same time while the groups only need to fit the
+    // PMU one at a time
+    let mut event_set = SystemEventSet::new(&[
+        vec![EventSpec::new(EventType::CpuCycles)],
+        vec![
+            EventSpec::new(EventType::CacheReferences),
+            EventSpec::new(EventType::CacheMisses),
+        ],
+        vec![
+            EventSpec::new(EventType::BranchInstructions),
+            EventSpec::new(EventType::BranchMisses),
+        ],
+        vec![
+            EventSpec::new(EventType::HwCache(L1D_READ_ACCESS)).optional(),
+            EventSpec::new(EventType::HwCache(L1D_READ_MISS)).optional(),
+        ],
+        vec![
+            EventSpec::new(EventType::HwCache(DTLB_READ_ACCESS)).optional(),
+            EventSpec::new(EventType::HwCache(DTLB_READ_MISS)).optional(),
+        ],
+        vec![
+            EventSpec::new(EventType::StalledCyclesFrontend).optional(),
+            EventSpec::new(EventType::StalledCyclesBackend).optional(),
+        ],
+    ])?;
+    for event in event_set.unavailable() {
+        println!("{} is not supported on this CPU", event.name);
+    }
     event_set.enable(&[
         EventType::CacheReferences,
         EventType::CacheMisses,
         EventType::BranchInstructions,
         EventType::BranchMisses,
-    ]);
+        EventType::HwCache(L1D_READ_ACCESS),
+        EventType::HwCache(L1D_READ_MISS),
+        EventType::HwCache(DTLB_READ_ACCESS),
+        EventType::HwCache(DTLB_READ_MISS),
+    ])?;
 
     if print_out {
-        println!("CPU performance for cpu = {}", cpu_id);
         println!(
-            "{:^8} {:^20} {:^20} {:^20} {:^20}",
-            "t", "cache accesses", "cache misses", "branch instructions", "branch misses"
+            "CPU performance for cpus = {:?}",
+            event_set.cpus().collect::<Vec<_>>()
+        );
+        println!(
+            "{:^8} {:^20} {:^20} {:^20} {:^20} {:^10}",
+            "t",
+            "cache accesses",
+            "cache misses",
+            "branch instructions",
+            "branch misses",
+            "running"
         );
     }
 
@@ -94,7 +961,7 @@ fn main() -> io::Result<()> {
     #[allow(unused_assignments)]
     let mut started_harvesting = SystemTime::now();
     loop {
-        let counts = event_set.get_counts()?;
+        let counts = event_set.get_counts()?.total;
         event_set.update_file_state(EventIOState::Enable)?;
         started_harvesting = SystemTime::now();
 
@@ -102,12 +969,15 @@ fn main() -> io::Result<()> {
 
         if print_out {
             println!(
-                "{:^8} {:^20} {:^20} {:^20} {:^20}",
+                "{:^8} {:^20} {:^20} {:^20} {:^20} {:^9.2}%",
                 t,
-                counts.num_cache_references,
-                counts.num_cache_misses,
-                counts.num_branch_instructions,
-                counts.num_branch_misses
+                counts.get_scaled(EventType::CacheReferences).unwrap_or(0),
+                counts.get_scaled(EventType::CacheMisses).unwrap_or(0),
+                counts
+                    .get_scaled(EventType::BranchInstructions)
+                    .unwrap_or(0),
+                counts.get_scaled(EventType::BranchMisses).unwrap_or(0),
+                counts.running_ratio() * 100.0
             );
         }
 
@@ -122,14 +992,7 @@ fn main() -> io::Result<()> {
             0xff00ff00,
         );
 
-        // Copy plot data into main buffer
-        for (window_row, plot_row) in two_dim_window_buffer
-            .rows_mut()
-            .skip(PLOT_Y)
-            .zip(two_dim_plot_buffer.rows())
-        {
-            window_row[PLOT_X..PLOT_X + PLOT_BUFFER_WIDTH].copy_from_slice(plot_row);
-        }
+        copy_plot(&mut two_dim_window_buffer, &two_dim_plot_buffer);
 
         t += 1;
         x11_window.show();
@@ -143,3 +1006,56 @@ fn main() -> io::Result<()> {
         event_set.update_file_state(EventIOState::Disable)?;
     }
 }
+
+/// Plot counts read from a recording in an X11 window, showing it until
+/// the program is stopped.
+fn show_plot(counts: &[EventCounts], time_extent: f64) -> io::Result<()> {
+    let mut window_buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    let mut plot_buffer: Vec<u32> = vec![0; PLOT_BUFFER_WIDTH * PLOT_BUFFER_HEIGHT];
+
+    let x11_window = X11Window::new(0, 0, WIDTH as u32, HEIGHT as u32, 1, 1, 0, &window_buffer)
+        .ok_or_else(|| io::Error::other("Failed to open window"))?;
+    x11_window.set_title("report").map_err(io::Error::other)?;
+    x11_window.show();
+    x11_window.wait_map_notify();
+
+    let mut two_dim_window_buffer = TwoDimensionalArray::new(&mut window_buffer, HEIGHT, WIDTH)
+        .expect("Failed to init buffer as 2D");
+    let mut two_dim_plot_buffer =
+        TwoDimensionalArray::new(&mut plot_buffer, PLOT_BUFFER_HEIGHT, PLOT_BUFFER_WIDTH)
+            .expect("Failed to init buffer as 2D");
+    decorate_plot(
+        &mut two_dim_window_buffer,
+        PLOT_X,
+        PLOT_Y,
+        PLOT_BUFFER_WIDTH,
+        PLOT_BUFFER_HEIGHT,
+        time_extent,
+        Colour::WHITE,
+        Colour::GREY,
+    );
+    plot_data_from_buffer(
+        counts,
+        NUM_TIME_SLICES,
+        &mut two_dim_plot_buffer,
+        2,
+        PLOT_BUFFER_WIDTH,
+        PLOT_BUFFER_HEIGHT,
+        0xff00ff00,
+    );
+    copy_plot(&mut two_dim_window_buffer, &two_dim_plot_buffer);
+
+    // Redrawn in case the window was covered
+    loop {
+        x11_window.update_window();
+        x11_window.show();
+        sleep(Duration::from_secs_f64(SLEEP_TIME));
+    }
+}
+
+/// Copy the plot into its place in the window.
+fn copy_plot(window: &mut TwoDimensionalArray<u32>, plot: &TwoDimensionalArray<u32>) {
+    for (window_row, plot_row) in window.rows_mut().skip(PLOT_Y).zip(plot.rows()) {
+        window_row[PLOT_X..PLOT_X + PLOT_BUFFER_WIDTH].copy_from_slice(plot_row);
+    }
+}
@@ -0,0 +1,165 @@
+mod reader;
+mod writer;
+
+pub use reader::*;
+pub use writer::*;
+
+use std::io;
+
+use crate::perf_events::PerfEventAttr;
+
+/// `PERFILE2` read as a native endian `u64`, starting every `perf.data`
+/// file.
+pub const PERF_MAGIC: u64 = u64::from_le_bytes(*b"PERFILE2");
+
+/// Size of [`FileHeader`] in the file.
+pub const FILE_HEADER_SIZE: usize = 104;
+/// Size of a [`FileSection`] in the file.
+pub const FILE_SECTION_SIZE: usize = 16;
+/// Size of [`PerfEventAttr`] as written by this crate.
+pub const ATTR_SIZE: usize = size_of::<PerfEventAttr>();
+
+// Feature bits, numbered as in perf's util/header.h
+pub const HEADER_TRACING_DATA: u32 = 1;
+pub const HEADER_BUILD_ID: u32 = 2;
+pub const HEADER_HOSTNAME: u32 = 3;
+pub const HEADER_OSRELEASE: u32 = 4;
+pub const HEADER_VERSION: u32 = 5;
+pub const HEADER_ARCH: u32 = 6;
+pub const HEADER_NRCPUS: u32 = 7;
+pub const HEADER_CPUDESC: u32 = 8;
+pub const HEADER_CPUID: u32 = 9;
+pub const HEADER_TOTAL_MEM: u32 = 10;
+pub const HEADER_CMDLINE: u32 = 11;
+pub const HEADER_EVENT_DESC: u32 = 12;
+pub const HEADER_COMPRESSED: u32 = 27;
+/// One past the highest feature bit a file can have.
+pub const HEADER_FEAT_BITS: u32 = 256;
+
+/// Record types from this up are written by perf itself rather than
+/// the kernel, and never end with a sample id.
+pub const PERF_RECORD_USER_TYPE_START: u32 = 64;
+/// Marks that every record before it is older than every record after,
+/// written by `perf record` after each pass over the ring buffers.
+pub const PERF_RECORD_FINISHED_ROUND: u32 = 68;
+/// Which event, CPU and thread each id was opened on.
+pub const PERF_RECORD_ID_INDEX: u32 = 69;
+/// AUX area data, such as Intel PT traces, which follows the record
+/// rather than being part of it.
+pub const PERF_RECORD_AUXTRACE: u32 = 71;
+/// Records compressed with zstd by `perf record -z`, which this crate
+/// does not decompress.
+pub const PERF_RECORD_COMPRESSED: u32 = 81;
+
+/// Strings in feature sections are padded to a multiple of this,
+/// including the terminating nul.
+const NAME_ALIGN: usize = 64;
+
+/// Where something is in the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileSection {
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// The header at the start of a `perf.data` file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileHeader {
+    /// Size of each entry in the attrs section, an attr followed by the
+    /// [`FileSection`] of its ids.
+    pub attr_size: u64,
+    pub attrs: FileSection,
+    pub data: FileSection,
+    /// Unused since perf 3.x, always empty.
+    pub event_types: FileSection,
+    /// Bitmap of the `HEADER_*` feature sections following the data.
+    pub features: [u64; 4],
+}
+
+impl FileHeader {
+    pub fn has_feature(&self, feature: u32) -> bool {
+        feature < HEADER_FEAT_BITS
+            && self.features[feature as usize / 64] & (1 << (feature % 64)) != 0
+    }
+
+    pub fn set_feature(&mut self, feature: u32) {
+        self.features[feature as usize / 64] |= 1 << (feature % 64);
+    }
+
+    /// The set feature bits, lowest first, the order their sections
+    /// appear in.
+    pub fn feature_bits(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..HEADER_FEAT_BITS).filter(|&feature| self.has_feature(feature))
+    }
+}
+
+/// An event recorded in a `perf.data` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PerfDataEvent {
+    pub attr: PerfEventAttr,
+    /// The kernel assigned ids of the events opened with `attr`, which
+    /// samples carry with [`crate::perf_events::flags::SampleType::IDENTIFIER`]
+    /// or `ID` to tell events apart.
+    pub ids: Vec<u64>,
+    /// The event name shown by `perf report`, such as `cycles`.
+    pub name: String,
+}
+
+/// The number of CPUs of the recording machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NrCpus {
+    pub available: u32,
+    pub online: u32,
+}
+
+/// Descriptions of the recording machine and session, from the
+/// feature sections of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Features {
+    pub hostname: Option<String>,
+    pub os_release: Option<String>,
+    /// The version of the recording tool.
+    pub version: Option<String>,
+    pub arch: Option<String>,
+    pub nr_cpus: Option<NrCpus>,
+    /// The command line of the recording tool.
+    pub cmdline: Option<Vec<String>>,
+}
+
+impl Features {
+    /// Describe this machine and the running process.
+    pub fn current() -> io::Result<Self> {
+        let mut uname: libc::utsname = unsafe { std::mem::zeroed() };
+        if unsafe { libc::uname(&mut uname) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let field = |chars: &[libc::c_char]| {
+            let bytes: Vec<u8> = chars
+                .iter()
+                .take_while(|&&c| c != 0)
+                .map(|&c| c as u8)
+                .collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        let available = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+        let online = crate::perf_events::online_cpus()?.len() as u32;
+        Ok(Self {
+            hostname: Some(field(&uname.nodename)),
+            os_release: Some(field(&uname.release)),
+            version: Some(format!("cpu_perf {}", env!("CARGO_PKG_VERSION"))),
+            arch: Some(field(&uname.machine)),
+            nr_cpus: Some(NrCpus {
+                available: (available as u32).max(online),
+                online,
+            }),
+            cmdline: Some(std::env::args().collect()),
+        })
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed perf.data: {}", reason),
+    )
+}
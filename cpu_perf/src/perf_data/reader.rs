@@ -0,0 +1,546 @@
+use std::{collections::HashMap, fs, io, path::Path, time::Duration};
+
+use super::{
+    ATTR_SIZE, FILE_HEADER_SIZE, FILE_SECTION_SIZE, Features, FileHeader, FileSection, HEADER_ARCH,
+    HEADER_CMDLINE, HEADER_COMPRESSED, HEADER_EVENT_DESC, HEADER_HOSTNAME, HEADER_NRCPUS,
+    HEADER_OSRELEASE, HEADER_VERSION, NrCpus, PERF_MAGIC, PERF_RECORD_AUXTRACE,
+    PERF_RECORD_ID_INDEX, PERF_RECORD_USER_TYPE_START, PerfDataEvent, malformed,
+};
+use crate::perf_events::{
+    EventCounts, EventSpec, EventType, PERF_RECORD_SAMPLE, PerfEventAttr, RECORD_HEADER_SIZE,
+    RawRecords, Record, RecordError, RecordFormat, RecordHeader, Sample,
+    flags::{PerfEventFlags, SampleType},
+};
+
+/// A `perf.data` file, as written by [`super::PerfDataWriter`] or
+/// `perf record`.
+///
+/// Records decode to the same [`Record`]s as those read live from a
+/// [`crate::perf_events::Sampler`]. Records of types this crate does not
+/// decode, including those perf adds itself such as
+/// [`super::PERF_RECORD_FINISHED_ROUND`], are [`Record::Unknown`], and
+/// feature sections which are unknown or cannot be read are left out.
+///
+/// Ids listed by a [`PERF_RECORD_ID_INDEX`] are matched to their events
+/// as are those of the attrs section, and the trace data following a
+/// [`PERF_RECORD_AUXTRACE`] is stepped over. Files compressed with
+/// `perf record -z` are not supported.
+///
+/// # Example
+///
+/// Reading the samples of two events with different formats:
+///
+/// ```
+/// use std::io::Cursor;
+/// use cpu_perf::{
+///     perf_data::{Features, PERF_RECORD_FINISHED_ROUND, PerfData, PerfDataEvent, PerfDataWriter},
+///     perf_events::{
+///         EventType, PERF_RECORD_SAMPLE, PerfEventAttr, Record, event_name, flags::SampleType,
+///     },
+/// };
+///
+/// fn record(type_: u32, body: &[u8]) -> Vec<u8> {
+///     let mut record = type_.to_ne_bytes().to_vec();
+///     record.extend(2u16.to_ne_bytes());
+///     record.extend((8 + body.len() as u16).to_ne_bytes());
+///     record.extend(body);
+///     record
+/// }
+/// fn sample(id: u64, time: u64, period: u64, callchain: Option<&[u64]>) -> Vec<u8> {
+///     let mut body = Vec::new();
+///     for field in [id, 0x4010, time, period] {
+///         body.extend(field.to_ne_bytes());
+///     }
+///     if let Some(callchain) = callchain {
+///         body.extend((callchain.len() as u64).to_ne_bytes());
+///         callchain.iter().for_each(|ip| body.extend(ip.to_ne_bytes()));
+///     }
+///     record(PERF_RECORD_SAMPLE, &body)
+/// }
+///
+/// let sample_type = SampleType::IDENTIFIER | SampleType::IP | SampleType::TIME | SampleType::PERIOD;
+/// let event = |event, sample_type, id| PerfDataEvent {
+///     attr: PerfEventAttr::new(event).with_sample_type(sample_type),
+///     ids: vec![id],
+///     name: event_name(event),
+/// };
+/// let events = vec![
+///     event(EventType::BranchInstructions, sample_type, 1),
+///     event(EventType::BranchMisses, sample_type | SampleType::CALLCHAIN, 2),
+/// ];
+/// let mut writer = PerfDataWriter::new(Cursor::new(Vec::new()), events).unwrap();
+/// writer.write_record(&sample(1, 1_000, 400, None)).unwrap();
+/// writer.write_record(&sample(2, 2_000, 30, Some(&[0x4010, 0x5000]))).unwrap();
+/// writer.write_record(&record(PERF_RECORD_FINISHED_ROUND, &[])).unwrap();
+/// writer.write_record(&sample(1, 9_000, 600, None)).unwrap();
+/// let file = writer.finish(&Features::default()).unwrap().into_inner();
+///
+/// let perf_data = PerfData::parse(file).unwrap();
+/// let records: Vec<Record> = perf_data.records().map(Result::unwrap).collect();
+/// let Record::Sample(sample) = &records[1] else { panic!() };
+/// assert_eq!(sample.callchain, [0x4010, 0x5000]);
+/// assert_eq!(perf_data.sample_event(sample).unwrap().name, "branch-misses");
+/// assert!(matches!(records[2], Record::Unknown { .. }));
+///
+/// let counts = perf_data.counts_over_time(2);
+/// assert_eq!(counts.slices[0].get(EventType::BranchInstructions), Some(400));
+/// assert_eq!(counts.slices[0].get(EventType::BranchMisses), Some(30));
+/// assert_eq!(counts.slices[1].get(EventType::BranchInstructions), Some(600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PerfData {
+    bytes: Vec<u8>,
+    header: FileHeader,
+    events: Vec<PerfDataEvent>,
+    features: Features,
+    /// The record format of each event.
+    formats: Vec<RecordFormat>,
+    /// Whether every event has the same format, so the event of a
+    /// record need not be found to decode it.
+    same_format: bool,
+    /// The index in `events` of each id.
+    ids: HashMap<u64, usize>,
+}
+
+/// Counts of each event over successive slices of a recording, summed
+/// from the periods of its samples.
+#[derive(Debug, Clone, Default)]
+pub struct CountsOverTime {
+    /// Time of the first sample, in nanoseconds of the clock of the
+    /// recording.
+    pub start: u64,
+    pub slice: Duration,
+    pub slices: Vec<EventCounts>,
+}
+
+impl PerfData {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(fs::read(path)?)
+    }
+
+    pub fn parse(bytes: Vec<u8>) -> io::Result<Self> {
+        let header = parse_header(&bytes)?;
+        if header.has_feature(HEADER_COMPRESSED) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Compressed perf.data files are not supported, \
+                 decompress with perf inject -i <file> -o <output>",
+            ));
+        }
+        let mut events = Vec::new();
+        let attr_size = header.attr_size as usize;
+        if attr_size < FILE_SECTION_SIZE {
+            return Err(malformed("attr size too small"));
+        }
+        let attrs = section(&bytes, header.attrs)?;
+        for entry in attrs.chunks_exact(attr_size) {
+            let (attr, ids) = entry.split_at(attr_size - FILE_SECTION_SIZE);
+            let ids = section(&bytes, parse_section(ids)?)?;
+            events.push(PerfDataEvent {
+                attr: parse_attr(attr),
+                ids: ids.chunks_exact(8).map(|id| u64_at(id, 0)).collect(),
+                name: String::new(),
+            });
+        }
+        if events.is_empty() {
+            return Err(malformed("no events"));
+        }
+        let formats: Vec<RecordFormat> = events
+            .iter()
+            .map(|event| RecordFormat::from_attr(&event.attr))
+            .collect();
+        let same_format = formats.iter().all(|format| *format == formats[0]);
+        let mut ids: HashMap<u64, usize> = events
+            .iter()
+            .enumerate()
+            .flat_map(|(i, event)| event.ids.iter().map(move |&id| (id, i)))
+            .collect();
+        let data = section(&bytes, header.data).unwrap_or_default();
+        for (id, i) in id_index(data) {
+            if i < events.len() {
+                ids.entry(id).or_insert(i);
+            }
+        }
+        let mut perf_data = Self {
+            bytes,
+            header,
+            events,
+            features: Features::default(),
+            formats,
+            same_format,
+            ids,
+        };
+        perf_data.parse_features();
+        Ok(perf_data)
+    }
+
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// The recorded events, named from the event description feature
+    /// when present.
+    pub fn events(&self) -> &[PerfDataEvent] {
+        &self.events
+    }
+
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    /// The format of the records of the first event.
+    pub fn record_format(&self) -> &RecordFormat {
+        &self.formats[0]
+    }
+
+    /// The event with kernel assigned id `id`.
+    pub fn event_for_id(&self, id: u64) -> Option<&PerfDataEvent> {
+        self.ids.get(&id).map(|&i| &self.events[i])
+    }
+
+    /// The event which made `sample`, found by its id, or the only
+    /// event when there is one.
+    pub fn sample_event(&self, sample: &Sample) -> Option<&PerfDataEvent> {
+        match (sample.identifier.or(sample.id), &self.events[..]) {
+            (Some(id), _) => self.event_for_id(id),
+            (None, [event]) => Some(event),
+            (None, _) => None,
+        }
+    }
+
+    /// The raw records of the data section.
+    pub fn data(&self) -> &[u8] {
+        section(&self.bytes, self.header.data).unwrap_or_default()
+    }
+
+    /// Decode the records of the data section, each in the format of
+    /// the event which made it.
+    pub fn records(&self) -> PerfDataRecords<'_> {
+        PerfDataRecords {
+            perf_data: self,
+            raw: RawRecords::new(self.data()),
+        }
+    }
+
+    /// Sum the periods of the samples of each event over `slices` equal
+    /// slices of the time from the first sample to the last, as a
+    /// counting event would have counted them. Samples without a time
+    /// are left out.
+    pub fn counts_over_time(&self, slices: usize) -> CountsOverTime {
+        let mut samples = Vec::new();
+        for record in self.records() {
+            let Ok(Record::Sample(sample)) = record else {
+                continue;
+            };
+            let (Some(time), Some(event)) = (sample.time, self.sample_event(&sample)) else {
+                continue;
+            };
+            // Events sampled by period only record it when asked to
+            let fixed_period = match event.attr.flags & PerfEventFlags::FREQ.bits() {
+                0 => event.attr.sample_period_or_freq,
+                _ => 1,
+            };
+            let period = sample.period.unwrap_or(fixed_period);
+            samples.push((time, EventType::from_attr(&event.attr), period));
+        }
+        let times = samples.iter().map(|&(time, _, _)| time);
+        let (Some(start), Some(end)) = (times.clone().min(), times.max()) else {
+            return CountsOverTime::default();
+        };
+
+        let nr_slices = slices.max(1);
+        // Rounded up past the end, so the last sample is in the last slice
+        let slice = (end - start) / nr_slices as u64 + 1;
+        let mut counts: Vec<Vec<(EventType, u64)>> = vec![Vec::new(); nr_slices];
+        for (time, event, period) in samples {
+            let counts = &mut counts[((time - start) / slice) as usize];
+            match counts.iter_mut().find(|(e, _)| *e == event) {
+                Some((_, count)) => *count += period,
+                None => counts.push((event, period)),
+            }
+        }
+        CountsOverTime {
+            start,
+            slice: Duration::from_nanos(slice),
+            slices: counts
+                .into_iter()
+                .map(|counts| {
+                    let counts = counts
+                        .into_iter()
+                        .map(|(event, count)| (EventSpec::new(event), count))
+                        .collect();
+                    EventCounts::new(counts, slice, slice)
+                })
+                .collect(),
+        }
+    }
+
+    /// The format of `record`, that of the event whose id it carries.
+    fn format_of(&self, record: &[u8]) -> &RecordFormat {
+        if self.same_format {
+            return &self.formats[0];
+        }
+        self.record_id(record)
+            .and_then(|id| self.ids.get(&id))
+            .map_or(&self.formats[0], |&i| &self.formats[i])
+    }
+
+    /// The id carried by `record`. As perf only records events whose ids
+    /// are in the same place in every format, the place is found from
+    /// the format of the first event.
+    fn record_id(&self, record: &[u8]) -> Option<u64> {
+        let format = &self.formats[0];
+        let has = |field| format.sample_type.contains(field);
+        let header = RecordHeader::parse(record)?;
+        let body = &record[RECORD_HEADER_SIZE..];
+        let offset = if header.type_ == PERF_RECORD_SAMPLE {
+            if has(SampleType::IDENTIFIER) {
+                0
+            } else if has(SampleType::ID) {
+                let before = [
+                    SampleType::IP,
+                    SampleType::TID,
+                    SampleType::TIME,
+                    SampleType::ADDR,
+                ];
+                before.into_iter().filter(|&field| has(field)).count() * 8
+            } else {
+                return None;
+            }
+        } else if header.type_ < PERF_RECORD_USER_TYPE_START && format.sample_id_all {
+            // Counted back from the end of the sample id
+            let from_end = if has(SampleType::IDENTIFIER) {
+                8
+            } else if has(SampleType::ID) {
+                let after = [SampleType::STREAM_ID, SampleType::CPU];
+                8 + after.into_iter().filter(|&field| has(field)).count() * 8
+            } else {
+                return None;
+            };
+            body.len().checked_sub(from_end)?
+        } else {
+            return None;
+        };
+        body.get(offset..offset + 8).map(|id| u64_at(id, 0))
+    }
+
+    /// Read the feature sections. One which cannot be read is left out
+    /// rather than failing the whole file, as perf does.
+    fn parse_features(&mut self) {
+        let table_offset = self.header.data.offset + self.header.data.size;
+        let bits: Vec<u32> = self.header.feature_bits().collect();
+        for (i, feature) in bits.into_iter().enumerate() {
+            let entry = FileSection {
+                offset: table_offset + (i * FILE_SECTION_SIZE) as u64,
+                size: FILE_SECTION_SIZE as u64,
+            };
+            let _ = self.parse_feature(entry, feature);
+        }
+    }
+
+    fn parse_feature(&mut self, entry: FileSection, feature: u32) -> io::Result<()> {
+        let location = parse_section(section(&self.bytes, entry)?)?;
+        let data = section(&self.bytes, location)?;
+        let mut fields = Fields { bytes: data };
+        let features = &mut self.features;
+        match feature {
+            HEADER_HOSTNAME => features.hostname = Some(fields.string()?),
+            HEADER_OSRELEASE => features.os_release = Some(fields.string()?),
+            HEADER_VERSION => features.version = Some(fields.string()?),
+            HEADER_ARCH => features.arch = Some(fields.string()?),
+            HEADER_NRCPUS => {
+                features.nr_cpus = Some(NrCpus {
+                    available: fields.u32()?,
+                    online: fields.u32()?,
+                })
+            }
+            HEADER_CMDLINE => {
+                let nr = fields.u32()?;
+                let cmdline = (0..nr)
+                    .map(|_| fields.string())
+                    .collect::<io::Result<_>>()?;
+                features.cmdline = Some(cmdline);
+            }
+            HEADER_EVENT_DESC => {
+                let nr = fields.u32()?;
+                let attr_size = fields.u32()? as usize;
+                // Described in the same order as the attrs section
+                for i in 0..nr as usize {
+                    fields.take(attr_size)?;
+                    let nr_ids = fields.u32()?;
+                    let name = fields.string()?;
+                    fields.take(nr_ids as usize * 8)?;
+                    if let Some(event) = self.events.get_mut(i) {
+                        event.name = name;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the records of a [`PerfData`], see [`PerfData::records`].
+pub struct PerfDataRecords<'a> {
+    perf_data: &'a PerfData,
+    raw: RawRecords<'a>,
+}
+
+impl Iterator for PerfDataRecords<'_> {
+    type Item = Result<Record, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            next_record(&mut self.raw)?
+                .and_then(|record| self.perf_data.format_of(record).parse(record)),
+        )
+    }
+}
+
+/// The next record of `raw`, stepping over the data which follows a
+/// [`PERF_RECORD_AUXTRACE`], its size the first field of the record.
+fn next_record<'a>(raw: &mut RawRecords<'a>) -> Option<Result<&'a [u8], RecordError>> {
+    let record = match raw.next()? {
+        Ok(record) => record,
+        err => return Some(err),
+    };
+    if RecordHeader::parse(record).map(|header| header.type_) == Some(PERF_RECORD_AUXTRACE) {
+        let truncated = RecordError::Truncated {
+            type_: PERF_RECORD_AUXTRACE,
+        };
+        let Some(size) = record.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + 8) else {
+            return Some(Err(truncated));
+        };
+        if !raw.skip(u64_at(size, 0) as usize) {
+            return Some(Err(truncated));
+        }
+    }
+    Some(Ok(record))
+}
+
+/// The id and event index of each entry of the [`PERF_RECORD_ID_INDEX`]
+/// records in `data`. Each entry is the id, index, CPU and thread.
+fn id_index(data: &[u8]) -> Vec<(u64, usize)> {
+    let mut ids = Vec::new();
+    let mut raw = RawRecords::new(data);
+    while let Some(Ok(record)) = next_record(&mut raw) {
+        if RecordHeader::parse(record).map(|header| header.type_) != Some(PERF_RECORD_ID_INDEX) {
+            continue;
+        }
+        let body = &record[RECORD_HEADER_SIZE..];
+        let Some(nr) = body.get(..8).map(|nr| u64_at(nr, 0)) else {
+            continue;
+        };
+        for entry in body[8..].chunks_exact(32).take(nr as usize) {
+            ids.push((u64_at(entry, 0), u64_at(entry, 8) as usize));
+        }
+    }
+    ids
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<FileHeader> {
+    let start = bytes.get(..16).ok_or_else(|| malformed("file too short"))?;
+    match u64_at(start, 0) {
+        PERF_MAGIC => {}
+        magic if magic == PERF_MAGIC.swap_bytes() => {
+            return Err(malformed(
+                "files from machines of other endianness are not supported",
+            ));
+        }
+        _ => return Err(malformed("not a perf.data file")),
+    }
+    // perf record -o - writes just the magic and size, then everything
+    // else as records
+    if u64_at(start, 8) < FILE_HEADER_SIZE as u64 {
+        return Err(malformed("files written to a pipe are not supported"));
+    }
+    let header = bytes
+        .get(..FILE_HEADER_SIZE)
+        .ok_or_else(|| malformed("file too short"))?;
+    let section_at = |offset| FileSection {
+        offset: u64_at(header, offset),
+        size: u64_at(header, offset + 8),
+    };
+    Ok(FileHeader {
+        attr_size: u64_at(header, 16),
+        attrs: section_at(24),
+        data: section_at(40),
+        event_types: section_at(56),
+        features: [
+            u64_at(header, 72),
+            u64_at(header, 80),
+            u64_at(header, 88),
+            u64_at(header, 96),
+        ],
+    })
+}
+
+/// An attr of any size, truncating fields this crate does not know and
+/// zeroing those missing from older files.
+fn parse_attr(bytes: &[u8]) -> PerfEventAttr {
+    let mut attr = PerfEventAttr::default();
+    let len = bytes.len().min(ATTR_SIZE);
+    // PerfEventAttr is repr(C) plain data
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            &mut attr as *mut PerfEventAttr as *mut u8,
+            len,
+        );
+    }
+    attr
+}
+
+fn parse_section(bytes: &[u8]) -> io::Result<FileSection> {
+    let bytes = bytes
+        .get(..FILE_SECTION_SIZE)
+        .ok_or_else(|| malformed("section truncated"))?;
+    Ok(FileSection {
+        offset: u64_at(bytes, 0),
+        size: u64_at(bytes, 8),
+    })
+}
+
+fn section(bytes: &[u8], section: FileSection) -> io::Result<&[u8]> {
+    let start = usize::try_from(section.offset).map_err(|_| malformed("section out of range"))?;
+    let size = usize::try_from(section.size).map_err(|_| malformed("section out of range"))?;
+    start
+        .checked_add(size)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| malformed("section out of range"))
+}
+
+/// The native endian u64 at `offset`, which callers have checked is in
+/// range.
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A cursor over the fields of a feature section.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(malformed("feature section truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A length prefixed, nul padded string.
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
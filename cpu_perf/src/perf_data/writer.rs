@@ -0,0 +1,342 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use super::{
+    ATTR_SIZE, FILE_HEADER_SIZE, FILE_SECTION_SIZE, Features, FileHeader, FileSection, HEADER_ARCH,
+    HEADER_CMDLINE, HEADER_EVENT_DESC, HEADER_HOSTNAME, HEADER_NRCPUS, HEADER_OSRELEASE,
+    HEADER_VERSION, NAME_ALIGN, PERF_MAGIC, PerfDataEvent,
+};
+use crate::{
+    perf_events::{
+        PERF_RECORD_COMM, PERF_RECORD_MISC_USER, PERF_RECORD_MMAP2, PerfEventAttr, RecordFormat,
+        SampleId, flags::SampleType,
+    },
+    symbols::Mapping,
+};
+
+/// Writes records to a `perf.data` file which `perf report` and
+/// `perf script` can read.
+///
+/// The file is laid out as perf lays it out: the header, the ids and
+/// attrs of the events, the records, then the feature sections. The
+/// header is rewritten by [`Self::finish`] once the sizes are known.
+///
+/// # Example
+///
+/// Writing a file in memory and reading it back:
+///
+/// ```
+/// use std::io::Cursor;
+/// use cpu_perf::{
+///     perf_data::{Features, PerfData, PerfDataEvent, PerfDataWriter, comm_record},
+///     perf_events::{EventSpec, EventType, Record, SamplingConfig, flags::SampleType},
+/// };
+///
+/// let config = SamplingConfig::new(EventSpec::new(EventType::CpuClock));
+/// let sample_type = config.sample_type | SampleType::IDENTIFIER;
+/// let config = config.with_sample_type(sample_type);
+/// let event = PerfDataEvent {
+///     attr: config.attr(),
+///     ids: vec![41, 42],
+///     name: "cpu-clock".to_string(),
+/// };
+/// let mut writer = PerfDataWriter::new(Cursor::new(Vec::new()), vec![event.clone()]).unwrap();
+/// writer.write_record(&comm_record(&config.record_format(), 41, 100, 100, "app")).unwrap();
+/// let features = Features {
+///     hostname: Some("build-box".to_string()),
+///     cmdline: Some(vec!["cpu_perf".to_string(), "profile".to_string()]),
+///     ..Features::default()
+/// };
+/// let file = writer.finish(&features).unwrap().into_inner();
+///
+/// let perf_data = PerfData::parse(file).unwrap();
+/// assert_eq!(perf_data.events(), [event]);
+/// assert_eq!(perf_data.features(), &features);
+/// let records: Vec<Record> = perf_data.records().map(Result::unwrap).collect();
+/// let [Record::Comm(comm)] = &records[..] else {
+///     panic!("expected a comm record, got {:?}", records);
+/// };
+/// assert_eq!(comm.comm, "app");
+/// // Attributed to the event, so perf can tell its format
+/// assert_eq!(comm.sample_id.identifier, Some(41));
+/// assert_eq!(comm.sample_id.tid, Some(100));
+/// ```
+pub struct PerfDataWriter<W: Write + Seek> {
+    out: W,
+    events: Vec<PerfDataEvent>,
+    header: FileHeader,
+}
+
+impl PerfDataWriter<BufWriter<File>> {
+    /// Create the file at `path`, replacing any already there.
+    pub fn create(path: impl AsRef<Path>, events: Vec<PerfDataEvent>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), events)
+    }
+}
+
+impl<W: Write + Seek> PerfDataWriter<W> {
+    /// Start a file in `out` for records of `events`. Records of an
+    /// event must be in the format of its attr; when there is more than
+    /// one event every record must carry an id, in the same place in
+    /// every format, so readers can tell which event it came from.
+    pub fn new(mut out: W, events: Vec<PerfDataEvent>) -> io::Result<Self> {
+        if events.is_empty() {
+            return Err(io::Error::other("perf.data needs at least one event"));
+        }
+        // Filled in by finish
+        out.write_all(&[0; FILE_HEADER_SIZE])?;
+
+        let mut id_sections = Vec::with_capacity(events.len());
+        let mut offset = FILE_HEADER_SIZE as u64;
+        for event in &events {
+            for id in &event.ids {
+                out.write_all(&id.to_ne_bytes())?;
+            }
+            let size = event.ids.len() as u64 * 8;
+            id_sections.push(FileSection { offset, size });
+            offset += size;
+        }
+
+        let attr_size = (ATTR_SIZE + FILE_SECTION_SIZE) as u64;
+        let attrs = FileSection {
+            offset,
+            size: attr_size * events.len() as u64,
+        };
+        for (event, ids) in events.iter().zip(id_sections) {
+            out.write_all(attr_bytes(&event.attr))?;
+            write_section(&mut out, ids)?;
+        }
+
+        let header = FileHeader {
+            attr_size,
+            attrs,
+            data: FileSection {
+                offset: attrs.offset + attrs.size,
+                size: 0,
+            },
+            ..FileHeader::default()
+        };
+        Ok(Self {
+            out,
+            events,
+            header,
+        })
+    }
+
+    /// Append a record, laid out as in the ring buffer, such as from
+    /// [`crate::perf_events::Sampler::for_each_raw_record`].
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.out.write_all(record)?;
+        self.header.data.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Write `features`, along with the names of the events, then the
+    /// header, returning the finished output.
+    pub fn finish(mut self, features: &Features) -> io::Result<W> {
+        let mut sections: Vec<(u32, Vec<u8>)> = Vec::new();
+        let strings = [
+            (HEADER_HOSTNAME, &features.hostname),
+            (HEADER_OSRELEASE, &features.os_release),
+            (HEADER_VERSION, &features.version),
+            (HEADER_ARCH, &features.arch),
+        ];
+        for (feature, value) in strings {
+            if let Some(value) = value {
+                let mut section = Vec::new();
+                push_string(&mut section, value);
+                sections.push((feature, section));
+            }
+        }
+        if let Some(nr_cpus) = features.nr_cpus {
+            let mut section = Vec::new();
+            section.extend_from_slice(&nr_cpus.available.to_ne_bytes());
+            section.extend_from_slice(&nr_cpus.online.to_ne_bytes());
+            sections.push((HEADER_NRCPUS, section));
+        }
+        if let Some(cmdline) = &features.cmdline {
+            let mut section = Vec::new();
+            section.extend_from_slice(&(cmdline.len() as u32).to_ne_bytes());
+            for arg in cmdline {
+                push_string(&mut section, arg);
+            }
+            sections.push((HEADER_CMDLINE, section));
+        }
+        let mut section = Vec::new();
+        section.extend_from_slice(&(self.events.len() as u32).to_ne_bytes());
+        section.extend_from_slice(&(ATTR_SIZE as u32).to_ne_bytes());
+        for event in &self.events {
+            section.extend_from_slice(attr_bytes(&event.attr));
+            section.extend_from_slice(&(event.ids.len() as u32).to_ne_bytes());
+            push_string(&mut section, &event.name);
+            for id in &event.ids {
+                section.extend_from_slice(&id.to_ne_bytes());
+            }
+        }
+        sections.push((HEADER_EVENT_DESC, section));
+        sections.sort_by_key(|(feature, _)| *feature);
+
+        // A table of where each section is, straight after the data,
+        // then the sections themselves
+        let table_offset = self.header.data.offset + self.header.data.size;
+        let mut offset = table_offset + (sections.len() * FILE_SECTION_SIZE) as u64;
+        for (feature, section) in &sections {
+            self.header.set_feature(*feature);
+            let size = section.len() as u64;
+            write_section(&mut self.out, FileSection { offset, size })?;
+            offset += size;
+        }
+        for (_, section) in &sections {
+            self.out.write_all(section)?;
+        }
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header_bytes(&self.header))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A comm record naming thread `tid` of process `pid`, as perf
+/// synthesizes for processes already running when recording starts.
+///
+/// `id` is that of the event the record is attributed to, one of the
+/// [`PerfDataEvent::ids`], so that readers can find its format.
+pub fn comm_record(format: &RecordFormat, id: u64, pid: u32, tid: u32, comm: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&pid.to_ne_bytes());
+    body.extend_from_slice(&tid.to_ne_bytes());
+    body.extend_from_slice(comm.as_bytes());
+    body.push(0);
+    let sample_id = SampleId {
+        pid: Some(pid),
+        tid: Some(tid),
+        id: Some(id),
+        ..SampleId::default()
+    };
+    record(
+        PERF_RECORD_COMM,
+        PERF_RECORD_MISC_USER,
+        body,
+        format,
+        &sample_id,
+    )
+}
+
+/// An mmap2 record of an executable mapping of process `pid`, as
+/// perf synthesizes from `/proc/<pid>/maps` for processes already
+/// running when recording starts. `id` is as for [`comm_record`].
+pub fn mmap2_record(format: &RecordFormat, id: u64, pid: u32, mapping: &Mapping) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&pid.to_ne_bytes());
+    body.extend_from_slice(&pid.to_ne_bytes());
+    body.extend_from_slice(&mapping.start.to_ne_bytes());
+    body.extend_from_slice(&(mapping.end - mapping.start).to_ne_bytes());
+    body.extend_from_slice(&mapping.pgoff.to_ne_bytes());
+    // Device, inode and inode generation are unknown
+    body.extend_from_slice(&[0; 24]);
+    let prot = (libc::PROT_READ | libc::PROT_EXEC) as u32;
+    body.extend_from_slice(&prot.to_ne_bytes());
+    body.extend_from_slice(&(libc::MAP_PRIVATE as u32).to_ne_bytes());
+    body.extend_from_slice(mapping.path.as_bytes());
+    body.push(0);
+    let sample_id = SampleId {
+        pid: Some(pid),
+        tid: Some(pid),
+        id: Some(id),
+        ..SampleId::default()
+    };
+    record(
+        PERF_RECORD_MMAP2,
+        PERF_RECORD_MISC_USER,
+        body,
+        format,
+        &sample_id,
+    )
+}
+
+/// Pad `body` to 8 bytes, add `sample_id` if the format has one, and
+/// put a header in front.
+fn record(
+    type_: u32,
+    misc: u16,
+    mut body: Vec<u8>,
+    format: &RecordFormat,
+    sample_id: &SampleId,
+) -> Vec<u8> {
+    body.resize(body.len().next_multiple_of(8), 0);
+    if format.sample_id_all {
+        push_sample_id(&mut body, format, sample_id);
+    }
+    let size = (8 + body.len()) as u16;
+    let mut record = Vec::with_capacity(size as usize);
+    record.extend_from_slice(&type_.to_ne_bytes());
+    record.extend_from_slice(&misc.to_ne_bytes());
+    record.extend_from_slice(&size.to_ne_bytes());
+    record.extend_from_slice(&body);
+    record
+}
+
+/// Append the fields of `sample_id` which `format` has, in the order
+/// of [`RecordFormat::sample_id_size`]. Missing fields are zero, and the
+/// stream id is the id, as for events which are not inherited.
+fn push_sample_id(body: &mut Vec<u8>, format: &RecordFormat, sample_id: &SampleId) {
+    let has = |field| format.sample_type.contains(field);
+    let id = sample_id.id.unwrap_or(0);
+    if has(SampleType::TID) {
+        body.extend_from_slice(&sample_id.pid.unwrap_or(0).to_ne_bytes());
+        body.extend_from_slice(&sample_id.tid.unwrap_or(0).to_ne_bytes());
+    }
+    if has(SampleType::TIME) {
+        body.extend_from_slice(&sample_id.time.unwrap_or(0).to_ne_bytes());
+    }
+    if has(SampleType::ID) {
+        body.extend_from_slice(&id.to_ne_bytes());
+    }
+    if has(SampleType::STREAM_ID) {
+        body.extend_from_slice(&sample_id.stream_id.unwrap_or(id).to_ne_bytes());
+    }
+    if has(SampleType::CPU) {
+        body.extend_from_slice(&sample_id.cpu.unwrap_or(0).to_ne_bytes());
+        body.extend_from_slice(&0u32.to_ne_bytes());
+    }
+    if has(SampleType::IDENTIFIER) {
+        body.extend_from_slice(&sample_id.identifier.unwrap_or(id).to_ne_bytes());
+    }
+}
+
+fn attr_bytes(attr: &PerfEventAttr) -> &[u8] {
+    // PerfEventAttr is repr(C) with no padding
+    unsafe { std::slice::from_raw_parts(attr as *const PerfEventAttr as *const u8, ATTR_SIZE) }
+}
+
+fn header_bytes(header: &FileHeader) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(FILE_HEADER_SIZE);
+    bytes.extend_from_slice(&PERF_MAGIC.to_ne_bytes());
+    bytes.extend_from_slice(&(FILE_HEADER_SIZE as u64).to_ne_bytes());
+    bytes.extend_from_slice(&header.attr_size.to_ne_bytes());
+    for section in [header.attrs, header.data, header.event_types] {
+        bytes.extend_from_slice(&section.offset.to_ne_bytes());
+        bytes.extend_from_slice(&section.size.to_ne_bytes());
+    }
+    for word in header.features {
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    bytes
+}
+
+fn write_section(out: &mut impl Write, section: FileSection) -> io::Result<()> {
+    out.write_all(&section.offset.to_ne_bytes())?;
+    out.write_all(&section.size.to_ne_bytes())
+}
+
+/// Append a length prefixed, nul terminated and padded string.
+fn push_string(section: &mut Vec<u8>, string: &str) {
+    let len = (string.len() + 1).next_multiple_of(NAME_ALIGN);
+    section.extend_from_slice(&(len as u32).to_ne_bytes());
+    section.extend_from_slice(string.as_bytes());
+    section.resize(section.len() + len - string.len(), 0);
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use super::BranchEntry;
+
+/// The kind of a branch, recorded with
+/// [`super::flags::BranchSampleType::TYPE_SAVE`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BranchType {
+    Unknown,
+    Conditional,
+    Unconditional,
+    Indirect,
+    Call,
+    IndirectCall,
+    Return,
+    Syscall,
+    SyscallReturn,
+    ConditionalCall,
+    ConditionalReturn,
+    ExceptionReturn,
+    Interrupt,
+    SystemError,
+    NoTransaction,
+    /// A type added after this was written, or an architecture specific
+    /// one.
+    Other(u8),
+}
+
+impl BranchType {
+    /// The type from its `PERF_BR_*` value.
+    pub fn from_raw(value: u8) -> Self {
+        match value {
+            0 => Self::Unknown,
+            1 => Self::Conditional,
+            2 => Self::Unconditional,
+            3 => Self::Indirect,
+            4 => Self::Call,
+            5 => Self::IndirectCall,
+            6 => Self::Return,
+            7 => Self::Syscall,
+            8 => Self::SyscallReturn,
+            9 => Self::ConditionalCall,
+            10 => Self::ConditionalReturn,
+            11 => Self::ExceptionReturn,
+            12 => Self::Interrupt,
+            13 => Self::SystemError,
+            14 => Self::NoTransaction,
+            value => Self::Other(value),
+        }
+    }
+}
+
+// Bit fields of the flags of struct perf_branch_entry
+const BRANCH_MISPRED: u64 = 1 << 0;
+const BRANCH_PREDICTED: u64 = 1 << 1;
+const BRANCH_IN_TX: u64 = 1 << 2;
+const BRANCH_ABORT: u64 = 1 << 3;
+const BRANCH_CYCLES_SHIFT: u64 = 4;
+const BRANCH_TYPE_SHIFT: u64 = 20;
+
+/// Decoding the packed flags of a branch. Hardware which cannot tell
+/// whether a branch was predicted leaves both
+/// [`Self::mispredicted`] and [`Self::predicted`] false.
+///
+/// ```
+/// use cpu_perf::perf_events::{BranchEntry, BranchType};
+///
+/// // Mispredicted, 37 cycles since the previous branch, a return
+/// let entry = BranchEntry { from: 0x4010, to: 0x4200, flags: 1 | 37 << 4 | 6 << 20 };
+/// assert!(entry.mispredicted());
+/// assert!(!entry.predicted());
+/// assert_eq!(entry.cycles(), 37);
+/// assert_eq!(entry.branch_type(), BranchType::Return);
+/// ```
+impl BranchEntry {
+    pub fn mispredicted(&self) -> bool {
+        self.flags & BRANCH_MISPRED != 0
+    }
+
+    pub fn predicted(&self) -> bool {
+        self.flags & BRANCH_PREDICTED != 0
+    }
+
+    /// Whether the branch was inside a hardware transaction.
+    pub fn in_transaction(&self) -> bool {
+        self.flags & BRANCH_IN_TX != 0
+    }
+
+    /// Whether the branch was a transaction abort.
+    pub fn aborted(&self) -> bool {
+        self.flags & BRANCH_ABORT != 0
+    }
+
+    /// Core cycles since the previous branch in the stack, or 0 if not
+    /// supported.
+    pub fn cycles(&self) -> u16 {
+        (self.flags >> BRANCH_CYCLES_SHIFT) as u16
+    }
+
+    /// The kind of branch, [`BranchType::Unknown`] unless recorded with
+    /// [`super::flags::BranchSampleType::TYPE_SAVE`].
+    pub fn branch_type(&self) -> BranchType {
+        BranchType::from_raw((self.flags >> BRANCH_TYPE_SHIFT) as u8 & 0xf)
+    }
+}
+
+/// Counts of branches from the branch stacks of samples, for finding
+/// the branches which are mispredicted most often.
+///
+/// ```
+/// use cpu_perf::perf_events::{BranchEntry, BranchProfile};
+///
+/// let branch = |from, mispredicted: bool| BranchEntry {
+///     from,
+///     to: from + 0x20,
+///     flags: if mispredicted { 1 } else { 2 } | 10 << 4,
+/// };
+/// let mut profile = BranchProfile::default();
+/// profile.add(100, &[branch(0x1000, true), branch(0x2000, false), branch(0x3000, true)]);
+/// profile.add(100, &[branch(0x1000, true), branch(0x1000, false)]);
+///
+/// let hottest = profile.hottest_mispredicted(2);
+/// assert_eq!(hottest[0].from, 0x1000);
+/// assert_eq!((hottest[0].mispredicted, hottest[0].taken), (2, 3));
+/// assert_eq!(hottest[0].average_cycles(), Some(10.0));
+/// assert_eq!(hottest[1].from, 0x3000);
+/// assert_eq!(profile.mispredicted(), 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BranchProfile {
+    branches: HashMap<(u32, u64, u64), BranchCounts>,
+    taken: u64,
+    mispredicted: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BranchCounts {
+    taken: u64,
+    mispredicted: u64,
+    /// Total cycles of the entries which had them.
+    cycles: u64,
+    with_cycles: u64,
+}
+
+/// A row of [`BranchProfile::hottest_mispredicted`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HotBranch {
+    pub pid: u32,
+    pub from: u64,
+    pub to: u64,
+    /// Times the branch was seen in a branch stack.
+    pub taken: u64,
+    pub mispredicted: u64,
+    cycles: u64,
+    with_cycles: u64,
+}
+
+impl HotBranch {
+    /// The share of the times the branch was taken that it was
+    /// mispredicted.
+    pub fn mispredict_percent(&self) -> f64 {
+        match self.taken {
+            0 => 0.0,
+            taken => self.mispredicted as f64 * 100.0 / taken as f64,
+        }
+    }
+
+    /// The average cycles before the branch, if the hardware reports
+    /// them.
+    pub fn average_cycles(&self) -> Option<f64> {
+        match self.with_cycles {
+            0 => None,
+            with_cycles => Some(self.cycles as f64 / with_cycles as f64),
+        }
+    }
+}
+
+impl BranchProfile {
+    /// Add the branch stack of a sample of process `pid`.
+    pub fn add(&mut self, pid: u32, branch_stack: &[BranchEntry]) {
+        for entry in branch_stack {
+            let counts = self
+                .branches
+                .entry((pid, entry.from, entry.to))
+                .or_default();
+            counts.taken += 1;
+            self.taken += 1;
+            if entry.mispredicted() {
+                counts.mispredicted += 1;
+                self.mispredicted += 1;
+            }
+            if entry.cycles() > 0 {
+                counts.cycles += entry.cycles() as u64;
+                counts.with_cycles += 1;
+            }
+        }
+    }
+
+    /// The number of branch stack entries seen.
+    pub fn taken(&self) -> u64 {
+        self.taken
+    }
+
+    /// The number of those which were mispredicted.
+    pub fn mispredicted(&self) -> u64 {
+        self.mispredicted
+    }
+
+    /// The `count` branches mispredicted most often, most first.
+    /// Branches which were never mispredicted are left out.
+    pub fn hottest_mispredicted(&self, count: usize) -> Vec<HotBranch> {
+        let mut branches: Vec<HotBranch> = self
+            .branches
+            .iter()
+            .filter(|(_, counts)| counts.mispredicted > 0)
+            .map(|(&(pid, from, to), counts)| HotBranch {
+                pid,
+                from,
+                to,
+                taken: counts.taken,
+                mispredicted: counts.mispredicted,
+                cycles: counts.cycles,
+                with_cycles: counts.with_cycles,
+            })
+            .collect();
+        branches.sort_by(|a, b| {
+            b.mispredicted
+                .cmp(&a.mispredicted)
+                .then_with(|| (a.pid, a.from, a.to).cmp(&(b.pid, b.from, b.to)))
+        });
+        branches.truncate(count);
+        branches
+    }
+}
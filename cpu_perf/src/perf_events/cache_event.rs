@@ -0,0 +1,97 @@
+/// A generic hardware cache event, counted with `PERF_TYPE_HW_CACHE`.
+///
+/// Each event is the combination of a cache, the operation performed
+/// on it and whether that operation hit or missed. Not every
+/// combination is supported by every CPU, unsupported combinations
+/// fail when opened.
+///
+/// Use through [`super::EventType::HwCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheEvent {
+    pub cache: CacheId,
+    pub op: CacheOp,
+    pub result: CacheResult,
+}
+
+impl CacheEvent {
+    pub const fn new(cache: CacheId, op: CacheOp, result: CacheResult) -> Self {
+        Self { cache, op, result }
+    }
+
+    /// The `config` field of [`super::PerfEventAttr`] for this event.
+    pub fn config(self) -> u64 {
+        (self.cache as u64) | ((self.op as u64) << 8) | ((self.result as u64) << 16)
+    }
+
+    /// The event with the given `config`, or `None` if it is not one
+    /// this crate knows.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{CacheEvent, CacheId, CacheOp, CacheResult};
+    ///
+    /// let event = CacheEvent::new(CacheId::DTLB, CacheOp::Read, CacheResult::Miss);
+    /// assert_eq!(CacheEvent::from_config(event.config()), Some(event));
+    /// assert_eq!(CacheEvent::from_config(0x3_00_07), None);
+    /// ```
+    pub fn from_config(config: u64) -> Option<Self> {
+        let cache = match config & 0xff {
+            0 => CacheId::L1D,
+            1 => CacheId::L1I,
+            2 => CacheId::LL,
+            3 => CacheId::DTLB,
+            4 => CacheId::ITLB,
+            5 => CacheId::BPU,
+            6 => CacheId::NODE,
+            _ => return None,
+        };
+        let op = match (config >> 8) & 0xff {
+            0 => CacheOp::Read,
+            1 => CacheOp::Write,
+            2 => CacheOp::Prefetch,
+            _ => return None,
+        };
+        let result = match config >> 16 {
+            0 => CacheResult::Access,
+            1 => CacheResult::Miss,
+            _ => return None,
+        };
+        Some(Self::new(cache, op, result))
+    }
+}
+
+/// The cache being measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CacheId {
+    /// Level 1 data cache.
+    L1D = 0,
+    /// Level 1 instruction cache.
+    L1I = 1,
+    /// Last level cache.
+    LL = 2,
+    /// Data translation lookaside buffer.
+    DTLB = 3,
+    /// Instruction translation lookaside buffer.
+    ITLB = 4,
+    /// Branch prediction unit.
+    BPU = 5,
+    /// Local memory accesses.
+    NODE = 6,
+}
+
+/// The operation performed on the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CacheOp {
+    Read = 0,
+    Write = 1,
+    Prefetch = 2,
+}
+
+/// Whether the operation hit (was an access) or missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CacheResult {
+    Access = 0,
+    Miss = 1,
+}
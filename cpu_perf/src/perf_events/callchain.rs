@@ -0,0 +1,68 @@
+use super::{CpuMode, Sample};
+
+// Markers in callchains giving the context of the addresses which
+// follow, negative numbers in the kernel's definition
+pub const PERF_CONTEXT_HV: u64 = -32i64 as u64;
+pub const PERF_CONTEXT_KERNEL: u64 = -128i64 as u64;
+pub const PERF_CONTEXT_USER: u64 = -512i64 as u64;
+pub const PERF_CONTEXT_GUEST: u64 = -2048i64 as u64;
+pub const PERF_CONTEXT_GUEST_KERNEL: u64 = -2176i64 as u64;
+pub const PERF_CONTEXT_GUEST_USER: u64 = -2560i64 as u64;
+/// Values at or above this are markers rather than addresses.
+pub const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+/// A return address from a callchain along with where it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub ip: u64,
+    pub mode: CpuMode,
+}
+
+/// Split a raw callchain into frames, using the `PERF_CONTEXT_*`
+/// markers to tell kernel frames from user frames. Frames are in the
+/// order of the callchain, innermost first.
+///
+/// ```
+/// use cpu_perf::perf_events::{
+///     CpuMode, Frame, PERF_CONTEXT_KERNEL, PERF_CONTEXT_USER, callchain_frames,
+/// };
+///
+/// let callchain = [PERF_CONTEXT_KERNEL, 0xffffffff81001000, PERF_CONTEXT_USER, 0x4010, 0x4321];
+/// let frames = callchain_frames(&callchain);
+/// assert_eq!(frames[0], Frame { ip: 0xffffffff81001000, mode: CpuMode::Kernel });
+/// assert_eq!(frames[1], Frame { ip: 0x4010, mode: CpuMode::User });
+/// assert_eq!(frames.len(), 3);
+/// ```
+pub fn callchain_frames(callchain: &[u64]) -> Vec<Frame> {
+    let mut mode = CpuMode::Unknown;
+    let mut frames = Vec::with_capacity(callchain.len());
+    for &ip in callchain {
+        if ip >= PERF_CONTEXT_MAX {
+            mode = match ip {
+                PERF_CONTEXT_HV => CpuMode::Hypervisor,
+                PERF_CONTEXT_KERNEL => CpuMode::Kernel,
+                PERF_CONTEXT_USER => CpuMode::User,
+                PERF_CONTEXT_GUEST_KERNEL => CpuMode::GuestKernel,
+                PERF_CONTEXT_GUEST_USER => CpuMode::GuestUser,
+                _ => CpuMode::Unknown,
+            };
+            continue;
+        }
+        frames.push(Frame { ip, mode });
+    }
+    frames
+}
+
+impl Sample {
+    /// The frames of the callchain, innermost first, or just the ip if
+    /// the sample has no callchain.
+    pub fn frames(&self) -> Vec<Frame> {
+        match (self.callchain.is_empty(), self.ip) {
+            (true, Some(ip)) => vec![Frame {
+                ip,
+                mode: self.cpu_mode(),
+            }],
+            _ => callchain_frames(&self.callchain),
+        }
+    }
+}
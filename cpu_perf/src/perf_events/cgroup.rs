@@ -0,0 +1,312 @@
+use std::{
+    fs::{self, File},
+    io,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+/// The mount table of the current process, listing where the cgroup v2
+/// hierarchy is mounted.
+pub const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Prefixes container runtimes give the cgroup of a container, followed
+/// by the container id and `.scope`.
+const CONTAINER_SCOPE_PREFIXES: [&str; 4] = ["docker-", "libpod-", "cri-containerd-", "crio-"];
+
+/// Length of a full container id in hex.
+const CONTAINER_ID_LEN: usize = 64;
+
+/// Where Docker keeps the metadata of each container, by full id.
+const DOCKER_CONTAINERS: &str = "var/lib/docker/containers";
+
+/// An open cgroup v2 directory, which events can be scoped to with
+/// [`super::EventSet::for_cgroup`] or [`super::SystemEventSet::for_cgroup`].
+///
+/// Only tasks in the cgroup, or in cgroups below it, are counted.
+/// Counting is per CPU, so a cgroup must be opened on each CPU of
+/// interest.
+///
+/// # Example
+///
+/// Resolving containers against a fixture directory laid out like the
+/// cgroup hierarchy:
+///
+/// ```
+/// use std::fs;
+/// use cpu_perf::perf_events::Cgroup;
+///
+/// let root = std::env::temp_dir().join(format!("cpu_perf_cgroup_doc_{}", std::process::id()));
+/// let system = root.join("system.slice");
+/// fs::create_dir_all(system.join("docker-4f3a9c1e.scope")).unwrap();
+/// fs::create_dir_all(system.join("docker-4f9b0d2a.scope")).unwrap();
+/// fs::create_dir_all(root.join("machine.slice/libpod-77aa01.scope")).unwrap();
+///
+/// let cgroup = Cgroup::find_container_in(&root, "4f3a").unwrap();
+/// assert_eq!(cgroup.path(), system.join("docker-4f3a9c1e.scope"));
+///
+/// let cgroup = Cgroup::find_container_in(&root, "77aa01").unwrap();
+/// assert_eq!(cgroup.path(), root.join("machine.slice/libpod-77aa01.scope"));
+///
+/// // Ambiguous and unknown ids are errors
+/// assert!(Cgroup::find_container_in(&root, "4f").is_err());
+/// assert!(Cgroup::find_container_in(&root, "ffff").is_err());
+///
+/// let cgroup = Cgroup::open_in(&root, "/system.slice").unwrap();
+/// assert_eq!(cgroup.path(), system);
+/// fs::remove_dir_all(&root).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    dir: File,
+}
+
+impl Cgroup {
+    /// Open a cgroup by a path relative to the cgroup v2 hierarchy, see
+    /// [`cgroup2_root`], as shown in `/proc/<pid>/cgroup`, e.g.
+    /// `/system.slice/nginx.service`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_in(cgroup2_root()?, path)
+    }
+
+    /// Open a cgroup by a path relative to `root`. A leading `/` is
+    /// ignored and a path already under `root` is used as is.
+    pub fn open_in(root: impl AsRef<Path>, path: impl AsRef<Path>) -> io::Result<Self> {
+        let (root, path) = (root.as_ref(), path.as_ref());
+        let path = if path.starts_with(root) {
+            path.to_path_buf()
+        } else {
+            root.join(path.strip_prefix("/").unwrap_or(path))
+        };
+        Self::open_dir(path)
+    }
+
+    /// Find and open the cgroup of the container whose id starts with
+    /// `id`, as created by Docker, Podman, containerd or CRI-O with
+    /// either the systemd or cgroupfs cgroup driver.
+    pub fn find_container(id: &str) -> io::Result<Self> {
+        Self::find_container_in(cgroup2_root()?, id)
+    }
+
+    /// As [`Self::find_container`], searching the hierarchy under `root`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no container matches, or if more than one does.
+    pub fn find_container_in(root: impl AsRef<Path>, id: &str) -> io::Result<Self> {
+        if id.is_empty() {
+            return Err(io::Error::other("Container id cannot be empty"));
+        }
+        let mut scopes = Vec::new();
+        find_scopes(root.as_ref(), &mut scopes)?;
+        scopes.retain(|(_, scope_id)| scope_id.starts_with(id));
+        match scopes.len() {
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No cgroup found for container {}", id),
+            )),
+            1 => Self::open_dir(scopes.remove(0).0),
+            n => Err(io::Error::other(format!(
+                "Container id {} is ambiguous, it matches {} cgroups",
+                id, n
+            ))),
+        }
+    }
+
+    /// Find and open the cgroup of the container called `name`. This is
+    /// the name Docker gave the container, or else the host name of the
+    /// container, which Podman sets to its name and other runtimes to
+    /// that given with `--hostname`.
+    ///
+    /// Reading either needs the same privileges as counting the cgroup.
+    pub fn find_named_container(name: &str) -> io::Result<Self> {
+        Self::find_named_container_in(cgroup2_root()?, "/", name)
+    }
+
+    /// As [`Self::find_named_container`], searching the hierarchy under
+    /// `root` and reading Docker's metadata and `/proc` under `sysroot`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no container is called `name`, or if more than one is.
+    pub fn find_named_container_in(
+        root: impl AsRef<Path>,
+        sysroot: impl AsRef<Path>,
+        name: &str,
+    ) -> io::Result<Self> {
+        let sysroot = sysroot.as_ref();
+        let mut scopes = Vec::new();
+        find_scopes(root.as_ref(), &mut scopes)?;
+        scopes.retain(|(path, scope_id)| {
+            docker_name(sysroot, scope_id).as_deref() == Some(name)
+                || container_hostname(sysroot, path).as_deref() == Some(name)
+        });
+        match scopes.len() {
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No cgroup found for container named {}", name),
+            )),
+            1 => Self::open_dir(scopes.remove(0).0),
+            n => Err(io::Error::other(format!(
+                "Container name {} is ambiguous, it matches {} cgroups",
+                name, n
+            ))),
+        }
+    }
+
+    /// Open a cgroup given as a path, see [`Self::open`], failing that
+    /// as a container id, see [`Self::find_container`], and failing that
+    /// as a container name, see [`Self::find_named_container`].
+    pub fn resolve(name: &str) -> io::Result<Self> {
+        let root = cgroup2_root()?;
+        match Self::open_in(&root, name) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            res => return res,
+        }
+        match Self::find_container_in(&root, name) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            res => return res,
+        }
+        Self::find_named_container_in(&root, "/", name).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No cgroup, container id or container name {} found", name),
+            ),
+            _ => err,
+        })
+    }
+
+    fn open_dir(path: PathBuf) -> io::Result<Self> {
+        let dir = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(&path)?;
+        Ok(Self { path, dir })
+    }
+
+    /// The directory of the cgroup.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file descriptor of the cgroup directory, passed in place of
+    /// a pid with [`super::PERF_FLAG_PID_CGROUP`].
+    pub fn fd(&self) -> i32 {
+        self.dir.as_raw_fd()
+    }
+}
+
+/// Find where the cgroup v2 hierarchy is mounted, from [`MOUNTINFO`].
+///
+/// This is `/sys/fs/cgroup` on most systems, but `/sys/fs/cgroup/unified`
+/// on those which also mount cgroup v1 controllers.
+///
+/// # Errors
+///
+/// Fails with [`io::ErrorKind::NotFound`] if no cgroup v2 hierarchy is
+/// mounted, as counting a cgroup v1 hierarchy is not supported.
+pub fn cgroup2_root() -> io::Result<PathBuf> {
+    cgroup2_root_in(MOUNTINFO)
+}
+
+/// As [`cgroup2_root`], reading the mount table at `mountinfo`.
+pub fn cgroup2_root_in(mountinfo: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let mountinfo = mountinfo.as_ref();
+    for line in fs::read_to_string(mountinfo)?.lines() {
+        // <id> <parent> <dev> <root> <mount point> <options> [optional...] - <type> ...
+        let mut fields = line.split(' ');
+        let Some(mount_point) = fields.nth(4) else {
+            continue;
+        };
+        if fields.skip_while(|field| *field != "-").nth(1) == Some("cgroup2") {
+            return Ok(PathBuf::from(unescape_mount_path(mount_point)));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "No cgroup v2 hierarchy is mounted according to {}, only cgroup v2 can be counted",
+            mountinfo.display()
+        ),
+    ))
+}
+
+/// Undo the octal escapes of spaces, tabs, newlines and backslashes in
+/// paths of the mount table.
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// The name Docker gave the container with full id `id`, from the
+/// top level `"Name":"/<name>"` field of its `config.v2.json`.
+fn docker_name(sysroot: &Path, id: &str) -> Option<String> {
+    let config = fs::read(
+        sysroot
+            .join(DOCKER_CONTAINERS)
+            .join(id)
+            .join("config.v2.json"),
+    )
+    .ok()?;
+    let config: serde_json::Value = serde_json::from_slice(&config).ok()?;
+    let name = config.get("Name")?.as_str()?;
+    Some(name.strip_prefix('/').unwrap_or(name).to_string())
+}
+
+/// The host name seen by the first task of the container cgroup `path`,
+/// from `/etc/hostname` in its root.
+fn container_hostname(sysroot: &Path, path: &Path) -> Option<String> {
+    let procs = fs::read_to_string(path.join("cgroup.procs")).ok()?;
+    let pid = procs.lines().next()?;
+    let hostname =
+        fs::read_to_string(sysroot.join("proc").join(pid).join("root/etc/hostname")).ok()?;
+    Some(hostname.trim_end().to_string())
+}
+
+/// Collect every container scope under `dir` with its container id.
+fn find_scopes(dir: &Path, scopes: &mut Vec<(PathBuf, String)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let name = entry.file_name();
+        let scope_id = name.to_str().and_then(|name| {
+            // The cgroupfs driver names the cgroup after the bare id
+            if name.len() == CONTAINER_ID_LEN && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Some(name);
+            }
+            let name = name.strip_suffix(".scope")?;
+            CONTAINER_SCOPE_PREFIXES
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix))
+        });
+        match scope_id {
+            Some(scope_id) => {
+                let scope_id = scope_id.to_string();
+                scopes.push((path, scope_id));
+            }
+            None => find_scopes(&path, scopes)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+use std::{
+    ffi::{CString, OsStr, c_char},
+    fmt, io,
+    os::unix::{ffi::OsStrExt, io::RawFd, process::ExitStatusExt},
+    process::ExitStatus,
+    ptr,
+    time::{Duration, Instant},
+};
+
+use super::{EventCounts, EventSpec, Pmus, TaskEventSet, flags::PerfEventFlags};
+
+/// The result of [`measure_command`].
+#[derive(Debug, Clone)]
+pub struct CommandStats {
+    /// Counts of each group over the lifetime of the command and every
+    /// process and thread it created.
+    pub counts: Vec<EventCounts>,
+    /// Optional events the kernel did not support.
+    pub unavailable: Vec<EventSpec>,
+    /// Time from the command being started to it exiting.
+    pub wall_time: Duration,
+    pub exit_status: ExitStatus,
+}
+
+/// Run a command and count `groups` over its lifetime, the equivalent
+/// of `perf stat <command>`. Each group is opened as its own
+/// [`TaskEventSet`], so only the events of a group need to fit on the
+/// PMU together, and hardware events count on every kind of core of
+/// hybrid CPUs. Groups of only unsupported optional events are left out
+/// and listed as unavailable.
+///
+/// `argv[0]` is the program, looked up in `PATH`, and the rest its
+/// arguments. The counters are opened on the child before it execs with
+/// [`PerfEventFlags::ENABLE_ON_EXEC`], so nothing before the exec is
+/// counted, and [`PerfEventFlags::INHERIT`], so children of the command
+/// are counted too.
+///
+/// # Errors
+///
+/// Fails if `argv` or a group is empty, the events cannot be opened, the
+/// process cannot be created or the program cannot be executed, as for
+/// [`PausedCommand::resume`].
+pub fn measure_command<S: AsRef<OsStr>>(
+    argv: &[S],
+    groups: &[Vec<EventSpec>],
+) -> io::Result<CommandStats> {
+    let pmus = Pmus::discover()?;
+    let mut command = PausedCommand::spawn(argv)?;
+    let mut event_sets = Vec::with_capacity(groups.len());
+    let mut unavailable = Vec::new();
+    for group in groups {
+        let specs: Vec<EventSpec> = group
+            .iter()
+            .map(|spec| {
+                let flags = spec.flags | PerfEventFlags::ENABLE_ON_EXEC | PerfEventFlags::INHERIT;
+                spec.clone().with_flags(flags)
+            })
+            .collect();
+        match TaskEventSet::new(&specs, command.pid(), &pmus) {
+            Ok(event_set) => {
+                unavailable.extend(event_set.unavailable());
+                event_sets.push(event_set);
+            }
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                unavailable.extend(group.iter().cloned())
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let started = Instant::now();
+    command.resume()?;
+    let exit_status = command.wait()?;
+    let wall_time = started.elapsed();
+
+    Ok(CommandStats {
+        counts: event_sets
+            .iter()
+            .map(TaskEventSet::get_counts)
+            .collect::<io::Result<_>>()?,
+        unavailable,
+        wall_time,
+        exit_status,
+    })
+}
+
+/// A child process running a command, held back before it execs until
+/// [`Self::resume`] so that events can be opened on it first.
+///
+/// Dropping a command which was never resumed makes the child exit
+/// without running the command.
+pub struct PausedCommand {
+    pid: libc::pid_t,
+    /// The child blocks reading the other end until a byte is written.
+    go: Option<RawFd>,
+    /// The child writes the errno of a failed exec to the other end,
+    /// which is closed without writing when the exec succeeds.
+    exec_error: Option<RawFd>,
+    /// `argv[0]`, for errors.
+    program: String,
+    exit_status: Option<ExitStatus>,
+}
+
+impl PausedCommand {
+    /// Create the child for `argv`, where `argv[0]` is the program,
+    /// looked up in `PATH`, and the rest its arguments.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `argv` is empty or the process cannot be created. A
+    /// program which cannot be executed is reported by
+    /// [`Self::resume`].
+    pub fn spawn<S: AsRef<OsStr>>(argv: &[S]) -> io::Result<Self> {
+        if argv.is_empty() {
+            return Err(io::Error::other("No command given to run"));
+        }
+        // Everything the child needs is prepared before forking, as only
+        // async-signal-safe calls are allowed between fork and exec
+        let args = argv
+            .iter()
+            .map(|arg| CString::new(arg.as_ref().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut arg_ptrs: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        arg_ptrs.push(ptr::null());
+
+        let mut go = [0; 2];
+        if unsafe { libc::pipe2(go.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [go_read, go_write] = go;
+        let mut exec_error = [0; 2];
+        if unsafe { libc::pipe2(exec_error.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(go_read);
+                libc::close(go_write);
+            }
+            return Err(err);
+        }
+        let [error_read, error_write] = exec_error;
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                for fd in [go_read, go_write, error_read, error_write] {
+                    libc::close(fd);
+                }
+            }
+            return Err(err);
+        }
+        if pid == 0 {
+            unsafe {
+                libc::close(go_write);
+                libc::close(error_read);
+                let mut byte = 0u8;
+                if libc::read(go_read, &mut byte as *mut u8 as *mut _, 1) == 1 && byte == 1 {
+                    libc::execvp(arg_ptrs[0], arg_ptrs.as_ptr());
+                    // Only reached if the exec failed
+                    let errno = *libc::__errno_location();
+                    libc::write(
+                        error_write,
+                        &errno as *const i32 as *const _,
+                        size_of::<i32>(),
+                    );
+                }
+                libc::_exit(127);
+            }
+        }
+        unsafe {
+            libc::close(go_read);
+            libc::close(error_write);
+        }
+        Ok(Self {
+            pid,
+            go: Some(go_write),
+            exec_error: Some(error_read),
+            program: String::from_utf8_lossy(args[0].as_bytes()).into_owned(),
+            exit_status: None,
+        })
+    }
+
+    /// The pid of the child, to open events on.
+    pub fn pid(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Let the child exec the command, returning once it has.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the program cannot be executed, with
+    /// [`io::ErrorKind::NotFound`] if it is not found. The child then
+    /// exits with status 127 as in a shell. The error wraps an
+    /// [`ExecError`] giving the status a shell would exit with.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{ExecError, PausedCommand};
+    /// use std::io::ErrorKind;
+    ///
+    /// let mut command = PausedCommand::spawn(&["no-such-command"]).unwrap();
+    /// let err = command.resume().unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::NotFound);
+    /// assert_eq!(err.to_string(), "no-such-command: command not found");
+    /// assert_eq!(command.wait().unwrap().code(), Some(127));
+    /// assert_eq!(ExecError::exit_code_of(&err), Some(127));
+    /// ```
+    pub fn resume(&mut self) -> io::Result<()> {
+        let Some(go) = self.go.take() else {
+            return Ok(());
+        };
+        let res = unsafe { libc::write(go, &1u8 as *const u8 as *const _, 1) };
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(go) };
+        if res != 1 {
+            return Err(err);
+        }
+        match self.exec_error()? {
+            None => Ok(()),
+            Some(err) => {
+                self.wait()?;
+                Err(io::Error::new(
+                    err.kind(),
+                    ExecError {
+                        program: self.program.clone(),
+                        source: err,
+                    },
+                ))
+            }
+        }
+    }
+
+    /// Wait for the child to exec, returning the error if it failed.
+    fn exec_error(&mut self) -> io::Result<Option<io::Error>> {
+        let Some(fd) = self.exec_error.take() else {
+            return Ok(None);
+        };
+        let mut errno = 0i32;
+        let res = loop {
+            // Blocks until the exec closes the pipe or the child writes
+            let res = unsafe { libc::read(fd, &mut errno as *mut i32 as *mut _, size_of::<i32>()) };
+            if res >= 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                break res;
+            }
+        };
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        match res {
+            0 => Ok(None),
+            res if res as usize == size_of::<i32>() => {
+                Ok(Some(io::Error::from_raw_os_error(errno)))
+            }
+            res if res < 0 => Err(err),
+            _ => Err(io::Error::other("Short read of the exec error")),
+        }
+    }
+
+    /// The exit status of the command if it has exited, without
+    /// blocking.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.exit_status.is_none() {
+            self.exit_status = wait_for(self.pid, libc::WNOHANG)?;
+        }
+        Ok(self.exit_status)
+    }
+
+    /// Block until the command exits. Resumes it first if needed.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.resume()?;
+        if let Some(exit_status) = self.exit_status {
+            return Ok(exit_status);
+        }
+        let exit_status = wait_for(self.pid, 0)?.expect("waitpid blocks until the child exits");
+        self.exit_status = Some(exit_status);
+        Ok(exit_status)
+    }
+}
+
+/// A program which could not be executed, as reported by
+/// [`PausedCommand::resume`] inside an [`io::Error`].
+#[derive(Debug)]
+pub struct ExecError {
+    pub program: String,
+    pub source: io::Error,
+}
+
+impl ExecError {
+    /// The status a shell exits with for this error: 127 if the program
+    /// was not found, 126 if it was found but could not be executed.
+    pub fn exit_code(&self) -> i32 {
+        match self.source.kind() {
+            io::ErrorKind::NotFound => 127,
+            _ => 126,
+        }
+    }
+
+    /// [`Self::exit_code`] of the [`ExecError`] in `err`, if there is
+    /// one.
+    pub fn exit_code_of(err: &io::Error) -> Option<i32> {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<ExecError>())
+            .map(ExecError::exit_code)
+    }
+}
+
+impl std::error::Error for ExecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source.kind() {
+            io::ErrorKind::NotFound => write!(f, "{}: command not found", self.program),
+            _ => write!(f, "{}: {}", self.program, self.source),
+        }
+    }
+}
+
+impl Drop for PausedCommand {
+    fn drop(&mut self) {
+        if let Some(go) = self.go.take() {
+            // Closing the pipe without writing makes the child exit
+            unsafe { libc::close(go) };
+            let _ = self.wait();
+        }
+        if let Some(fd) = self.exec_error.take() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Wait for the child `pid` to exit, returning `None` if it has not and
+/// `options` contains `WNOHANG`.
+fn wait_for(pid: libc::pid_t, options: i32) -> io::Result<Option<ExitStatus>> {
+    let mut status = 0;
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, options) } {
+            0 => return Ok(None),
+            res if res > 0 => return Ok(Some(ExitStatus::from_raw(status))),
+            _ => {}
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
@@ -1,279 +1,703 @@
 use std::{ffi::c_void, io, mem};
 
-use libc::ioctl;
+use super::{Cgroup, EventIOState, EventSpec, EventType, PERF_FLAG_PID_CGROUP, PerfEvent};
 
-use super::{
-    EventIOState, EventType, PerfEvent, PerfEventAttr, SIZE_OF_U64, flags::PerfEventFlags,
-};
-
-/// Counts of each event for a single event period
-#[derive(Default, Clone, Copy)]
+/// Counts of each event for a single event period, keyed by the
+/// [`EventSpec`] they were collected for.
+///
+/// Only the events the [`EventSet`] was built with are present, in the
+/// order they were requested.
+///
+/// When more events are requested than the PMU has counters the kernel
+/// multiplexes them, so each event only counts for part of the period.
+/// The raw counts from [`Self::get`] are then an underestimate and
+/// [`Self::get_scaled`] should be used instead. Each event keeps the
+/// times of the group it was counted in, as groups are multiplexed
+/// independently, and [`Self::running_ratio_of`] reports how much of the
+/// period it was actually counted.
+///
+/// ```
+/// use cpu_perf::perf_events::{EventCounts, EventSpec, EventType};
+///
+/// // Two groups which each only got half or a quarter of the PMU
+/// let mut counts = EventCounts::new(vec![(EventSpec::new(EventType::CpuCycles), 100)], 1_000, 500);
+/// let misses = EventSpec::new(EventType::CacheMisses);
+/// counts.accumulate(&EventCounts::new(vec![(misses, 10)], 1_000, 250));
+/// assert!(counts.is_scaled());
+/// assert_eq!(counts.get_scaled(EventType::CpuCycles), Some(200));
+/// assert_eq!(counts.get_scaled(EventType::CacheMisses), Some(40));
+/// assert_eq!(counts.running_ratio(), 0.25);
+/// ```
+#[derive(Default, Clone, Debug)]
 pub struct EventCounts {
-    /// Total CPU cycles. Be wary of what happens as the CPU frequency
-    /// scales.
-    pub num_cpu_cycles: u64,
-    /// Retired instructions. Can be affected by various issues, most
-    /// notable hardware interrupt counts.
-    pub num_instructions: u64,
-    /// Cache accesses, generally Last Level Cache but may vary
-    /// depending on your CPU. May also include prefetches and coherency
-    /// messages, again CPU dependent.
-    pub num_cache_references: u64,
-    /// Cache misses, as with `num_cache_references`, generally Last
-    /// Level Cache, but CPU dependent. Intended for calculating miss
-    /// rate with `num_cache_references`.
-    pub num_cache_misses: u64,
-    /// Retired branch instruction.
-    pub num_branch_instructions: u64,
-    /// Mispredicted branch instructions.
-    pub num_branch_misses: u64,
-    /// Bus cycles, not to be confused with total cycles.
-    pub num_bus_cycles: u64,
-    // pub num_stalled_cycles_frontend: u64,
-    // pub num_stalled_cycles_backend: u64,
-    /// Total cycles, not influenced by CPU frequency scaling.
-    pub num_ref_cpu_cycles: u64,
+    counts: Vec<EventCount>,
+}
+
+/// The count of one event with the times of its group.
+#[derive(Clone, Debug)]
+struct EventCount {
+    spec: EventSpec,
+    value: u64,
+    /// Nanoseconds the group was enabled.
+    time_enabled: u64,
+    /// Nanoseconds the group was actually counting on the PMU.
+    time_running: u64,
+}
+
+impl EventCount {
+    fn scaled(&self) -> u64 {
+        if self.time_running == 0 {
+            0
+        } else if self.time_running >= self.time_enabled {
+            self.value
+        } else {
+            (self.value as u128 * self.time_enabled as u128 / self.time_running as u128) as u64
+        }
+    }
+
+    fn running_ratio(&self) -> f64 {
+        if self.time_enabled == 0 {
+            1.0
+        } else {
+            self.time_running as f64 / self.time_enabled as f64
+        }
+    }
+}
+
+impl EventCounts {
+    /// Counts of a single group gathered elsewhere, such as from the
+    /// samples of a `perf.data` file. Counts of other groups can be
+    /// added with [`Self::accumulate`].
+    pub fn new(counts: Vec<(EventSpec, u64)>, time_enabled: u64, time_running: u64) -> Self {
+        Self {
+            counts: counts
+                .into_iter()
+                .map(|(spec, value)| EventCount {
+                    spec,
+                    value,
+                    time_enabled,
+                    time_running,
+                })
+                .collect(),
+        }
+    }
+
+    fn find(&self, event: impl Into<EventSpec>) -> Option<&EventCount> {
+        let event = event.into();
+        self.counts
+            .iter()
+            .find(|count| count.spec.same_event(&event))
+    }
+
+    /// The raw count for `event`, or `None` if it was not part of the set.
+    /// An [`EventType`] stands for the event with the default flags of
+    /// [`EventSpec::new`].
+    pub fn get(&self, event: impl Into<EventSpec>) -> Option<u64> {
+        self.find(event).map(|count| count.value)
+    }
+
+    /// The count for `event` extrapolated over the full time its group
+    /// was enabled, or `None` if it was not part of the set.
+    ///
+    /// Identical to [`Self::get`] unless the group was multiplexed. If the
+    /// group never ran the estimate is zero.
+    pub fn get_scaled(&self, event: impl Into<EventSpec>) -> Option<u64> {
+        self.find(event).map(EventCount::scaled)
+    }
+
+    /// Nanoseconds the group enabled for longest was enabled.
+    pub fn time_enabled(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|count| count.time_enabled)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Nanoseconds the group counting least was actually counting on
+    /// the PMU.
+    pub fn time_running(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|count| count.time_running)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Fraction of the enabled time the group of `event` was actually
+    /// counting, between 0 and 1, or `None` if it was not part of the
+    /// set. Equivalent to the percentage column of `perf stat`. A group
+    /// which was never enabled reports 1.
+    pub fn running_ratio_of(&self, event: impl Into<EventSpec>) -> Option<f64> {
+        self.find(event).map(EventCount::running_ratio)
+    }
+
+    /// The lowest [`Self::running_ratio_of`] of any event, so how much
+    /// of the time the most multiplexed group was counting.
+    pub fn running_ratio(&self) -> f64 {
+        self.counts
+            .iter()
+            .map(EventCount::running_ratio)
+            .fold(1.0, f64::min)
+    }
+
+    /// Whether any count is extrapolated because its group was not
+    /// counting for the whole time it was enabled.
+    pub fn is_scaled(&self) -> bool {
+        self.counts
+            .iter()
+            .any(|count| count.time_running < count.time_enabled)
+    }
+
+    /// Iterate over `(event, count)` pairs of raw counts in the order the
+    /// events were requested.
+    pub fn iter(&self) -> impl Iterator<Item = (&EventSpec, u64)> + '_ {
+        self.counts.iter().map(|count| (&count.spec, count.value))
+    }
+
+    /// As [`Self::iter`] but with counts scaled as in [`Self::get_scaled`].
+    pub fn iter_scaled(&self) -> impl Iterator<Item = (&EventSpec, u64)> + '_ {
+        self.counts
+            .iter()
+            .map(|count| (&count.spec, count.scaled()))
+    }
+
+    /// Add `other` into these counts, as when totalling several CPUs.
+    ///
+    /// Counts are added by event, see [`EventSpec::same_event`], with
+    /// events missing from `self` appended. As `perf stat` does, the
+    /// enabled and running times of each event are summed too, so
+    /// [`Self::get_scaled`] scales the total by the overall fraction of
+    /// time counted.
+    pub fn accumulate(&mut self, other: &EventCounts) {
+        for count in &other.counts {
+            match self.find_mut(&count.spec) {
+                Some(total) => {
+                    total.value += count.value;
+                    total.time_enabled += count.time_enabled;
+                    total.time_running += count.time_running;
+                }
+                None => self.counts.push(count.clone()),
+            }
+        }
+    }
+
+    /// Add `other`, the same events of the same task counted on another
+    /// core PMU of a hybrid CPU, into these counts.
+    ///
+    /// A task runs on one kind of core at a time, and each PMU only
+    /// counts while it runs on its own kind, though both are enabled the
+    /// whole time. So the running times are summed while the enabled
+    /// times overlap, and counts are only scaled if the PMUs were also
+    /// multiplexed.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventCounts, EventSpec, EventType};
+    ///
+    /// let cycles = || EventSpec::new(EventType::CpuCycles);
+    /// // Of 4ms running, 3ms on P-cores and 1ms on E-cores
+    /// let mut counts = EventCounts::new(vec![(cycles(), 300)], 4_000_000, 3_000_000);
+    /// let e_cores = EventCounts::new(vec![(cycles(), 100)], 4_000_000, 1_000_000);
+    /// counts.accumulate_core_pmu(&e_cores);
+    /// assert!(!counts.is_scaled());
+    /// assert_eq!(counts.get_scaled(EventType::CpuCycles), Some(400));
+    /// ```
+    pub fn accumulate_core_pmu(&mut self, other: &EventCounts) {
+        for count in &other.counts {
+            match self.find_mut(&count.spec) {
+                Some(total) => {
+                    total.value += count.value;
+                    total.time_enabled = total.time_enabled.max(count.time_enabled);
+                    total.time_running =
+                        (total.time_running + count.time_running).min(total.time_enabled);
+                }
+                None => self.counts.push(count.clone()),
+            }
+        }
+    }
+
+    fn find_mut(&mut self, event: &EventSpec) -> Option<&mut EventCount> {
+        self.counts
+            .iter_mut()
+            .find(|count| count.spec.same_event(event))
+    }
+
+    /// The number of events with counts.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Whether there are no counts at all.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
 }
-pub const SIZE_OF_EVENT_COUNTS: usize = std::mem::size_of::<EventCounts>();
 
 /// Struct that wraps a set of perf_event file descriptors
 ///
-/// Can track any combination of [`EventType`], across
+/// Tracks a caller-supplied list of [`EventType`] or [`EventSpec`], across
 ///
 /// - All CPUs for a specific process.
 /// - All processes on a specific CPU.
 /// - A specific process on a specific CPU.
 ///
-/// Event types are enabled and disabled using [`Self::enable`] and
-/// [`Self::disable`].
+/// Only the requested events are opened, so an event the CPU does not
+/// support only matters if it is asked for. Events marked with
+/// [`EventSpec::optional`] are skipped when unsupported, and are listed
+/// by [`Self::unavailable`] instead.
+///
+/// Events are opened in one or more groups, see [`Self::from_groups`].
+/// The events of a group are always scheduled onto the PMU together, so
+/// ratios between them are exact, but a group must fit in the counters
+/// of the PMU. Separate groups are multiplexed by the kernel when they
+/// do not all fit at once, and their counts are scaled independently.
+///
+/// Individual events are enabled and disabled using [`Self::enable`]
+/// and [`Self::disable`], which take effect immediately without
+/// reopening the group. Members start enabled.
 ///
 /// Measurement starts and stops by calling [`Self::update_file_state`]
-/// with [`EventIOState::Enable`] and [`EventIOState::Disable`].
+/// with [`EventIOState::Enable`] and [`EventIOState::Disable`]. The
+/// groups start disabled.
 ///
 /// The most recent set of counts between enable and disable are obtained
-/// through [`Self::get_counts`].
+/// through [`Self::get_counts`], which only reports enabled events.
 ///
-/// As the different EventTypes are collected into a group,
-/// one event must be the parent. [`EventType::CpuCycles`] has
-/// been arbitrarily chosen as the parent. As such, it cannot
-/// be disabled as that will disable the whole group.
-///
-/// The whole group can be disabled using [`Self::disable_group`].
+/// As the events of a group are collected together, one event must be
+/// the parent of each group. The first event of a group which could be
+/// opened is its parent. As such, it cannot be disabled as that will
+/// disable the whole group.
 pub struct EventSet {
-    parent_fd: i32,
-    cpu_cycles: PerfEvent,
-    instructions: PerfEvent,
-    cache_references: PerfEvent,
-    cache_misses: PerfEvent,
-    branch_instructions: PerfEvent,
-    branch_misses: PerfEvent,
-    bus_cycles: PerfEvent,
-    // stalled_cycles_frontend: PerfEvent,
-    // stalled_cycles_backend: PerfEvent,
-    ref_cpu_cycles: PerfEvent,
+    /// The members of each group in the order they were opened. The
+    /// first of each is the group parent.
+    groups: Vec<Vec<GroupMember>>,
+    /// Optional events the kernel does not support.
+    unavailable: Vec<EventSpec>,
+}
+
+/// An opened event along with the id the kernel reports for it in
+/// group reads.
+struct GroupMember {
+    spec: EventSpec,
+    perf_event: PerfEvent,
+    id: u64,
+    /// Whether the member is enabled, the parent always is.
+    active: bool,
+}
+
+impl GroupMember {
+    fn new(spec: EventSpec, perf_event: PerfEvent) -> io::Result<Self> {
+        let id = perf_event
+            .id()
+            .inspect_err(|_| eprintln!("Error fetching id of file descriptor: {}", spec.name))?;
+        Ok(Self {
+            spec,
+            perf_event,
+            id,
+            active: true,
+        })
+    }
 }
 
 impl EventSet {
-    pub fn new(cpu_id: Option<u32>, process_id: Option<u32>) -> io::Result<Self> {
+    /// Open a single group containing `events`, excluding the
+    /// hypervisor. The first event becomes the group parent.
+    ///
+    /// See [`Self::from_specs`] to choose the flags of each event.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `events` is empty, if both `cpu_id` and `process_id`
+    /// are `None` or if any of the events cannot be opened, including
+    /// if there are more than the PMU has counters. Optional events are
+    /// only an error if they fail for a reason other than being
+    /// unsupported, or if none of the events could be opened, in which
+    /// case the error is of kind [`io::ErrorKind::Unsupported`].
+    pub fn new(
+        events: &[EventType],
+        cpu_id: Option<u32>,
+        process_id: Option<u32>,
+    ) -> io::Result<Self> {
+        let specs: Vec<EventSpec> = events.iter().copied().map(EventSpec::new).collect();
+        Self::from_specs(&specs, cpu_id, process_id)
+    }
+
+    /// Open a single group containing `specs`, e.g. as parsed by
+    /// [`super::EventParser`]. The first event becomes the group parent.
+    ///
+    /// Events which only differ by their modifiers are separate
+    /// members:
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventParser, EventSet};
+    ///
+    /// let parser = EventParser::default();
+    /// let user = parser.parse_event("page-faults:u").unwrap();
+    /// let kernel = parser.parse_event("page-faults:k").unwrap();
+    /// let specs = [parser.parse_event("task-clock").unwrap(), user.clone(), kernel.clone()];
+    /// let mut set = EventSet::from_specs(&specs, None, Some(0)).unwrap();
+    ///
+    /// set.disable(&[kernel.clone()]).unwrap();
+    /// assert!(set.is_enabled(user.clone()));
+    /// let counts = set.get_counts().unwrap();
+    /// assert!(counts.get(user).is_some());
+    /// assert_eq!(counts.get(kernel), None);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::new`].
+    pub fn from_specs(
+        specs: &[EventSpec],
+        cpu_id: Option<u32>,
+        process_id: Option<u32>,
+    ) -> io::Result<Self> {
+        Self::from_groups(&[specs], cpu_id, process_id)
+    }
+
+    /// Open each of `groups` as its own group, such as those parsed by
+    /// [`super::EventParser::parse_list`]. Only the events of a group
+    /// are guaranteed to be counted at the same time, and the groups
+    /// together may hold more events than the PMU has counters.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventSet, EventSpec, EventType};
+    ///
+    /// let group = |events: &[EventType]| -> Vec<EventSpec> {
+    ///     events.iter().copied().map(EventSpec::new).collect()
+    /// };
+    /// let groups = [
+    ///     group(&[EventType::TaskClock, EventType::PageFaults]),
+    ///     group(&[EventType::ContextSwitches]),
+    /// ];
+    /// let set = EventSet::from_groups(&groups, None, Some(0)).unwrap();
+    /// assert_eq!(set.groups().count(), 2);
+    /// assert!(set.get_counts().unwrap().get(EventType::ContextSwitches).is_some());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::new`], for any of the groups. A group in which no
+    /// event could be opened is left out, with its events listed by
+    /// [`Self::unavailable`], unless none of the groups could be opened.
+    pub fn from_groups<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        cpu_id: Option<u32>,
+        process_id: Option<u32>,
+    ) -> io::Result<Self> {
         if cpu_id.is_none() && process_id.is_none() {
             return Err(io::Error::other(
                 "Process Id and CPU Id cannot both be None",
             ));
         }
-        let cpu_id = cpu_id.map_or(-1, |id| id as i32);
-        let process_id = process_id.map_or(-1, |id| id as i32);
-
-        let flags = 0;
-        let attrs_flags = PerfEventFlags::EXCLUDE_HV;
-
-        // Group parent, do not disable
-        let cpu_cycles = PerfEvent::open(
-            PerfEventAttr::new(EventType::CpuCycles)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            None,
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising parent file descriptor: CpuCycles"))?;
-
-        // TODO: Consider a macro for this
-        let parent_fd = cpu_cycles.fd;
-        let instructions = PerfEvent::open(
-            PerfEventAttr::new(EventType::Instructions)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: Instructions"))?;
-        let cache_references = PerfEvent::open(
-            PerfEventAttr::new(EventType::CacheReferences)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
+        Self::open_groups(
+            groups,
+            process_id.map_or(-1, |id| id as i32),
+            cpu_id.map_or(-1, |id| id as i32),
+            0,
         )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: CacheReferences"))?;
-        let cache_misses = PerfEvent::open(
-            PerfEventAttr::new(EventType::CacheMisses)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: CacheMisses"))?;
-        let branch_instructions = PerfEvent::open(
-            PerfEventAttr::new(EventType::BranchInstructions)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: BranchInstructions"))?;
-        let branch_misses = PerfEvent::open(
-            PerfEventAttr::new(EventType::BranchMisses)
-                .with_flags(attrs_flags)
-                .with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: BranchMisses"))?;
-        let bus_cycles = PerfEvent::open(
-            PerfEventAttr::new(EventType::BusCycles).with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: BusCycles"))?;
-        // let stalled_cycles_frontend = PerfEvent::open(
-        //     PerfEventAttr::new(EventType::StalledCyclesFrontend).with_perf_format_group(),
-        //     Some(parent_fd),
-        //     process_id,
-        //     cpu_id,
-        //     flags,
-        // ).inspect_err(|_| eprintln!("Error initialising file descriptor: StalledCyclesFrontend"))?;
-        // let stalled_cycles_backend = PerfEvent::open(
-        //     PerfEventAttr::new(EventType::StalledCyclesBackend).with_perf_format_group(),
-        //     Some(parent_fd),
-        //     process_id,
-        //     cpu_id,
-        //     flags,
-        // ).inspect_err(|_| eprintln!("Error initialising file descriptor: StalledCyclesBackend"))?;
-        let ref_cpu_cycles = PerfEvent::open(
-            PerfEventAttr::new(EventType::RefCpuCycles).with_perf_format_group(),
-            Some(parent_fd),
-            process_id,
-            cpu_id,
-            flags,
-        )
-        .inspect_err(|_| eprintln!("Error initialising file descriptor: RefCpuCycles"))?;
+    }
+
+    /// Open each of `groups`, as in [`Self::from_groups`], counting only
+    /// tasks in `cgroup` while they run on `cpu_id`. Open one per CPU to
+    /// count the cgroup everywhere, e.g. with
+    /// [`super::SystemEventSet::for_cgroup`].
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::from_groups`].
+    pub fn for_cgroup<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        cgroup: &Cgroup,
+        cpu_id: u32,
+    ) -> io::Result<Self> {
+        Self::open_groups(groups, cgroup.fd(), cpu_id as i32, PERF_FLAG_PID_CGROUP)
+    }
+
+    /// Open the groups with the raw `perf_event_open` arguments.
+    fn open_groups<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        pid: i32,
+        cpu_id: i32,
+        flags: u64,
+    ) -> io::Result<Self> {
+        if groups.is_empty() || groups.iter().any(|group| group.as_ref().is_empty()) {
+            return Err(io::Error::other("EventSet requires at least one event"));
+        }
+
+        let mut opened = Vec::with_capacity(groups.len());
+        let mut unavailable = Vec::new();
+        for group in groups {
+            let members = Self::open_group(group.as_ref(), pid, cpu_id, flags, &mut unavailable)?;
+            if !members.is_empty() {
+                opened.push(members);
+            }
+        }
+        if opened.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "None of the events in the EventSet are supported",
+            ));
+        }
 
         Ok(Self {
-            parent_fd,
-            cpu_cycles,
-            instructions,
-            cache_references,
-            cache_misses,
-            branch_instructions,
-            branch_misses,
-            bus_cycles,
-            // stalled_cycles_frontend,
-            // stalled_cycles_backend,
-            ref_cpu_cycles,
+            groups: opened,
+            unavailable,
         })
     }
 
-    /// Enable tracking of a set of events.
+    /// Open `specs` as one group, adding optional events which are not
+    /// supported to `unavailable`.
+    fn open_group(
+        specs: &[EventSpec],
+        pid: i32,
+        cpu_id: i32,
+        flags: u64,
+        unavailable: &mut Vec<EventSpec>,
+    ) -> io::Result<Vec<GroupMember>> {
+        let mut opened: Vec<GroupMember> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            // The first event to open is the group parent, do not disable
+            let parent_fd = opened.first().map(|parent| parent.perf_event.fd);
+            let res = PerfEvent::open(
+                spec.attr()
+                    .with_perf_format_group()
+                    .with_perf_format_id()
+                    .with_perf_format_total_times(),
+                parent_fd,
+                pid,
+                cpu_id,
+                flags,
+            );
+            match res {
+                Ok(perf_event) => opened.push(GroupMember::new(spec.clone(), perf_event)?),
+                Err(err)
+                    if spec.optional
+                        && (is_unsupported(&err) || (parent_fd.is_some() && is_full(&err))) =>
+                {
+                    unavailable.push(spec.clone())
+                }
+                Err(err) => {
+                    match parent_fd {
+                        None => {
+                            eprintln!("Error initialising parent file descriptor: {}", spec.name)
+                        }
+                        Some(_) => eprintln!("Error initialising file descriptor: {}", spec.name),
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(opened)
+    }
+
+    /// Optional events which were requested but which the kernel
+    /// rejected as unsupported, or which did not fit in their group,
+    /// see [`EventSpec::optional`].
+    pub fn unavailable(&self) -> &[EventSpec] {
+        &self.unavailable
+    }
+
+    /// The events in this set, group by group in the order they were
+    /// opened.
+    pub fn events(&self) -> impl Iterator<Item = &EventSpec> + '_ {
+        self.members().map(|member| &member.spec)
+    }
+
+    /// The events of each group which was opened, each scheduled onto
+    /// the PMU together.
+    pub fn groups(&self) -> impl Iterator<Item = Vec<&EventSpec>> + '_ {
+        self.groups
+            .iter()
+            .map(|group| group.iter().map(|member| &member.spec).collect())
+    }
+
+    fn members(&self) -> impl Iterator<Item = &GroupMember> + '_ {
+        self.groups.iter().flatten()
+    }
+
+    /// Whether `event` is part of the set and enabled.
+    pub fn is_enabled(&self, event: impl Into<EventSpec>) -> bool {
+        let event = event.into();
+        self.members()
+            .any(|member| member.spec.same_event(&event) && member.active)
+    }
+
+    /// Enable tracking of a set of events. Takes effect immediately,
+    /// including while the groups are counting. Every member which is
+    /// the same event, see [`EventSpec::same_event`], is enabled. Group
+    /// parents are always enabled, as enabling one would start its whole
+    /// group, so they are left as they are.
     ///
     /// See [`Self::update_file_state`] for starting the actual counting.
-    pub fn enable(&mut self, events: &[EventType]) {
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventSet, EventType};
+    ///
+    /// let events = [EventType::TaskClock, EventType::PageFaults];
+    /// let mut set = EventSet::new(&events, None, Some(0)).unwrap();
+    /// set.enable(&events).unwrap();
+    /// let pages = vec![1u8; 1 << 20];
+    /// // The group itself was never started
+    /// assert_eq!(set.get_counts().unwrap().get(EventType::PageFaults), Some(0));
+    /// # drop(pages);
+    /// ```
+    pub fn enable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
         for event in events {
-            match event {
-                EventType::BranchInstructions => self.branch_instructions.enable(),
-                EventType::BranchMisses => self.branch_misses.enable(),
-                EventType::BusCycles => self.bus_cycles.enable(),
-                EventType::CacheMisses => self.cache_misses.enable(),
-                EventType::CacheReferences => self.cache_references.enable(),
-                EventType::Instructions => self.instructions.enable(),
-                EventType::RefCpuCycles => self.ref_cpu_cycles.enable(),
-                EventType::CpuCycles => self.cpu_cycles.enable(),
+            let event: EventSpec = event.clone().into();
+            let mut found = false;
+            for group in &mut self.groups {
+                for (i, member) in group.iter_mut().enumerate() {
+                    if !member.spec.same_event(&event) {
+                        continue;
+                    }
+                    found = true;
+                    if i > 0 {
+                        member.perf_event.enable()?;
+                        member.active = true;
+                    }
+                }
+            }
+            if !found && !self.is_unavailable(&event) {
+                eprintln!("Note: {} is not part of this EventSet", event.name);
             }
         }
+        Ok(())
     }
 
-    /// Disable tracking of a set of events.
+    /// Disable tracking of a set of events. Takes effect immediately,
+    /// including while the groups are counting. Disabled events keep the
+    /// count they had, but are left out of [`Self::get_counts`].
     ///
     /// See [`Self::update_file_state`] for ending the actual counting.
-    pub fn disable(&mut self, events: &[EventType]) {
+    pub fn disable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
         for event in events {
-            match event {
-                EventType::BranchInstructions => self.branch_instructions.disable(),
-                EventType::BranchMisses => self.branch_misses.disable(),
-                EventType::BusCycles => self.bus_cycles.disable(),
-                EventType::CacheMisses => self.cache_misses.disable(),
-                EventType::CacheReferences => self.cache_references.disable(),
-                EventType::Instructions => self.instructions.disable(),
-                EventType::RefCpuCycles => self.ref_cpu_cycles.disable(),
-                EventType::CpuCycles => {
-                    eprintln!("Note: CpuCycles cannot be disabled as it is the parent of the group")
+            let event: EventSpec = event.clone().into();
+            let mut found = false;
+            for group in &mut self.groups {
+                for (i, member) in group.iter_mut().enumerate() {
+                    if !member.spec.same_event(&event) {
+                        continue;
+                    }
+                    found = true;
+                    if i == 0 {
+                        eprintln!(
+                            "Note: {} cannot be disabled as it is the parent of the group",
+                            event.name
+                        );
+                    } else {
+                        member.perf_event.disable()?;
+                        member.active = false;
+                    }
                 }
             }
+            if !found && !self.is_unavailable(&event) {
+                eprintln!("Note: {} is not part of this EventSet", event.name);
+            }
         }
+        Ok(())
     }
 
-    /// Update the state of the file which collects event counts.
-    /// Use this to start and end counting by passing
+    /// Update the state of the files which collect event counts, for
+    /// every group. Use this to start and end counting by passing
     /// [`EventIOState::Enable`] and [`EventIOState::Disable`].
-    pub fn update_file_state(&self, state: EventIOState) -> io::Result<i32> {
-        let res = unsafe { ioctl(self.parent_fd, state as u64, 0) };
-        if res < 0 {
-            Err(io::Error::last_os_error())
-        } else {
-            Ok(res)
+    /// [`EventIOState::Reset`] zeroes every member, not just the parents.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventIOState, EventSet, EventType};
+    ///
+    /// let events = [EventType::TaskClock, EventType::PageFaults];
+    /// let set = EventSet::new(&events, None, Some(0)).unwrap();
+    /// set.update_file_state(EventIOState::Enable).unwrap();
+    /// let pages = vec![1u8; 1 << 20];
+    /// set.update_file_state(EventIOState::Disable).unwrap();
+    /// set.update_file_state(EventIOState::Reset).unwrap();
+    /// let counts = set.get_counts().unwrap();
+    /// assert_eq!(counts.get(EventType::TaskClock), Some(0));
+    /// assert_eq!(counts.get(EventType::PageFaults), Some(0));
+    /// # drop(pages);
+    /// ```
+    pub fn update_file_state(&self, state: EventIOState) -> io::Result<()> {
+        for group in &self.groups {
+            let parent = &group[0].perf_event;
+            match state {
+                // Members keep their own counts when only the parent is reset
+                EventIOState::Reset => parent.update_group_state(state)?,
+                // Enabling the group must not enable members disabled on their own
+                _ => parent.update_file_state(state)?,
+            };
         }
+        Ok(())
     }
 
-    /// Get the counts currently in the count file. Calling this
+    /// Get the counts currently in the count files. Calling this
     /// while counting is technically probably okay, but not advised.
+    ///
+    /// Values are matched back to their events by the id the kernel
+    /// reports alongside each value, so the layout of the group read
+    /// does not need to match the order the events were opened in.
+    /// Each event has the enabled and running times of its group.
+    /// Disabled events are left out.
     pub fn get_counts(&self) -> io::Result<EventCounts> {
-        let mut buf: [u64; SIZE_OF_EVENT_COUNTS / SIZE_OF_U64 + 1] =
-            [0; SIZE_OF_EVENT_COUNTS / SIZE_OF_U64 + 1];
-
-        let res = unsafe {
-            libc::read(
-                self.parent_fd,
-                buf.as_mut_ptr() as *mut c_void,
-                mem::size_of_val(&buf),
-            )
-        };
-        if res < 0 {
-            return Err(io::Error::last_os_error());
+        let mut counts = Vec::with_capacity(self.members().count());
+        for group in &self.groups {
+            // buf[0] holds the number of events (types), buf[1] and buf[2]
+            // the time enabled and running, followed by a (value, id) pair
+            // for each event
+            let mut buf = vec![0u64; 3 + 2 * group.len()];
+
+            let res = unsafe {
+                libc::read(
+                    group[0].perf_event.fd,
+                    buf.as_mut_ptr() as *mut c_void,
+                    mem::size_of_val(buf.as_slice()),
+                )
+            };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let num_events = (buf[0] as usize).min(group.len());
+            let values = &buf[3..3 + 2 * num_events];
+            for member in group.iter().filter(|member| member.active) {
+                let value = values
+                    .chunks_exact(2)
+                    .find(|value_and_id| value_and_id[1] == member.id)
+                    .map(|value_and_id| value_and_id[0])
+                    .ok_or_else(|| {
+                        io::Error::other(format!(
+                            "Group read is missing a value for {} (id {})",
+                            member.spec.name, member.id
+                        ))
+                    })?;
+                counts.push(EventCount {
+                    spec: member.spec.clone(),
+                    value,
+                    time_enabled: buf[1],
+                    time_running: buf[2],
+                });
+            }
         }
-        // buf[0] holds the number of events (types)
-        Ok(EventCounts {
-            num_cpu_cycles: buf[1],
-            num_instructions: buf[2],
-            num_cache_references: buf[3],
-            num_cache_misses: buf[4],
-            num_branch_instructions: buf[5],
-            num_branch_misses: buf[6],
-            num_bus_cycles: buf[7],
-            // num_stalled_cycles_frontend: buf[],
-            // num_stalled_cycles_backend: buf[],
-            num_ref_cpu_cycles: buf[8],
-        })
+        Ok(EventCounts { counts })
+    }
+
+    fn is_unavailable(&self, event: &EventSpec) -> bool {
+        self.unavailable.iter().any(|spec| spec.same_event(event))
     }
 }
+
+/// Whether opening an event failed because the CPU or kernel does not
+/// support it, rather than e.g. permissions.
+fn is_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOENT) | Some(libc::EOPNOTSUPP)
+    )
+}
+
+/// Whether adding a member to a group failed because the group no longer
+/// fits in the counters of the PMU, which the kernel reports as `EINVAL`
+/// when it validates the group.
+fn is_full(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EINVAL)
+}
@@ -1,4 +1,4 @@
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PerfEventFlags(u64);
 
 #[allow(dead_code)]
@@ -100,3 +100,134 @@ pub enum PreciseIp {
     RequestZeroSkid = 2,
     ForceZeroSkid = 3,
 }
+
+/// The fields included in each sample, set in `sample_type` of
+/// [`super::PerfEventAttr`]. Fields appear in samples in the order of
+/// their bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SampleType(u64);
+
+#[allow(dead_code)]
+impl SampleType {
+    pub const IP: Self = Self(1 << 0);
+    pub const TID: Self = Self(1 << 1);
+    pub const TIME: Self = Self(1 << 2);
+    pub const ADDR: Self = Self(1 << 3);
+    pub const READ: Self = Self(1 << 4);
+    pub const CALLCHAIN: Self = Self(1 << 5);
+    pub const ID: Self = Self(1 << 6);
+    pub const CPU: Self = Self(1 << 7);
+    pub const PERIOD: Self = Self(1 << 8);
+    pub const STREAM_ID: Self = Self(1 << 9);
+    pub const RAW: Self = Self(1 << 10);
+    pub const BRANCH_STACK: Self = Self(1 << 11);
+    pub const REGS_USER: Self = Self(1 << 12);
+    pub const STACK_USER: Self = Self(1 << 13);
+    pub const WEIGHT: Self = Self(1 << 14);
+    pub const DATA_SRC: Self = Self(1 << 15);
+    pub const IDENTIFIER: Self = Self(1 << 16);
+    pub const TRANSACTION: Self = Self(1 << 17);
+    pub const REGS_INTR: Self = Self(1 << 18);
+    pub const PHYS_ADDR: Self = Self(1 << 19);
+    pub const AUX: Self = Self(1 << 20);
+    pub const CGROUP: Self = Self(1 << 21);
+    pub const DATA_PAGE_SIZE: Self = Self(1 << 22);
+    pub const CODE_PAGE_SIZE: Self = Self(1 << 23);
+    pub const WEIGHT_STRUCT: Self = Self(1 << 24);
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl std::ops::BitOr for SampleType {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for SampleType {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+impl std::ops::BitAnd for SampleType {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// Which branches to record in [`SampleType::BRANCH_STACK`], set in
+/// `branch_sample_type` of [`super::PerfEventAttr`]. Without any of
+/// `USER`, `KERNEL` or `HV` the privilege levels of the event are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BranchSampleType(u64);
+
+#[allow(dead_code)]
+impl BranchSampleType {
+    pub const USER: Self = Self(1 << 0); /* user branches */
+    pub const KERNEL: Self = Self(1 << 1); /* kernel branches */
+    pub const HV: Self = Self(1 << 2); /* hypervisor branches */
+    pub const ANY: Self = Self(1 << 3); /* any branch types */
+    pub const ANY_CALL: Self = Self(1 << 4); /* any call branch */
+    pub const ANY_RETURN: Self = Self(1 << 5); /* any return branch */
+    pub const IND_CALL: Self = Self(1 << 6); /* indirect calls */
+    pub const ABORT_TX: Self = Self(1 << 7); /* transaction aborts */
+    pub const IN_TX: Self = Self(1 << 8); /* in transaction */
+    pub const NO_TX: Self = Self(1 << 9); /* not in transaction */
+    pub const COND: Self = Self(1 << 10); /* conditional branches */
+    pub const CALL_STACK: Self = Self(1 << 11); /* call/ret stack */
+    pub const IND_JUMP: Self = Self(1 << 12); /* indirect jumps */
+    pub const CALL: Self = Self(1 << 13); /* direct call */
+    pub const NO_FLAGS: Self = Self(1 << 14); /* no flags */
+    pub const NO_CYCLES: Self = Self(1 << 15); /* no cycles */
+    pub const TYPE_SAVE: Self = Self(1 << 16); /* save branch type */
+    pub const HW_INDEX: Self = Self(1 << 17); /* save low level index of raw branch records */
+    pub const PRIV_SAVE: Self = Self(1 << 18); /* save privilege mode */
+    pub const COUNTERS: Self = Self(1 << 19); /* save occurrences of events on a branch */
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl std::ops::BitOr for BranchSampleType {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for BranchSampleType {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+impl std::ops::BitAnd for BranchSampleType {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
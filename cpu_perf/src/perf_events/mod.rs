@@ -1,6 +1,38 @@
+mod branch_stack;
+mod cache_event;
+mod callchain;
+mod cgroup;
+mod command;
 mod event_set;
 pub mod flags;
+mod overflow;
+mod parse;
 mod perf_event;
+mod pmu;
+mod process_event_set;
+mod raw_event;
+mod record;
+mod ring_buffer;
+mod sampling;
+mod system_event_set;
+mod task_event_set;
+mod user_counter;
 
+pub use branch_stack::*;
+pub use cache_event::*;
+pub use callchain::*;
+pub use cgroup::*;
+pub use command::*;
 pub use event_set::*;
+pub use overflow::*;
+pub use parse::*;
 pub use perf_event::*;
+pub use pmu::*;
+pub use process_event_set::*;
+pub use raw_event::*;
+pub use record::*;
+pub use ring_buffer::*;
+pub use sampling::*;
+pub use system_event_set::*;
+pub use task_event_set::*;
+pub use user_counter::*;
@@ -0,0 +1,255 @@
+use std::{
+    io,
+    os::unix::thread::JoinHandleExt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{EventSpec, Record, Sample, SampleRate, Sampler, SamplingConfig};
+
+/// Notices each time an event counts another `threshold` occurrences,
+/// such as every million cache misses, without polling its count.
+///
+/// The event samples every `threshold` occurrences and wakes readers
+/// on every sample, so an overflow is the [`Sample`] taken at it, with
+/// the thread, CPU and instruction it happened at. Overflows are
+/// delivered either on the calling thread by [`Self::wait`], through
+/// `poll` readiness of the events, or to a callback on a thread of
+/// their own by [`Self::spawn`], through signals.
+///
+/// # Example
+///
+/// Noticing every millisecond of CPU time this thread uses:
+///
+/// ```
+/// use std::time::Duration;
+/// use cpu_perf::perf_events::{EventSpec, EventType, OverflowWatcher};
+///
+/// let tid = unsafe { libc::gettid() } as u32;
+/// let spec = EventSpec::new(EventType::TaskClock);
+/// let mut watcher = OverflowWatcher::for_threads(spec, 1_000_000, &[tid]).unwrap();
+/// watcher.enable().unwrap();
+///
+/// let mut overflows = 0;
+/// while overflows < 3 {
+///     // Use some CPU time
+///     (0..100_000u64).map(std::hint::black_box).sum::<u64>();
+///     watcher
+///         .wait(Some(Duration::ZERO), |sample| {
+///             assert_eq!(sample.tid, Some(tid));
+///             overflows += 1;
+///         })
+///         .unwrap();
+/// }
+/// ```
+pub struct OverflowWatcher {
+    sampler: Sampler,
+    threshold: u64,
+}
+
+impl OverflowWatcher {
+    /// Pages of each ring buffer, enough for many overflows between
+    /// reads.
+    const DATA_PAGES: usize = 2;
+
+    fn config(spec: EventSpec, threshold: u64) -> SamplingConfig {
+        SamplingConfig::new(spec)
+            .with_rate(SampleRate::Period(threshold))
+            .with_tracking(false)
+            .with_data_pages(Self::DATA_PAGES)
+            .with_wakeup_events(1)
+    }
+
+    /// Watch every process on every online CPU.
+    pub fn system_wide(spec: EventSpec, threshold: u64) -> io::Result<Self> {
+        Ok(Self {
+            sampler: Sampler::system_wide(Self::config(spec, threshold))?,
+            threshold,
+        })
+    }
+
+    /// Watch the thread `pid` wherever it runs, with an event per CPU.
+    /// See [`Sampler::for_process`].
+    pub fn for_process(spec: EventSpec, threshold: u64, pid: u32) -> io::Result<Self> {
+        Ok(Self {
+            sampler: Sampler::for_process(Self::config(spec, threshold), pid)?,
+            threshold,
+        })
+    }
+
+    /// Watch each of the existing threads `tids` wherever they run,
+    /// counting each thread separately.
+    pub fn for_threads(spec: EventSpec, threshold: u64, tids: &[u32]) -> io::Result<Self> {
+        Ok(Self {
+            sampler: Sampler::for_threads(Self::config(spec, threshold), tids)?,
+            threshold,
+        })
+    }
+
+    /// The occurrences counted between overflows.
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Start counting, with an overflow every `threshold` occurrences
+    /// until [`Self::disable`].
+    pub fn enable(&self) -> io::Result<()> {
+        self.sampler.enable()
+    }
+
+    /// Start counting for `overflows` more overflows of each event, as
+    /// for a single alert, after which the events disable themselves
+    /// until armed again. Fails for events with
+    /// [`super::flags::PerfEventFlags::INHERIT`].
+    pub fn arm(&self, overflows: u32) -> io::Result<()> {
+        self.sampler.refresh(overflows)
+    }
+
+    /// Stop counting. Overflows already taken can still be read.
+    pub fn disable(&self) -> io::Result<()> {
+        self.sampler.disable()
+    }
+
+    /// Wait until there are overflows or `timeout` passes, then call
+    /// `f` with each overflow since the last call. Returns the number
+    /// of overflows.
+    pub fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        mut f: impl FnMut(&Sample),
+    ) -> io::Result<usize> {
+        self.sampler.poll(timeout)?;
+        self.read(&mut f)
+    }
+
+    /// Call `f` with each overflow on a new thread, until the returned
+    /// [`OverflowThread`] is stopped or dropped. The events send the
+    /// thread `signal`, such as `libc::SIGRTMIN()`, which is blocked on
+    /// the thread only, so needs no handler. Counting is left as it is,
+    /// so enable or arm the watcher first.
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use cpu_perf::perf_events::{EventSpec, EventType, OverflowWatcher};
+    ///
+    /// let tid = unsafe { libc::gettid() } as u32;
+    /// let spec = EventSpec::new(EventType::TaskClock);
+    /// let watcher = OverflowWatcher::for_threads(spec, 1_000_000, &[tid]).unwrap();
+    /// // A single alert after 2ms of CPU time
+    /// watcher.arm(2).unwrap();
+    ///
+    /// let (alerts, alerted) = mpsc::channel();
+    /// let thread = watcher
+    ///     .spawn(libc::SIGRTMIN(), move |sample| alerts.send(sample.tid).unwrap())
+    ///     .unwrap();
+    /// while alerted.try_recv().is_err() {
+    ///     (0..100_000u64).map(std::hint::black_box).sum::<u64>();
+    /// }
+    /// thread.stop().unwrap();
+    /// ```
+    pub fn spawn(
+        mut self,
+        signal: i32,
+        mut f: impl FnMut(&Sample) + Send + 'static,
+    ) -> io::Result<OverflowThread> {
+        // Overflows missed between signals are still read in time
+        const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (started_tx, started_rx) = mpsc::channel();
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+                let started = unsafe {
+                    libc::sigemptyset(&mut signals);
+                    libc::sigaddset(&mut signals, signal);
+                    match libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) {
+                        0 => self.sampler.notify_by_signal(signal, libc::gettid() as u32),
+                        err => Err(io::Error::from_raw_os_error(err)),
+                    }
+                };
+                let failed = started.is_err();
+                let _ = started_tx.send(started);
+                if failed {
+                    return Ok(());
+                }
+                let timeout = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: WAIT_INTERVAL.as_nanos() as libc::c_long,
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    let res =
+                        unsafe { libc::sigtimedwait(&signals, std::ptr::null_mut(), &timeout) };
+                    if res < 0 {
+                        let err = io::Error::last_os_error();
+                        if !matches!(err.raw_os_error(), Some(libc::EAGAIN | libc::EINTR)) {
+                            return Err(err);
+                        }
+                    }
+                    self.read(&mut f)?;
+                }
+                Ok(())
+            })
+        };
+        match started_rx.recv() {
+            Ok(Ok(())) => Ok(OverflowThread {
+                stop,
+                signal,
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(io::Error::other("Overflow thread exited before starting")),
+        }
+    }
+
+    fn read(&mut self, f: &mut impl FnMut(&Sample)) -> io::Result<usize> {
+        let mut overflows = 0;
+        for record in self.sampler.read_records().map_err(io::Error::other)? {
+            if let Record::Sample(sample) = record {
+                f(&sample);
+                overflows += 1;
+            }
+        }
+        Ok(overflows)
+    }
+}
+
+/// The thread started by [`OverflowWatcher::spawn`], stopped and its
+/// events closed when dropped.
+pub struct OverflowThread {
+    stop: Arc<AtomicBool>,
+    signal: i32,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl OverflowThread {
+    /// Stop the thread, returning any error which stopped it early.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = &self.thread {
+            // Wake it rather than wait out its interval
+            unsafe { libc::pthread_kill(thread.as_pthread_t(), self.signal) };
+        }
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("Overflow callback panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for OverflowThread {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
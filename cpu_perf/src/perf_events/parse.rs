@@ -0,0 +1,506 @@
+use std::{fmt, io};
+
+use super::{
+    CacheEvent, CacheId, CacheOp, CacheResult, EventType, PERF_TYPE_HARDWARE, PERF_TYPE_HW_CACHE,
+    PERF_TYPE_RAW, PerfEventAttr, PmuError, Pmus, RawEvent,
+    flags::{BranchSampleType, PerfEventFlags, PreciseIp},
+};
+
+/// An event along with the flags to open it with, as produced by
+/// [`EventParser`] and consumed by [`super::EventSet::from_specs`].
+///
+/// Events are told apart by their event and flags, so
+/// `page-faults:u` and `page-faults:k` are counted and reported
+/// separately, while the name is only used for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventSpec {
+    /// How the event is labelled in output. The event as written for
+    /// parsed events, such as `page-faults:u`, and the `perf` name of
+    /// the [`EventType`] otherwise, as given by [`event_name`].
+    pub name: String,
+    pub event: EventType,
+    pub flags: PerfEventFlags,
+    /// Whether an [`super::EventSet`] should carry on without this event
+    /// if the kernel reports it as unsupported.
+    pub optional: bool,
+    /// The PMU to open hardware events on, needed on hybrid CPUs. See
+    /// [`PerfEventAttr::with_pmu_type`].
+    pub pmu_type: Option<u32>,
+}
+
+impl EventSpec {
+    /// The event with the default flags of [`super::EventSet::new`],
+    /// excluding the hypervisor.
+    pub fn new(event: EventType) -> Self {
+        Self {
+            name: event_name(event),
+            event,
+            flags: PerfEventFlags::EXCLUDE_HV,
+            optional: false,
+            pmu_type: None,
+        }
+    }
+
+    pub fn with_flags(self, flags: PerfEventFlags) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Label the event `name` in output.
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..self
+        }
+    }
+
+    /// Whether `other` is the same event, whatever it is named, whichever
+    /// PMU it was routed to and whether it was optional. Counts of the
+    /// same event are added together, e.g. over every CPU.
+    pub fn same_event(&self, other: &EventSpec) -> bool {
+        self.event == other.event && self.flags == other.flags
+    }
+
+    /// Mark the event as optional, so an unsupported event, or one which
+    /// does not fit in the counters left by the rest of its group, is
+    /// reported by [`super::EventSet::unavailable`] rather than failing
+    /// to open the set. Useful for events such as
+    /// [`EventType::StalledCyclesFrontend`] which many CPUs lack.
+    pub fn optional(self) -> Self {
+        Self {
+            optional: true,
+            ..self
+        }
+    }
+
+    /// Open hardware events on the PMU with type `pmu_type`.
+    pub fn on_pmu(self, pmu_type: u32) -> Self {
+        Self {
+            pmu_type: Some(pmu_type),
+            ..self
+        }
+    }
+
+    /// Whether this is a generic hardware or cache event not yet routed
+    /// to a PMU, which on hybrid CPUs only counts on one kind of core
+    /// unless opened on each core PMU.
+    pub fn is_generic_hardware(&self) -> bool {
+        self.pmu_type.is_none()
+            && matches!(self.event.type_(), PERF_TYPE_HARDWARE | PERF_TYPE_HW_CACHE)
+    }
+
+    /// The attributes to open this event with.
+    pub fn attr(&self) -> PerfEventAttr {
+        let attr = PerfEventAttr::new(self.event).with_flags(self.flags);
+        match self.pmu_type {
+            Some(pmu_type) => attr.with_pmu_type(pmu_type),
+            None => attr,
+        }
+    }
+}
+
+impl From<EventType> for EventSpec {
+    fn from(event: EventType) -> Self {
+        Self::new(event)
+    }
+}
+
+/// Parses events written the way `perf` accepts them on the command
+/// line.
+///
+/// Supported are
+///
+/// - Named hardware and software events, e.g. `cycles`, `page-faults`.
+/// - Generic cache events, e.g. `L1-dcache-load-misses`, `dTLB-loads`.
+/// - Raw codes, e.g. `r01c2`.
+/// - PMU events, e.g. `cpu/event=0x3c/`, when constructed with the
+///   [`Pmus`] to resolve them against.
+/// - Modifiers `:u`, `:k` and `:h` to count only user, kernel or
+///   hypervisor, and `:p`, `:pp`, `:ppp` for precise instruction
+///   pointers. PMU events may also take them after the slash, `cpu/.../u`.
+/// - Groups, `{cycles,instructions}:u`, where the modifiers apply to
+///   every member.
+///
+/// Events without `:u`, `:k` or `:h` exclude the hypervisor, as
+/// [`super::EventSet::new`] does.
+///
+/// # Example
+///
+/// ```
+/// use cpu_perf::perf_events::{EventParser, EventType, flags::PerfEventFlags};
+///
+/// let parser = EventParser::default();
+///
+/// let spec = parser.parse_event("instructions:u").unwrap();
+/// assert_eq!(spec.event, EventType::Instructions);
+/// assert_eq!(spec.name, "instructions:u");
+/// assert!(spec.flags.contains(PerfEventFlags::EXCLUDE_KERNEL | PerfEventFlags::EXCLUDE_HV));
+///
+/// let groups = parser.parse_list("{cycles,instructions}:k,page-faults").unwrap();
+/// assert_eq!(groups.len(), 2);
+/// assert_eq!(groups[0].len(), 2);
+/// assert_eq!(groups[0][1].name, "instructions:k");
+///
+/// assert!(parser.parse_event("not-an-event").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EventParser {
+    pmus: Pmus,
+}
+
+impl EventParser {
+    /// A parser resolving `pmu/.../` events against `pmus`.
+    pub fn new(pmus: Pmus) -> Self {
+        Self { pmus }
+    }
+
+    /// A parser resolving `pmu/.../` events against the PMUs of the
+    /// running kernel.
+    pub fn discover() -> io::Result<Self> {
+        Ok(Self::new(Pmus::discover()?))
+    }
+
+    /// Parse a comma separated list of events and `{...}` groups. Each
+    /// event outside of braces is its own group.
+    pub fn parse_list(&self, list: &str) -> Result<Vec<Vec<EventSpec>>, EventParseError> {
+        let mut groups = Vec::new();
+        for item in split_top_level(list)? {
+            if let Some(group) = item.strip_prefix('{') {
+                let (members, modifiers) = group
+                    .split_once('}')
+                    .ok_or_else(|| EventParseError::Malformed(item.to_string()))?;
+                let modifiers = match modifiers {
+                    "" => None,
+                    modifiers => Some(
+                        modifiers
+                            .strip_prefix(':')
+                            .ok_or_else(|| EventParseError::Malformed(item.to_string()))?,
+                    ),
+                };
+                let mut specs = Vec::new();
+                for member in split_top_level(members)? {
+                    let mut spec = self.parse_event(member)?;
+                    if let Some(modifiers) = modifiers {
+                        spec.flags = apply_modifiers(spec.flags, modifiers)
+                            .ok_or_else(|| EventParseError::UnknownModifier(item.to_string()))?;
+                        // Labelled as perf does, cs:k for {cs,faults}:k
+                        if !spec.name.contains(['/', ':']) {
+                            spec.name.push(':');
+                        }
+                        spec.name.push_str(modifiers);
+                    }
+                    specs.push(spec);
+                }
+                if specs.is_empty() {
+                    return Err(EventParseError::Empty);
+                }
+                groups.push(specs);
+            } else {
+                groups.push(vec![self.parse_event(item)?]);
+            }
+        }
+        if groups.is_empty() {
+            return Err(EventParseError::Empty);
+        }
+        Ok(groups)
+    }
+
+    /// Parse a single event with optional modifiers.
+    pub fn parse_event(&self, event: &str) -> Result<EventSpec, EventParseError> {
+        let event = event.trim();
+        if event.is_empty() {
+            return Err(EventParseError::Empty);
+        }
+
+        let (event_type, modifiers) = if event.contains('/') {
+            // pmu/terms/ followed by modifiers, optionally after a colon
+            let end = event
+                .rfind('/')
+                .filter(|end| Some(*end) != event.find('/'))
+                .ok_or_else(|| EventParseError::Malformed(event.to_string()))?;
+            let modifiers = &event[end + 1..];
+            let modifiers = modifiers.strip_prefix(':').unwrap_or(modifiers);
+            (
+                EventType::Raw(self.pmus.parse_event(&event[..=end])?),
+                modifiers,
+            )
+        } else {
+            let (name, modifiers) = event.split_once(':').unwrap_or((event, ""));
+            let event_type = event_type_from_name(name)
+                .ok_or_else(|| EventParseError::UnknownEvent(name.to_string()))?;
+            (event_type, modifiers)
+        };
+
+        let flags = apply_modifiers(PerfEventFlags::EXCLUDE_HV, modifiers)
+            .ok_or_else(|| EventParseError::UnknownModifier(event.to_string()))?;
+        Ok(EventSpec::new(event_type)
+            .with_flags(flags)
+            .with_name(event))
+    }
+}
+
+/// The [`EventType`] `perf` calls `name`, including generic cache
+/// events and raw codes.
+pub fn event_type_from_name(name: &str) -> Option<EventType> {
+    let event = match name {
+        "cycles" | "cpu-cycles" => EventType::CpuCycles,
+        "instructions" => EventType::Instructions,
+        "cache-references" => EventType::CacheReferences,
+        "cache-misses" => EventType::CacheMisses,
+        "branches" | "branch-instructions" => EventType::BranchInstructions,
+        "branch-misses" => EventType::BranchMisses,
+        "bus-cycles" => EventType::BusCycles,
+        "stalled-cycles-frontend" | "idle-cycles-frontend" => EventType::StalledCyclesFrontend,
+        "stalled-cycles-backend" | "idle-cycles-backend" => EventType::StalledCyclesBackend,
+        "ref-cycles" => EventType::RefCpuCycles,
+        "cpu-clock" => EventType::CpuClock,
+        "task-clock" => EventType::TaskClock,
+        "page-faults" | "faults" => EventType::PageFaults,
+        "minor-faults" => EventType::MinorPageFaults,
+        "major-faults" => EventType::MajorPageFaults,
+        "context-switches" | "cs" => EventType::ContextSwitches,
+        "cpu-migrations" | "migrations" => EventType::CpuMigrations,
+        "alignment-faults" => EventType::AlignmentFaults,
+        "emulation-faults" => EventType::EmulationFaults,
+        _ => {
+            return cache_event_from_name(name)
+                .map(EventType::HwCache)
+                .or_else(|| RawEvent::from_code(name).map(EventType::Raw));
+        }
+    };
+    Some(event)
+}
+
+/// The name `perf` gives `event`, such as `cycles` or
+/// `L1-dcache-load-misses`, the reverse of [`event_type_from_name`].
+/// Raw events on the core PMU are named by their code.
+///
+/// ```
+/// use cpu_perf::perf_events::{EventType, event_name, event_type_from_name};
+///
+/// assert_eq!(event_name(EventType::TaskClock), "task-clock");
+/// for name in ["cycles", "branches", "dTLB-load-misses", "r1c2"] {
+///     assert_eq!(event_name(event_type_from_name(name).unwrap()), name);
+/// }
+/// ```
+pub fn event_name(event: EventType) -> String {
+    let name = match event {
+        EventType::CpuCycles => "cycles",
+        EventType::Instructions => "instructions",
+        EventType::CacheReferences => "cache-references",
+        EventType::CacheMisses => "cache-misses",
+        EventType::BranchInstructions => "branches",
+        EventType::BranchMisses => "branch-misses",
+        EventType::BusCycles => "bus-cycles",
+        EventType::StalledCyclesFrontend => "stalled-cycles-frontend",
+        EventType::StalledCyclesBackend => "stalled-cycles-backend",
+        EventType::RefCpuCycles => "ref-cycles",
+        EventType::CpuClock => "cpu-clock",
+        EventType::TaskClock => "task-clock",
+        EventType::PageFaults => "page-faults",
+        EventType::MinorPageFaults => "minor-faults",
+        EventType::MajorPageFaults => "major-faults",
+        EventType::ContextSwitches => "context-switches",
+        EventType::CpuMigrations => "cpu-migrations",
+        EventType::AlignmentFaults => "alignment-faults",
+        EventType::EmulationFaults => "emulation-faults",
+        EventType::HwCache(cache_event) => {
+            let cache = CACHES.iter().find(|(_, id)| *id == cache_event.cache);
+            let op = OPS.iter().find(|(_, _, op)| *op == cache_event.op);
+            return match (cache, op, cache_event.result) {
+                (Some((cache, _)), Some((access, _, _)), CacheResult::Access) => {
+                    format!("{}-{}", cache, access)
+                }
+                (Some((cache, _)), Some((_, miss, _)), CacheResult::Miss) => {
+                    format!("{}-{}", cache, miss)
+                }
+                _ => format!("{:?}", cache_event),
+            };
+        }
+        EventType::Raw(raw) => {
+            return match raw {
+                RawEvent {
+                    type_: PERF_TYPE_RAW,
+                    config1: 0,
+                    config2: 0,
+                    ..
+                } => format!("r{:x}", raw.config),
+                _ => format!("{:?}", raw),
+            };
+        }
+    };
+    name.to_string()
+}
+
+/// The branches to record for a comma separated list of filters as
+/// taken by `perf record -j`, such as `any,u` or `cond`. `None` if a
+/// filter is unknown.
+///
+/// ```
+/// use cpu_perf::perf_events::{branch_filter_from_names, flags::BranchSampleType};
+///
+/// assert_eq!(
+///     branch_filter_from_names("any_call,u"),
+///     Some(BranchSampleType::ANY_CALL | BranchSampleType::USER)
+/// );
+/// assert_eq!(branch_filter_from_names("sideways"), None);
+/// ```
+pub fn branch_filter_from_names(filters: &str) -> Option<BranchSampleType> {
+    let mut branch_sample_type = BranchSampleType::default();
+    for filter in filters.split(',') {
+        branch_sample_type |= match filter.trim() {
+            "u" => BranchSampleType::USER,
+            "k" => BranchSampleType::KERNEL,
+            "hv" => BranchSampleType::HV,
+            "any" => BranchSampleType::ANY,
+            "any_call" => BranchSampleType::ANY_CALL,
+            "any_ret" => BranchSampleType::ANY_RETURN,
+            "ind_call" => BranchSampleType::IND_CALL,
+            "ind_jmp" => BranchSampleType::IND_JUMP,
+            "call" => BranchSampleType::CALL,
+            "cond" => BranchSampleType::COND,
+            "call_stack" => BranchSampleType::CALL_STACK,
+            "abort_tx" => BranchSampleType::ABORT_TX,
+            "in_tx" => BranchSampleType::IN_TX,
+            "no_tx" => BranchSampleType::NO_TX,
+            "save_type" => BranchSampleType::TYPE_SAVE,
+            "priv" => BranchSampleType::PRIV_SAVE,
+            _ => return None,
+        };
+    }
+    Some(branch_sample_type)
+}
+
+/// The `perf` names of each cache, as in `L1-dcache-load-misses`.
+const CACHES: [(&str, CacheId); 7] = [
+    ("L1-dcache", CacheId::L1D),
+    ("L1-icache", CacheId::L1I),
+    ("LLC", CacheId::LL),
+    ("dTLB", CacheId::DTLB),
+    ("iTLB", CacheId::ITLB),
+    ("branch", CacheId::BPU),
+    ("node", CacheId::NODE),
+];
+
+/// The `perf` names of accesses and misses of each operation.
+const OPS: [(&str, &str, CacheOp); 3] = [
+    ("loads", "load-misses", CacheOp::Read),
+    ("stores", "store-misses", CacheOp::Write),
+    ("prefetches", "prefetch-misses", CacheOp::Prefetch),
+];
+
+/// Parse `perf` generic cache event names such as
+/// `L1-dcache-load-misses`.
+fn cache_event_from_name(name: &str) -> Option<CacheEvent> {
+    let (cache, rest) = CACHES.iter().find_map(|(prefix, cache)| {
+        name.strip_prefix(prefix)?
+            .strip_prefix('-')
+            .map(|rest| (*cache, rest))
+    })?;
+    OPS.iter().find_map(|(access, miss, op)| {
+        if rest == *access {
+            Some(CacheEvent::new(cache, *op, CacheResult::Access))
+        } else if rest == *miss {
+            Some(CacheEvent::new(cache, *op, CacheResult::Miss))
+        } else {
+            None
+        }
+    })
+}
+
+/// Apply `perf` modifiers to `flags`. `None` for unknown modifiers.
+fn apply_modifiers(flags: PerfEventFlags, modifiers: &str) -> Option<PerfEventFlags> {
+    let (mut user, mut kernel, mut hypervisor, mut precise) = (false, false, false, 0);
+    for modifier in modifiers.chars() {
+        match modifier {
+            'u' => user = true,
+            'k' => kernel = true,
+            'h' => hypervisor = true,
+            'p' => precise += 1,
+            _ => return None,
+        }
+    }
+
+    let mut flags = flags;
+    if user || kernel || hypervisor {
+        let exclude_all = PerfEventFlags::EXCLUDE_USER
+            | PerfEventFlags::EXCLUDE_KERNEL
+            | PerfEventFlags::EXCLUDE_HV;
+        flags &= !exclude_all;
+        if !user {
+            flags |= PerfEventFlags::EXCLUDE_USER;
+        }
+        if !kernel {
+            flags |= PerfEventFlags::EXCLUDE_KERNEL;
+        }
+        if !hypervisor {
+            flags |= PerfEventFlags::EXCLUDE_HV;
+        }
+    }
+    let precise = match precise {
+        0 => return Some(flags),
+        1 => PreciseIp::Constant,
+        2 => PreciseIp::RequestZeroSkid,
+        3 => PreciseIp::ForceZeroSkid,
+        _ => return None,
+    };
+    Some(flags.with_precise_ip(precise))
+}
+
+/// Split on commas which are not inside `{...}` or `pmu/.../`.
+fn split_top_level(list: &str) -> Result<Vec<&str>, EventParseError> {
+    let mut items = Vec::new();
+    let (mut depth, mut in_pmu, mut start) = (0usize, false, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| EventParseError::Malformed(list.to_string()))?
+            }
+            '/' => in_pmu = !in_pmu,
+            ',' if depth == 0 && !in_pmu => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 || in_pmu {
+        return Err(EventParseError::Malformed(list.to_string()));
+    }
+    items.push(list[start..].trim());
+    Ok(items.into_iter().filter(|item| !item.is_empty()).collect())
+}
+
+/// Errors parsing event strings.
+#[derive(Debug)]
+pub enum EventParseError {
+    Empty,
+    /// Not a known event name, cache event or raw code.
+    UnknownEvent(String),
+    UnknownModifier(String),
+    /// Unbalanced or nested braces, or unbalanced slashes.
+    Malformed(String),
+    Pmu(PmuError),
+}
+
+impl From<PmuError> for EventParseError {
+    fn from(err: PmuError) -> Self {
+        Self::Pmu(err)
+    }
+}
+
+impl std::error::Error for EventParseError {}
+
+impl fmt::Display for EventParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "No events given"),
+            Self::UnknownEvent(name) => write!(f, "Unknown event {}", name),
+            Self::UnknownModifier(event) => write!(f, "Unknown modifier in {}", event),
+            Self::Malformed(event) => write!(f, "Malformed event {}", event),
+            Self::Pmu(err) => err.fmt(f),
+        }
+    }
+}
@@ -1,15 +1,31 @@
 use std::io;
-use std::os::unix::io::RawFd;
+use std::{ffi::CString, os::unix::io::RawFd};
 
-use libc::{_IO, Ioctl, SYS_perf_event_open, ioctl, pid_t, read, syscall};
+use libc::{_IO, _IOR, _IOW, Ioctl, SYS_perf_event_open, c_ulong, ioctl, pid_t, read, syscall};
 
-use crate::perf_events::flags::PerfEventFlags;
+use crate::perf_events::{
+    CacheEvent, RawEvent,
+    flags::{BranchSampleType, PerfEventFlags, SampleType},
+};
 
 pub const PERF_EVENT_IOC_ENABLE: Ioctl = _IO(b'$' as u32, 0);
 pub const PERF_EVENT_IOC_DISABLE: Ioctl = _IO(b'$' as u32, 1);
 pub const PERF_EVENT_IOC_REFRESH: Ioctl = _IO(b'$' as u32, 2);
 pub const PERF_EVENT_IOC_RESET: Ioctl = _IO(b'$' as u32, 3);
+pub const PERF_EVENT_IOC_PERIOD: Ioctl = _IOW::<u64>(b'$' as u32, 4);
+pub const PERF_EVENT_IOC_SET_OUTPUT: Ioctl = _IO(b'$' as u32, 5);
+pub const PERF_EVENT_IOC_SET_FILTER: Ioctl = _IOW::<*mut libc::c_char>(b'$' as u32, 6);
+pub const PERF_EVENT_IOC_ID: Ioctl = _IOR::<*mut u64>(b'$' as u32, 7);
+pub const PERF_EVENT_IOC_SET_BPF: Ioctl = _IOW::<u32>(b'$' as u32, 8);
+pub const PERF_EVENT_IOC_PAUSE_OUTPUT: Ioctl = _IOW::<u32>(b'$' as u32, 9);
+pub const PERF_EVENT_IOC_MODIFY_ATTRIBUTES: Ioctl = _IOW::<*mut PerfEventAttr>(b'$' as u32, 11);
 
+/// Argument to the enable, disable, refresh and reset ioctls to apply
+/// them to every event in the group of the event rather than just the
+/// event.
+pub const PERF_IOC_FLAG_GROUP: c_ulong = 1;
+
+#[derive(Debug, Clone, Copy)]
 #[repr(u64)]
 pub enum EventIOState {
     Enable = PERF_EVENT_IOC_ENABLE,
@@ -18,22 +34,210 @@ pub enum EventIOState {
     Reset = PERF_EVENT_IOC_RESET,
 }
 
+/// The events which can be counted, along with the `type_` and `config`
+/// which identify them to `perf_event_open`.
+///
+/// Hardware events come from the PMU and are unavailable in most
+/// virtual machines and containers. Software events are counted by the
+/// kernel and work anywhere. The two can be mixed within a group.
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EventType {
-    CpuCycles = 0,
-    Instructions = 1,
-    CacheReferences = 2,
-    CacheMisses = 3,
-    BranchInstructions = 4,
-    BranchMisses = 5,
-    BusCycles = 6,
-    // StalledCyclesFrontend = 7,
-    // StalledCyclesBackend = 8,
-    RefCpuCycles = 9,
+    /// Total CPU cycles. Be wary of what happens as the CPU frequency
+    /// scales.
+    CpuCycles,
+    /// Retired instructions. Can be affected by various issues, most
+    /// notable hardware interrupt counts.
+    Instructions,
+    /// Cache accesses, generally Last Level Cache but may vary
+    /// depending on your CPU. May also include prefetches and coherency
+    /// messages, again CPU dependent.
+    CacheReferences,
+    /// Cache misses, as with `CacheReferences`, generally Last
+    /// Level Cache, but CPU dependent. Intended for calculating miss
+    /// rate with `CacheReferences`.
+    CacheMisses,
+    /// Retired branch instruction.
+    BranchInstructions,
+    /// Mispredicted branch instructions.
+    BranchMisses,
+    /// Bus cycles, not to be confused with total cycles.
+    BusCycles,
+    /// Cycles where the frontend issued no instructions, e.g. due to
+    /// instruction cache misses. Unsupported on many CPUs, consider
+    /// [`super::EventSpec::optional`].
+    StalledCyclesFrontend,
+    /// Cycles where the backend retired no instructions, e.g. waiting
+    /// on data. Unsupported on many CPUs, consider
+    /// [`super::EventSpec::optional`].
+    StalledCyclesBackend,
+    /// Total cycles, not influenced by CPU frequency scaling.
+    RefCpuCycles,
+    /// Nanoseconds of CPU time according to the per-CPU high resolution
+    /// timer.
+    CpuClock,
+    /// Nanoseconds of CPU time according to the clock specific to the
+    /// task being measured.
+    TaskClock,
+    /// Page faults, both minor and major.
+    PageFaults,
+    /// Page faults which did not require disk I/O.
+    MinorPageFaults,
+    /// Page faults which required disk I/O.
+    MajorPageFaults,
+    /// Context switches.
+    ContextSwitches,
+    /// Migrations of the task to a different CPU.
+    CpuMigrations,
+    /// Unaligned accesses fixed up by the kernel. Zero on x86.
+    AlignmentFaults,
+    /// Unimplemented instructions emulated by the kernel. Zero on x86.
+    EmulationFaults,
+    /// A generic hardware cache event such as L1D read misses. See
+    /// [`CacheEvent`].
+    HwCache(CacheEvent),
+    /// A model specific event given by its PMU encoding. See [`RawEvent`].
+    Raw(RawEvent),
+}
+
+impl EventType {
+    /// The `type_` field of [`PerfEventAttr`] for this event.
+    pub fn type_(self) -> u32 {
+        match self {
+            Self::CpuCycles
+            | Self::Instructions
+            | Self::CacheReferences
+            | Self::CacheMisses
+            | Self::BranchInstructions
+            | Self::BranchMisses
+            | Self::BusCycles
+            | Self::StalledCyclesFrontend
+            | Self::StalledCyclesBackend
+            | Self::RefCpuCycles => PERF_TYPE_HARDWARE,
+            Self::CpuClock
+            | Self::TaskClock
+            | Self::PageFaults
+            | Self::MinorPageFaults
+            | Self::MajorPageFaults
+            | Self::ContextSwitches
+            | Self::CpuMigrations
+            | Self::AlignmentFaults
+            | Self::EmulationFaults => PERF_TYPE_SOFTWARE,
+            Self::HwCache(_) => PERF_TYPE_HW_CACHE,
+            Self::Raw(raw_event) => raw_event.type_,
+        }
+    }
+
+    /// The `config` field of [`PerfEventAttr`] for this event.
+    pub fn config(self) -> u64 {
+        match self {
+            Self::CpuCycles => 0,
+            Self::Instructions => 1,
+            Self::CacheReferences => 2,
+            Self::CacheMisses => 3,
+            Self::BranchInstructions => 4,
+            Self::BranchMisses => 5,
+            Self::BusCycles => 6,
+            Self::StalledCyclesFrontend => 7,
+            Self::StalledCyclesBackend => 8,
+            Self::RefCpuCycles => 9,
+            Self::CpuClock => 0,
+            Self::TaskClock => 1,
+            Self::PageFaults => 2,
+            Self::ContextSwitches => 3,
+            Self::CpuMigrations => 4,
+            Self::MinorPageFaults => 5,
+            Self::MajorPageFaults => 6,
+            Self::AlignmentFaults => 7,
+            Self::EmulationFaults => 8,
+            Self::HwCache(cache_event) => cache_event.config(),
+            Self::Raw(raw_event) => raw_event.config,
+        }
+    }
+
+    /// The `config1` field of [`PerfEventAttr`] for this event. Only
+    /// used by [`RawEvent`].
+    pub fn config1(self) -> u64 {
+        match self {
+            Self::Raw(raw_event) => raw_event.config1,
+            _ => 0,
+        }
+    }
+
+    /// The `config2` field of [`PerfEventAttr`] for this event. Only
+    /// used by [`RawEvent`].
+    pub fn config2(self) -> u64 {
+        match self {
+            Self::Raw(raw_event) => raw_event.config2,
+            _ => 0,
+        }
+    }
+
+    /// Whether the event is counted by the kernel rather than the PMU.
+    pub fn is_software(self) -> bool {
+        self.type_() == PERF_TYPE_SOFTWARE
+    }
+
+    /// The event an attr counts, such as one read from a `perf.data`
+    /// file. Events without a variant of their own are [`Self::Raw`].
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::{EventType, PerfEventAttr, RawEvent};
+    ///
+    /// let attr = PerfEventAttr::new(EventType::BranchMisses);
+    /// assert_eq!(EventType::from_attr(&attr), EventType::BranchMisses);
+    /// // Routed to the PMU of one kind of core of a hybrid CPU
+    /// assert_eq!(EventType::from_attr(&attr.with_pmu_type(8)), EventType::BranchMisses);
+    ///
+    /// let raw = EventType::Raw(RawEvent::new(0x1c2));
+    /// assert_eq!(EventType::from_attr(&PerfEventAttr::new(raw)), raw);
+    /// ```
+    pub fn from_attr(attr: &PerfEventAttr) -> Self {
+        const NAMED: [EventType; 19] = [
+            EventType::CpuCycles,
+            EventType::Instructions,
+            EventType::CacheReferences,
+            EventType::CacheMisses,
+            EventType::BranchInstructions,
+            EventType::BranchMisses,
+            EventType::BusCycles,
+            EventType::StalledCyclesFrontend,
+            EventType::StalledCyclesBackend,
+            EventType::RefCpuCycles,
+            EventType::CpuClock,
+            EventType::TaskClock,
+            EventType::PageFaults,
+            EventType::MinorPageFaults,
+            EventType::MajorPageFaults,
+            EventType::ContextSwitches,
+            EventType::CpuMigrations,
+            EventType::AlignmentFaults,
+            EventType::EmulationFaults,
+        ];
+        let raw = Self::Raw(RawEvent {
+            type_: attr.type_,
+            config: attr.config,
+            config1: attr.config1,
+            config2: attr.config2,
+        });
+        match attr.type_ {
+            PERF_TYPE_HARDWARE | PERF_TYPE_SOFTWARE => {
+                let config = attr.config & PERF_HW_EVENT_MASK;
+                NAMED
+                    .into_iter()
+                    .find(|event| event.type_() == attr.type_ && event.config() == config)
+                    .unwrap_or(raw)
+            }
+            PERF_TYPE_HW_CACHE => {
+                CacheEvent::from_config(attr.config & PERF_HW_EVENT_MASK).map_or(raw, Self::HwCache)
+            }
+            _ => raw,
+        }
+    }
 }
 
 #[repr(C)]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct PerfEventAttr {
     pub type_: u32,
     pub size: u32,
@@ -59,17 +263,17 @@ pub struct PerfEventAttr {
 impl PerfEventAttr {
     pub fn new(event: EventType) -> Self {
         Self {
-            type_: PERF_TYPE_HARDWARE,
+            type_: event.type_(),
             size: size_of::<Self>() as u32,
-            config: event as u64,
+            config: event.config(),
             sample_period_or_freq: 0,
             sample_type: 0,
             read_format: 0,
             flags: 0,
             wakeup_events_or_watermark: 0,
             bp_type: 0,
-            config1: 0,
-            config2: 0,
+            config1: event.config1(),
+            config2: event.config2(),
             branch_sample_type: 0,
             sample_regs_user: 0,
             sample_stack_user: 0,
@@ -90,20 +294,140 @@ impl PerfEventAttr {
 
     pub fn with_perf_format_group(self) -> Self {
         Self {
-            read_format: self.read_format | (1 << 3),
+            read_format: self.read_format | PERF_FORMAT_GROUP,
+            ..self
+        }
+    }
+
+    /// Include the total time the event was enabled and the total time
+    /// it was actually running on the PMU when reading. The two differ
+    /// when the kernel multiplexes events because more were requested
+    /// than there are hardware counters.
+    pub fn with_perf_format_total_times(self) -> Self {
+        Self {
+            read_format: self.read_format
+                | PERF_FORMAT_TOTAL_TIME_ENABLED
+                | PERF_FORMAT_TOTAL_TIME_RUNNING,
+            ..self
+        }
+    }
+
+    /// Route the event to the PMU with type `pmu_type`, as needed on
+    /// hybrid CPUs where each kind of core has its own PMU.
+    ///
+    /// Hardware and cache events carry the PMU in the high bits of
+    /// `config`, raw events for the core PMU take it as their type.
+    /// Other events are unchanged.
+    pub fn with_pmu_type(self, pmu_type: u32) -> Self {
+        match self.type_ {
+            PERF_TYPE_HARDWARE | PERF_TYPE_HW_CACHE => Self {
+                config: (self.config & PERF_HW_EVENT_MASK)
+                    | ((pmu_type as u64) << PERF_PMU_TYPE_SHIFT),
+                ..self
+            },
+            PERF_TYPE_RAW => Self {
+                type_: pmu_type,
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Include the kernel assigned id of each event alongside its
+    /// value when reading. See [`PerfEvent::id`].
+    pub fn with_perf_format_id(self) -> Self {
+        Self {
+            read_format: self.read_format | PERF_FORMAT_ID,
+            ..self
+        }
+    }
+
+    /// Take a sample every `period` occurrences of the event.
+    pub fn with_sample_period(self, period: u64) -> Self {
+        Self {
+            sample_period_or_freq: period,
+            flags: self.flags & !PerfEventFlags::FREQ.bits(),
+            ..self
+        }
+    }
+
+    /// Take around `frequency` samples a second, with the kernel
+    /// adjusting the period to match.
+    pub fn with_sample_freq(self, frequency: u64) -> Self {
+        Self {
+            sample_period_or_freq: frequency,
+            flags: self.flags | PerfEventFlags::FREQ.bits(),
+            ..self
+        }
+    }
+
+    /// The fields to record in each sample.
+    pub fn with_sample_type(self, sample_type: SampleType) -> Self {
+        Self {
+            sample_type: sample_type.bits(),
+            ..self
+        }
+    }
+
+    /// Which branches to record with [`SampleType::BRANCH_STACK`].
+    pub fn with_branch_sample_type(self, branch_sample_type: BranchSampleType) -> Self {
+        Self {
+            branch_sample_type: branch_sample_type.bits(),
+            ..self
+        }
+    }
+
+    /// Wake up readers of the ring buffer after every `events` samples.
+    pub fn with_wakeup_events(self, events: u32) -> Self {
+        Self {
+            wakeup_events_or_watermark: events,
+            flags: self.flags & !PerfEventFlags::WATERMARK.bits(),
             ..self
         }
     }
 }
 
 pub const PERF_TYPE_HARDWARE: u32 = 0;
+pub const PERF_TYPE_SOFTWARE: u32 = 1;
+pub const PERF_TYPE_HW_CACHE: u32 = 3;
+pub const PERF_TYPE_RAW: u32 = 4;
+
+/// Shift of the extended PMU type within `config` of hardware and cache
+/// events.
+pub const PERF_PMU_TYPE_SHIFT: u64 = 32;
+pub const PERF_HW_EVENT_MASK: u64 = 0xffffffff;
+
+pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+pub const PERF_FORMAT_ID: u64 = 1 << 2;
+pub const PERF_FORMAT_GROUP: u64 = 1 << 3;
+pub const PERF_FORMAT_LOST: u64 = 1 << 4;
+
+/// Flags passed to `perf_event_open` itself, rather than in the attrs.
+pub const PERF_FLAG_FD_NO_GROUP: u64 = 1 << 0;
+pub const PERF_FLAG_FD_OUTPUT: u64 = 1 << 1;
+/// `pid` is the fd of a cgroup directory, see [`super::Cgroup`].
+pub const PERF_FLAG_PID_CGROUP: u64 = 1 << 2;
+pub const PERF_FLAG_FD_CLOEXEC: u64 = 1 << 3;
+
+// fcntl commands missing from libc for glibc targets
+const F_SETSIG: i32 = 10;
+const F_SETOWN_EX: i32 = 15;
+const F_OWNER_TID: i32 = 0;
+
+/// `struct f_owner_ex`, the owner to signal for [`F_SETOWN_EX`].
+#[repr(C)]
+struct FOwnerEx {
+    type_: i32,
+    pid: pid_t,
+}
 
 pub const SIZE_OF_U64: usize = size_of::<u64>();
 const SIZE_OF_U64_AS_ISIZE: isize = size_of::<u64>() as isize;
 
 #[derive(Clone)]
 pub struct PerfEvent {
-    _attrs: PerfEventAttr,
+    attrs: PerfEventAttr,
     pub fd: i32,
 }
 
@@ -121,7 +445,7 @@ impl PerfEvent {
             attrs.flags &= (!PerfEventFlags::DISABLED).bits();
             group_fd
         } else {
-            attrs.flags &= (PerfEventFlags::DISABLED).bits();
+            attrs.flags |= PerfEventFlags::DISABLED.bits();
             -1
         };
 
@@ -141,16 +465,116 @@ impl PerfEvent {
                 fd
             }
         };
-        Ok(Self { _attrs: attrs, fd })
+        Ok(Self { attrs, fd })
+    }
+
+    /// The attributes the event was opened with.
+    pub fn attr(&self) -> &PerfEventAttr {
+        &self.attrs
     }
 
     pub fn update_file_state(&self, state: EventIOState) -> io::Result<i32> {
-        let res = unsafe { ioctl(self.fd, state as u64, 0) };
-        if res < 0 {
-            Err(io::Error::last_os_error())
-        } else {
-            Ok(res)
+        self.ioctl(state as Ioctl, 0)
+    }
+
+    /// As [`Self::update_file_state`], but for every event in the group
+    /// of this event at once, as with [`PERF_IOC_FLAG_GROUP`]. Members
+    /// which were disabled on their own are enabled too. Refreshing a
+    /// group is not supported.
+    pub fn update_group_state(&self, state: EventIOState) -> io::Result<i32> {
+        self.ioctl(state as Ioctl, PERF_IOC_FLAG_GROUP)
+    }
+
+    /// Enable the event for `overflows` more overflows of its sample
+    /// period, after which it disables itself and notifies with
+    /// `POLL_HUP` rather than `POLL_IN`. Fails for inherited events.
+    pub fn refresh(&self, overflows: u32) -> io::Result<()> {
+        self.ioctl(PERF_EVENT_IOC_REFRESH, overflows as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Change the sample period while the event is open, or the
+    /// frequency for events sampled by frequency. Takes effect from the
+    /// next overflow.
+    pub fn set_period(&self, period: u64) -> io::Result<()> {
+        self.ioctl(PERF_EVENT_IOC_PERIOD, &period as *const u64 as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Write the records of this event to the ring buffer of `target`
+    /// rather than one of its own, or stop with `None`. The events must
+    /// be on the same CPU, or both follow the same thread, and have the
+    /// same clock.
+    pub fn set_output(&self, target: Option<&PerfEvent>) -> io::Result<()> {
+        let fd = target.map_or(-1, |target| target.fd);
+        self.ioctl(PERF_EVENT_IOC_SET_OUTPUT, fd as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Only count events matching `filter`, for tracepoints such as
+    /// `common_pid == 1234`, or address ranges of instruction tracing
+    /// PMUs. An empty filter removes the filter.
+    pub fn set_filter(&self, filter: &str) -> io::Result<()> {
+        let filter =
+            CString::new(filter).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.ioctl(PERF_EVENT_IOC_SET_FILTER, filter.as_ptr() as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Attach the loaded BPF program `program_fd` to this tracepoint,
+    /// kprobe or uprobe event, to run on every hit.
+    pub fn set_bpf(&self, program_fd: RawFd) -> io::Result<()> {
+        self.ioctl(PERF_EVENT_IOC_SET_BPF, program_fd as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Stop or resume writing records to the ring buffer of this event,
+    /// without stopping the event counting.
+    pub fn pause_output(&self, paused: bool) -> io::Result<()> {
+        self.ioctl(PERF_EVENT_IOC_PAUSE_OUTPUT, paused as c_ulong)
+            .map(|_| ())
+    }
+
+    /// Change the attributes of the open event. Only supported for
+    /// breakpoint events, to move the breakpoint without reopening it.
+    pub fn modify_attributes(&mut self, mut attrs: PerfEventAttr) -> io::Result<()> {
+        self.ioctl(
+            PERF_EVENT_IOC_MODIFY_ATTRIBUTES,
+            &mut attrs as *mut PerfEventAttr as c_ulong,
+        )?;
+        self.attrs = attrs;
+        Ok(())
+    }
+
+    /// Send `signal` to thread `tid` each time the event overflows its
+    /// sample period, with `si_fd` of the signal info set to the fd of
+    /// the event. A realtime signal such as `SIGRTMIN` is queued rather
+    /// than merged with one already pending.
+    pub fn notify_by_signal(&self, signal: i32, tid: u32) -> io::Result<()> {
+        let owner = FOwnerEx {
+            type_: F_OWNER_TID,
+            pid: tid as pid_t,
+        };
+        unsafe {
+            if libc::fcntl(self.fd, F_SETOWN_EX, &owner as *const FOwnerEx) < 0
+                || libc::fcntl(self.fd, F_SETSIG, signal) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let flags = libc::fcntl(self.fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(self.fd, libc::F_SETFL, flags | libc::O_ASYNC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
         }
+        Ok(())
+    }
+
+    /// The kernel assigned id of this event. Matches the id reported
+    /// in reads made with [`PerfEventAttr::with_perf_format_id`].
+    pub fn id(&self) -> io::Result<u64> {
+        let mut id: u64 = 0;
+        self.ioctl(PERF_EVENT_IOC_ID, &mut id as *mut u64 as c_ulong)?;
+        Ok(id)
     }
 
     pub fn get_count(&self) -> io::Result<u64> {
@@ -171,12 +595,25 @@ impl PerfEvent {
         }
     }
 
-    pub fn enable(&mut self) {
-        self._attrs.flags &= !PerfEventFlags::DISABLED.bits();
+    fn ioctl(&self, request: Ioctl, arg: c_ulong) -> io::Result<i32> {
+        let res = unsafe { ioctl(self.fd, request, arg) };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res)
+        }
+    }
+
+    /// Start counting this event. For a group parent this starts the
+    /// whole group, for other members only the member itself.
+    pub fn enable(&self) -> io::Result<()> {
+        self.update_file_state(EventIOState::Enable).map(|_| ())
     }
 
-    pub fn disable(&mut self) {
-        self._attrs.flags |= PerfEventFlags::DISABLED.bits();
+    /// Stop counting this event. For a group parent this stops the
+    /// whole group, for other members only the member itself.
+    pub fn disable(&self) -> io::Result<()> {
+        self.update_file_state(EventIOState::Disable).map(|_| ())
     }
 }
 
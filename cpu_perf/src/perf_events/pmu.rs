@@ -0,0 +1,477 @@
+use std::{
+    fmt, fs, io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use super::{EventType, PerfEventAttr, RawEvent};
+
+/// Where the kernel exposes the PMUs it knows about.
+pub const SYSFS_EVENT_SOURCE_DEVICES: &str = "/sys/bus/event_source/devices";
+
+/// The PMUs exposed by the running kernel, as found under
+/// [`SYSFS_EVENT_SOURCE_DEVICES`].
+///
+/// Used to turn `perf` style `pmu/term=value,.../` strings into events.
+///
+/// # Example
+///
+/// ```
+/// use cpu_perf::perf_events::Pmus;
+///
+/// let pmus = Pmus::discover().unwrap();
+/// let software = pmus.get("software").unwrap();
+/// assert_eq!(software.type_, 1);
+///
+/// // Task clock, as the whole config of a software event
+/// let raw = pmus.parse_event("software/config=1/").unwrap();
+/// assert_eq!((raw.type_, raw.config), (1, 1));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Pmus {
+    pmus: Vec<Pmu>,
+}
+
+impl Pmus {
+    /// Discover the PMUs of the running kernel.
+    pub fn discover() -> io::Result<Self> {
+        Self::discover_in(SYSFS_EVENT_SOURCE_DEVICES)
+    }
+
+    /// Discover PMUs under an alternate root laid out like
+    /// [`SYSFS_EVENT_SOURCE_DEVICES`], with one directory per PMU.
+    ///
+    /// Directories without a `type` file are skipped, as are PMUs which
+    /// cannot be read, with a note, so one unusual PMU does not hide
+    /// the others.
+    pub fn discover_in(root: impl AsRef<Path>) -> io::Result<Self> {
+        let mut pmus = Vec::new();
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            if path.join("type").is_file() {
+                match Pmu::read(&path) {
+                    Ok(pmu) => pmus.push(pmu),
+                    Err(e) => eprintln!("Note: skipping PMU {}: {}", file_name(&path), e),
+                }
+            }
+        }
+        pmus.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { pmus })
+    }
+
+    /// The PMU called `name`.
+    pub fn get(&self, name: &str) -> Option<&Pmu> {
+        self.pmus.iter().find(|pmu| pmu.name == name)
+    }
+
+    /// The PMU whose type is `type_`.
+    pub fn get_by_type(&self, type_: u32) -> Option<&Pmu> {
+        self.pmus.iter().find(|pmu| pmu.type_ == type_)
+    }
+
+    /// Iterate over the PMUs, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Pmu> {
+        self.pmus.iter()
+    }
+
+    /// The PMUs of each kind of core on a hybrid CPU, e.g. `cpu_core`
+    /// and `cpu_atom` on Intel. Empty when the CPU is not hybrid.
+    pub fn core_pmus(&self) -> impl Iterator<Item = &Pmu> {
+        self.pmus.iter().filter(|pmu| pmu.core_type().is_some())
+    }
+
+    /// Whether the CPU has more than one kind of core, each with their
+    /// own PMU.
+    pub fn is_hybrid(&self) -> bool {
+        self.core_pmus().next().is_some()
+    }
+
+    /// The core PMU responsible for `cpu` on a hybrid CPU.
+    pub fn core_pmu_for_cpu(&self, cpu: u32) -> Option<&Pmu> {
+        self.core_pmus()
+            .find(|pmu| pmu.cpus.as_ref().is_some_and(|cpus| cpus.contains(&cpu)))
+    }
+
+    /// Parse a `pmu/term=value,.../` string into the event it describes.
+    ///
+    /// Terms are either format fields of the PMU (`event=0xc2`, a bare
+    /// `edge` meaning `edge=1`), `config`/`config1`/`config2` to set a
+    /// whole field, or names of event aliases the PMU exposes, whose
+    /// terms are expanded in place.
+    pub fn parse_event(&self, event: &str) -> Result<RawEvent, PmuError> {
+        let (pmu_name, terms) = event
+            .strip_suffix('/')
+            .and_then(|event| event.split_once('/'))
+            .ok_or_else(|| PmuError::Malformed(event.to_string()))?;
+        self.get(pmu_name)
+            .ok_or_else(|| PmuError::UnknownPmu(pmu_name.to_string()))?
+            .encode(terms)
+    }
+
+    /// As [`Self::parse_event`] but producing the attributes to open the
+    /// event with.
+    pub fn parse_attr(&self, event: &str) -> Result<PerfEventAttr, PmuError> {
+        Ok(PerfEventAttr::new(EventType::Raw(self.parse_event(event)?)))
+    }
+}
+
+/// A single PMU as described by `/sys/bus/event_source/devices/<name>`.
+#[derive(Debug, Clone)]
+pub struct Pmu {
+    pub name: String,
+    /// The `type_` to open events on this PMU with.
+    pub type_: u32,
+    /// The CPUs events on this PMU should be opened on, from `cpumask`
+    /// or `cpus`. `None` if the PMU does not restrict CPUs.
+    pub cpus: Option<Vec<u32>>,
+    /// How terms map onto bits of the `config` fields.
+    pub formats: Vec<FormatField>,
+    /// Named events and the terms they expand to.
+    pub events: Vec<EventAlias>,
+}
+
+impl Pmu {
+    /// Read the description of the PMU in `dir`.
+    ///
+    /// Format fields which cannot be parsed, such as ones targeting
+    /// `config3` of newer kernels, are left out with a note. Terms
+    /// naming them are then unknown to [`Self::encode`].
+    pub fn read(dir: &Path) -> io::Result<Self> {
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let type_ = read_trimmed(&dir.join("type"))?
+            .parse()
+            .map_err(|_| invalid_data(&dir.join("type")))?;
+
+        let mut cpus = None;
+        for cpus_file in ["cpumask", "cpus"] {
+            let path = dir.join(cpus_file);
+            if path.is_file() {
+                let contents = read_trimmed(&path)?;
+                cpus = Some(parse_cpu_list(&contents).ok_or_else(|| invalid_data(&path))?);
+                break;
+            }
+        }
+
+        let mut formats = Vec::new();
+        for path in read_dir_files(&dir.join("format"))? {
+            let contents = read_trimmed(&path)?;
+            let name = file_name(&path);
+            match FormatField::parse(&name, &contents) {
+                Some(format) => formats.push(format),
+                None => eprintln!(
+                    "Note: skipping format {} of PMU {}: unsupported definition {}",
+                    name,
+                    file_name(dir),
+                    contents
+                ),
+            }
+        }
+
+        let mut events = Vec::new();
+        for path in read_dir_files(&dir.join("events"))? {
+            let name = file_name(&path);
+            // Skip .scale, .unit and similar annotations of other events
+            if name.contains('.') {
+                continue;
+            }
+            events.push(EventAlias {
+                name,
+                terms: read_trimmed(&path)?,
+            });
+        }
+
+        Ok(Self {
+            name,
+            type_,
+            cpus,
+            formats,
+            events,
+        })
+    }
+
+    /// The kind of core this PMU counts for, if it is one of the core
+    /// PMUs of a hybrid CPU.
+    pub fn core_type(&self) -> Option<CoreType> {
+        match self.name.as_str() {
+            "cpu_core" => Some(CoreType::Performance),
+            "cpu_atom" => Some(CoreType::Efficiency),
+            _ => None,
+        }
+    }
+
+    /// The format field called `name`.
+    pub fn format(&self, name: &str) -> Option<&FormatField> {
+        self.formats.iter().find(|format| format.name == name)
+    }
+
+    /// The event alias called `name`.
+    pub fn event(&self, name: &str) -> Option<&EventAlias> {
+        self.events.iter().find(|event| event.name == name)
+    }
+
+    /// Encode comma separated `terms` (the part between the slashes of
+    /// `pmu/.../`) into an event on this PMU.
+    pub fn encode(&self, terms: &str) -> Result<RawEvent, PmuError> {
+        let mut event = RawEvent::new(0).with_type(self.type_);
+        self.encode_into(terms, &mut event, true)?;
+        Ok(event)
+    }
+
+    fn encode_into(
+        &self,
+        terms: &str,
+        event: &mut RawEvent,
+        expand_aliases: bool,
+    ) -> Result<(), PmuError> {
+        for term in terms.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (name, value) = match term.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (term, None),
+            };
+
+            let parse_value = || match value {
+                Some(value) => parse_number(value).ok_or_else(|| PmuError::InvalidValue {
+                    term: name.to_string(),
+                    value: value.to_string(),
+                }),
+                None => Ok(1),
+            };
+            if let Some(format) = self.format(name) {
+                format.apply(parse_value()?, event)?;
+            } else if let ("config" | "config1" | "config2", Some(_)) = (name, value) {
+                // Built in terms setting a whole config field
+                let field = match name {
+                    "config" => &mut event.config,
+                    "config1" => &mut event.config1,
+                    _ => &mut event.config2,
+                };
+                *field = parse_value()?;
+            } else if let (Some(alias), None, true) = (self.event(name), value, expand_aliases) {
+                self.encode_into(&alias.terms, event, false)?;
+            } else {
+                return Err(PmuError::UnknownTerm {
+                    pmu: self.name.clone(),
+                    term: name.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kind of core of a hybrid CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoreType {
+    /// P-cores, counted by the `cpu_core` PMU.
+    Performance,
+    /// E-cores, counted by the `cpu_atom` PMU.
+    Efficiency,
+}
+
+/// Which `config` field of [`PerfEventAttr`] a format field targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigField {
+    Config,
+    Config1,
+    Config2,
+}
+
+/// A format field definition, e.g. `umask` as `config:8-15`. The value
+/// of the term is spread over `bits` from the least significant bit up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatField {
+    pub name: String,
+    pub target: ConfigField,
+    pub bits: Vec<RangeInclusive<u32>>,
+}
+
+impl FormatField {
+    /// Parse the contents of a `format/<name>` file, such as
+    /// `config:0-7,32-35` or `config1:3`.
+    pub fn parse(name: &str, definition: &str) -> Option<Self> {
+        let (target, ranges) = definition.trim().split_once(':')?;
+        let target = match target {
+            "config" => ConfigField::Config,
+            "config1" => ConfigField::Config1,
+            "config2" => ConfigField::Config2,
+            _ => return None,
+        };
+        let mut bits = Vec::new();
+        for range in ranges.split(',') {
+            let range = match range.split_once('-') {
+                Some((start, end)) => start.parse().ok()?..=end.parse().ok()?,
+                None => {
+                    let bit = range.parse().ok()?;
+                    bit..=bit
+                }
+            };
+            if range.is_empty() || *range.end() > 63 {
+                return None;
+            }
+            bits.push(range);
+        }
+        Some(Self {
+            name: name.to_string(),
+            target,
+            bits,
+        })
+    }
+
+    /// The number of bits available for the value.
+    pub fn width(&self) -> u32 {
+        self.bits
+            .iter()
+            .map(|range| range.end() - range.start() + 1)
+            .sum()
+    }
+
+    /// Write `value` into the bits of `event` this field covers.
+    pub fn apply(&self, value: u64, event: &mut RawEvent) -> Result<(), PmuError> {
+        if self.width() < 64 && value >> self.width() != 0 {
+            return Err(PmuError::ValueTooLarge {
+                term: self.name.clone(),
+                value,
+            });
+        }
+        let config = match self.target {
+            ConfigField::Config => &mut event.config,
+            ConfigField::Config1 => &mut event.config1,
+            ConfigField::Config2 => &mut event.config2,
+        };
+        let mut value = value;
+        for range in &self.bits {
+            let width = range.end() - range.start() + 1;
+            let mask = if width == 64 {
+                u64::MAX
+            } else {
+                (1 << width) - 1
+            };
+            *config = (*config & !(mask << range.start())) | ((value & mask) << range.start());
+            value = value.checked_shr(width).unwrap_or(0);
+        }
+        Ok(())
+    }
+}
+
+/// A named event exposed by a PMU, e.g. `cpu-cycles` as `event=0x3c`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventAlias {
+    pub name: String,
+    /// Comma separated terms the alias expands to.
+    pub terms: String,
+}
+
+/// Errors turning `pmu/.../` strings into events.
+#[derive(Debug)]
+pub enum PmuError {
+    /// Not of the form `pmu/terms/`.
+    Malformed(String),
+    UnknownPmu(String),
+    /// Neither a format field nor an event alias of the PMU.
+    UnknownTerm {
+        pmu: String,
+        term: String,
+    },
+    InvalidValue {
+        term: String,
+        value: String,
+    },
+    /// The value does not fit in the bits of the format field.
+    ValueTooLarge {
+        term: String,
+        value: u64,
+    },
+}
+
+impl std::error::Error for PmuError {}
+
+impl fmt::Display for PmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(event) => write!(f, "Expected pmu/terms/ but got {}", event),
+            Self::UnknownPmu(pmu) => write!(f, "Unknown PMU {}", pmu),
+            Self::UnknownTerm { pmu, term } => {
+                write!(f, "Unknown term {} for PMU {}", term, pmu)
+            }
+            Self::InvalidValue { term, value } => {
+                write!(f, "Invalid value {} for term {}", value, term)
+            }
+            Self::ValueTooLarge { term, value } => {
+                write!(f, "Value {:#x} is too large for term {}", value, term)
+            }
+        }
+    }
+}
+
+/// Parse a kernel CPU list such as `0-3,8,10-11`.
+///
+/// ```
+/// use cpu_perf::perf_events::parse_cpu_list;
+///
+/// assert_eq!(parse_cpu_list("0-2,5"), Some(vec![0, 1, 2, 5]));
+/// assert_eq!(parse_cpu_list(""), Some(vec![]));
+/// assert_eq!(parse_cpu_list("3-1"), None);
+/// ```
+pub fn parse_cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (u32, u32) = (start.parse().ok()?, end.parse().ok()?);
+                if start > end {
+                    return None;
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+/// The files in `dir`, sorted, or nothing if `dir` does not exist.
+fn read_dir_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn invalid_data(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected contents in {}", path.display()),
+    )
+}
@@ -0,0 +1,270 @@
+use std::{fs, io};
+
+use super::{EventCounts, EventIOState, EventSpec, Pmus, TaskEventSet};
+
+/// The ids of the threads of process `pid`, from `/proc/<pid>/task`.
+pub fn thread_ids(pid: u32) -> io::Result<Vec<u32>> {
+    let mut tids = Vec::new();
+    for entry in fs::read_dir(format!("/proc/{}/task", pid))? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|tid| tid.parse().ok()) {
+            tids.push(tid);
+        }
+    }
+    tids.sort_unstable();
+    Ok(tids)
+}
+
+/// The name of thread `tid` of process `pid`, from its `comm` file.
+pub fn thread_name(pid: u32, tid: u32) -> io::Result<String> {
+    Ok(
+        fs::read_to_string(format!("/proc/{}/task/{}/comm", pid, tid))?
+            .trim_end()
+            .to_string(),
+    )
+}
+
+/// Counts from a [`ProcessEventSet`], for each thread and for the
+/// whole process.
+#[derive(Default, Clone, Debug)]
+pub struct ProcessCounts {
+    /// Counts for each thread, in the order they were found.
+    pub per_thread: Vec<ThreadCounts>,
+    /// Counts of each group summed over every thread. See
+    /// [`EventCounts::accumulate`] for how scaling applies.
+    pub total: Vec<EventCounts>,
+}
+
+/// The counts of a single thread.
+#[derive(Clone, Debug)]
+pub struct ThreadCounts {
+    pub tid: u32,
+    /// The thread name at the time it was found.
+    pub name: String,
+    /// Whether the thread has exited. Its counts are those at exit.
+    pub exited: bool,
+    /// The counts of each group.
+    pub counts: Vec<EventCounts>,
+}
+
+/// Counts the same groups of events on every thread of an existing
+/// process, with one [`TaskEventSet`] per group and thread.
+///
+/// Counting a process by pid only follows its main thread, and threads
+/// it already created are not inherited. Instead, each thread in
+/// `/proc/<pid>/task` is counted individually and [`Self::refresh`]
+/// picks up threads created since. Threads which are created and exit
+/// between two refreshes are never counted, so counts of processes with
+/// many short-lived threads are incomplete. Use [`super::measure_command`]
+/// to count every thread of a command instead.
+///
+/// Threads which exit keep their final counts, so totals never go
+/// backwards.
+pub struct ProcessEventSet {
+    pid: u32,
+    groups: Vec<Vec<EventSpec>>,
+    /// The core PMUs to route hardware events of new threads to.
+    pmus: Pmus,
+    /// The events of groups left out as none of them are supported.
+    unsupported: Vec<EventSpec>,
+    threads: Vec<ThreadEventSet>,
+    /// Whether counting is currently enabled, applied to new threads.
+    counting: bool,
+    /// Events currently disabled, applied to new threads.
+    disabled: Vec<EventSpec>,
+}
+
+struct ThreadEventSet {
+    tid: u32,
+    name: String,
+    /// One set for each group.
+    sets: Vec<TaskEventSet>,
+}
+
+impl ProcessEventSet {
+    /// Open each of `groups` on every current thread of process `pid`.
+    /// Groups of only unsupported optional events are left out and
+    /// listed by [`Self::unavailable`].
+    pub fn new(pid: u32, groups: &[Vec<EventSpec>]) -> io::Result<Self> {
+        Self::with_pmus(pid, groups, &Pmus::discover()?)
+    }
+
+    /// As [`Self::new`], routing hardware events to the core PMUs in
+    /// `pmus` if the CPU is hybrid.
+    pub fn with_pmus(pid: u32, groups: &[Vec<EventSpec>], pmus: &Pmus) -> io::Result<Self> {
+        if groups.is_empty() {
+            return Err(io::Error::other(
+                "ProcessEventSet requires at least one group",
+            ));
+        }
+        let mut process_set = Self {
+            pid,
+            groups: Vec::new(),
+            pmus: pmus.clone(),
+            unsupported: Vec::new(),
+            threads: Vec::new(),
+            counting: false,
+            disabled: Vec::new(),
+        };
+        // Support does not depend on the thread, so check on the first
+        let tid = *thread_ids(pid)?
+            .first()
+            .ok_or_else(|| io::Error::other(format!("Process {} has no threads", pid)))?;
+        for group in groups {
+            match TaskEventSet::new(group, tid, pmus) {
+                Ok(_) => process_set.groups.push(group.clone()),
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    process_set.unsupported.extend(group.iter().cloned())
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        process_set.refresh()?;
+        if process_set.threads.is_empty() {
+            return Err(io::Error::other(format!(
+                "Process {} has no threads to count",
+                pid
+            )));
+        }
+        Ok(process_set)
+    }
+
+    /// The process being counted.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Open the events on threads created since the last refresh,
+    /// returning how many were added. New threads take on the current
+    /// enabled and disabled state of the set.
+    pub fn refresh(&mut self) -> io::Result<usize> {
+        let mut added = 0;
+        for tid in thread_ids(self.pid)? {
+            if self.threads.iter().any(|thread| thread.tid == tid) {
+                continue;
+            }
+            let sets = match self.open_thread(tid) {
+                Ok(sets) => sets,
+                // The thread exited since it was listed
+                Err(err) if err.raw_os_error() == Some(libc::ESRCH) => continue,
+                Err(err) => {
+                    eprintln!("Error opening events on thread {}", tid);
+                    return Err(err);
+                }
+            };
+            self.threads.push(ThreadEventSet {
+                tid,
+                name: thread_name(self.pid, tid).unwrap_or_default(),
+                sets,
+            });
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// Open a set for each group on thread `tid`, in the current
+    /// enabled and disabled state.
+    fn open_thread(&self, tid: u32) -> io::Result<Vec<TaskEventSet>> {
+        let mut sets = Vec::with_capacity(self.groups.len());
+        for group in &self.groups {
+            let mut set = TaskEventSet::new(group, tid, &self.pmus)?;
+            set.disable(&self.disabled)?;
+            if self.counting {
+                set.update_file_state(EventIOState::Enable)?;
+            }
+            sets.push(set);
+        }
+        Ok(sets)
+    }
+
+    /// The threads being counted, including those which have exited.
+    pub fn thread_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.threads.iter().map(|thread| thread.tid)
+    }
+
+    /// Optional events which are unsupported on any of the threads.
+    pub fn unavailable(&self) -> Vec<EventSpec> {
+        let mut unavailable = self.unsupported.clone();
+        for set in self.threads.iter().flat_map(|thread| &thread.sets) {
+            for event in set.unavailable() {
+                if !unavailable.iter().any(|spec| spec.same_event(&event)) {
+                    unavailable.push(event);
+                }
+            }
+        }
+        unavailable
+    }
+
+    /// Enable tracking of a set of events on every thread.
+    ///
+    /// See [`TaskEventSet::enable`].
+    pub fn enable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        let events: Vec<EventSpec> = events.iter().cloned().map(Into::into).collect();
+        self.disabled
+            .retain(|disabled| !events.iter().any(|event| event.same_event(disabled)));
+        for set in self.threads.iter_mut().flat_map(|thread| &mut thread.sets) {
+            set.enable(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Disable tracking of a set of events on every thread.
+    ///
+    /// See [`TaskEventSet::disable`].
+    pub fn disable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        let events: Vec<EventSpec> = events.iter().cloned().map(Into::into).collect();
+        for event in &events {
+            if !self
+                .disabled
+                .iter()
+                .any(|disabled| disabled.same_event(event))
+            {
+                self.disabled.push(event.clone());
+            }
+        }
+        for set in self.threads.iter_mut().flat_map(|thread| &mut thread.sets) {
+            set.disable(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Update the state of the group on every thread.
+    ///
+    /// See [`TaskEventSet::update_file_state`].
+    pub fn update_file_state(&mut self, state: EventIOState) -> io::Result<()> {
+        match state {
+            EventIOState::Enable => self.counting = true,
+            EventIOState::Disable => self.counting = false,
+            _ => {}
+        }
+        for set in self.threads.iter().flat_map(|thread| &thread.sets) {
+            set.update_file_state(state)?;
+        }
+        Ok(())
+    }
+
+    /// Get the counts of every thread along with their total.
+    pub fn get_counts(&self) -> io::Result<ProcessCounts> {
+        let mut counts = ProcessCounts {
+            per_thread: Vec::with_capacity(self.threads.len()),
+            total: vec![EventCounts::default(); self.groups.len()],
+        };
+        for thread in &self.threads {
+            let thread_counts = thread
+                .sets
+                .iter()
+                .map(TaskEventSet::get_counts)
+                .collect::<io::Result<Vec<_>>>()?;
+            for (total, group_counts) in counts.total.iter_mut().zip(&thread_counts) {
+                total.accumulate(group_counts);
+            }
+            counts.per_thread.push(ThreadCounts {
+                tid: thread.tid,
+                name: thread.name.clone(),
+                exited: !fs::exists(format!("/proc/{}/task/{}", self.pid, thread.tid))
+                    .unwrap_or(false),
+                counts: thread_counts,
+            });
+        }
+        Ok(counts)
+    }
+}
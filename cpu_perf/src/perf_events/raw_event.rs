@@ -0,0 +1,63 @@
+use super::PERF_TYPE_RAW;
+
+/// A model specific event given directly by its PMU encoding.
+///
+/// For events not covered by the other [`super::EventType`] variants,
+/// e.g. offcore responses, which need `config1`/`config2`. The encoding
+/// is found in the CPU vendor's documentation or under
+/// `/sys/bus/event_source/devices/<pmu>/`.
+///
+/// Use through [`super::EventType::Raw`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawEvent {
+    /// The PMU type. `PERF_TYPE_RAW` for the core PMU, otherwise the
+    /// value in `/sys/bus/event_source/devices/<pmu>/type`.
+    pub type_: u32,
+    pub config: u64,
+    pub config1: u64,
+    pub config2: u64,
+}
+
+impl RawEvent {
+    /// A raw event on the core PMU.
+    pub const fn new(config: u64) -> Self {
+        Self {
+            type_: PERF_TYPE_RAW,
+            config,
+            config1: 0,
+            config2: 0,
+        }
+    }
+
+    /// A raw event on the PMU with the given type.
+    pub const fn with_type(self, type_: u32) -> Self {
+        Self { type_, ..self }
+    }
+
+    pub const fn with_config1(self, config1: u64) -> Self {
+        Self { config1, ..self }
+    }
+
+    pub const fn with_config2(self, config2: u64) -> Self {
+        Self { config2, ..self }
+    }
+
+    /// Parse a `perf` style raw code such as `r01c2`, the hexadecimal
+    /// `config` of an event on the core PMU.
+    ///
+    /// Returns `None` if `code` is not an `r` followed by hex digits.
+    ///
+    /// ```
+    /// use cpu_perf::perf_events::RawEvent;
+    ///
+    /// assert_eq!(RawEvent::from_code("r01c2"), Some(RawEvent::new(0x1c2)));
+    /// assert_eq!(RawEvent::from_code("01c2"), None);
+    /// ```
+    pub fn from_code(code: &str) -> Option<Self> {
+        let hex = code.strip_prefix('r')?;
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        u64::from_str_radix(hex, 16).ok().map(Self::new)
+    }
+}
@@ -0,0 +1,866 @@
+use std::fmt;
+
+use super::{
+    PERF_FORMAT_GROUP, PERF_FORMAT_ID, PERF_FORMAT_LOST, PERF_FORMAT_TOTAL_TIME_ENABLED,
+    PERF_FORMAT_TOTAL_TIME_RUNNING, PerfEventAttr,
+    flags::{BranchSampleType, PerfEventFlags, SampleType},
+};
+
+pub const PERF_RECORD_MMAP: u32 = 1;
+pub const PERF_RECORD_LOST: u32 = 2;
+pub const PERF_RECORD_COMM: u32 = 3;
+pub const PERF_RECORD_EXIT: u32 = 4;
+pub const PERF_RECORD_THROTTLE: u32 = 5;
+pub const PERF_RECORD_UNTHROTTLE: u32 = 6;
+pub const PERF_RECORD_FORK: u32 = 7;
+pub const PERF_RECORD_READ: u32 = 8;
+pub const PERF_RECORD_SAMPLE: u32 = 9;
+pub const PERF_RECORD_MMAP2: u32 = 10;
+
+pub const PERF_RECORD_MISC_CPUMODE_MASK: u16 = 0b111;
+pub const PERF_RECORD_MISC_KERNEL: u16 = 1;
+pub const PERF_RECORD_MISC_USER: u16 = 2;
+pub const PERF_RECORD_MISC_HYPERVISOR: u16 = 3;
+pub const PERF_RECORD_MISC_GUEST_KERNEL: u16 = 4;
+pub const PERF_RECORD_MISC_GUEST_USER: u16 = 5;
+/// On mmap records, the mapping is not executable.
+pub const PERF_RECORD_MISC_MMAP_DATA: u16 = 1 << 13;
+/// On comm records, the name changed because of an exec.
+pub const PERF_RECORD_MISC_COMM_EXEC: u16 = 1 << 13;
+/// On samples, the ip is exactly that of the instruction which
+/// overflowed.
+pub const PERF_RECORD_MISC_EXACT_IP: u16 = 1 << 14;
+/// On mmap2 records, a build id is given in place of the inode.
+pub const PERF_RECORD_MISC_MMAP_BUILD_ID: u16 = 1 << 14;
+
+/// Size of the header which starts every record.
+pub const RECORD_HEADER_SIZE: usize = 8;
+
+/// The header which starts every record in the ring buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// One of the `PERF_RECORD_*` types.
+    pub type_: u32,
+    pub misc: u16,
+    /// Size of the record including the header.
+    pub size: u16,
+}
+
+impl RecordHeader {
+    /// Read the header at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..RECORD_HEADER_SIZE)?;
+        Some(Self {
+            type_: u32::from_ne_bytes(bytes[0..4].try_into().ok()?),
+            misc: u16::from_ne_bytes(bytes[4..6].try_into().ok()?),
+            size: u16::from_ne_bytes(bytes[6..8].try_into().ok()?),
+        })
+    }
+
+    /// Where the CPU was when the record was made.
+    pub fn cpu_mode(self) -> CpuMode {
+        CpuMode::from_misc(self.misc)
+    }
+}
+
+/// Where the CPU was when a record was made, from the `misc` field of
+/// its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuMode {
+    Unknown,
+    Kernel,
+    User,
+    Hypervisor,
+    GuestKernel,
+    GuestUser,
+}
+
+impl CpuMode {
+    pub fn from_misc(misc: u16) -> Self {
+        match misc & PERF_RECORD_MISC_CPUMODE_MASK {
+            PERF_RECORD_MISC_KERNEL => Self::Kernel,
+            PERF_RECORD_MISC_USER => Self::User,
+            PERF_RECORD_MISC_HYPERVISOR => Self::Hypervisor,
+            PERF_RECORD_MISC_GUEST_KERNEL => Self::GuestKernel,
+            PERF_RECORD_MISC_GUEST_USER => Self::GuestUser,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// A record read from the ring buffer of a sampling event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    // Boxed as samples are much larger than other records
+    Sample(Box<Sample>),
+    Mmap(Mmap),
+    Mmap2(Mmap2),
+    Comm(Comm),
+    Fork(Task),
+    Exit(Task),
+    Lost(Lost),
+    /// A record of a type which is not decoded, kept as is.
+    Unknown {
+        header: RecordHeader,
+        /// The record after the header.
+        data: Vec<u8>,
+    },
+}
+
+/// A `PERF_RECORD_SAMPLE`. Only the fields in the
+/// [`RecordFormat::sample_type`] of the event are present.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub misc: u16,
+    pub identifier: Option<u64>,
+    pub ip: Option<u64>,
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub time: Option<u64>,
+    pub addr: Option<u64>,
+    pub id: Option<u64>,
+    pub stream_id: Option<u64>,
+    pub cpu: Option<u32>,
+    pub period: Option<u64>,
+    pub read: Option<SampleRead>,
+    /// Instruction pointers, most recent first, interspersed with
+    /// `PERF_CONTEXT_*` markers.
+    pub callchain: Vec<u64>,
+    pub raw: Vec<u8>,
+    /// The hardware index of the most recent branch, with
+    /// [`BranchSampleType::HW_INDEX`].
+    pub branch_hw_index: Option<u64>,
+    pub branch_stack: Vec<BranchEntry>,
+    pub regs_user: Option<SampleRegs>,
+    pub stack_user: Vec<u8>,
+    pub weight: Option<u64>,
+    pub data_src: Option<u64>,
+    pub transaction: Option<u64>,
+    pub regs_intr: Option<SampleRegs>,
+    pub phys_addr: Option<u64>,
+    pub cgroup: Option<u64>,
+    pub data_page_size: Option<u64>,
+    pub code_page_size: Option<u64>,
+    pub aux: Vec<u8>,
+}
+
+impl Sample {
+    /// Where the CPU was when the sample was taken.
+    pub fn cpu_mode(&self) -> CpuMode {
+        CpuMode::from_misc(self.misc)
+    }
+}
+
+/// The counts included in a sample with [`SampleType::READ`], laid out
+/// according to the read format of the event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleRead {
+    pub time_enabled: Option<u64>,
+    pub time_running: Option<u64>,
+    /// One value, or one per group member with `PERF_FORMAT_GROUP`.
+    pub values: Vec<ReadValue>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadValue {
+    pub value: u64,
+    pub id: Option<u64>,
+    pub lost: Option<u64>,
+}
+
+/// A taken branch from [`SampleType::BRANCH_STACK`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchEntry {
+    pub from: u64,
+    pub to: u64,
+    /// Packed prediction, cycles and type of the branch, see
+    /// [`Self::mispredicted`] and the methods alongside it.
+    pub flags: u64,
+}
+
+/// Registers from [`SampleType::REGS_USER`] or
+/// [`SampleType::REGS_INTR`], in the order of the bits of the mask.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleRegs {
+    pub abi: u64,
+    pub regs: Vec<u64>,
+}
+
+/// The fields appended to records other than samples with
+/// [`PerfEventFlags::SAMPLE_ID_ALL`], identifying when and where they
+/// happened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SampleId {
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub time: Option<u64>,
+    pub id: Option<u64>,
+    pub stream_id: Option<u64>,
+    pub cpu: Option<u32>,
+    pub identifier: Option<u64>,
+}
+
+/// A `PERF_RECORD_MMAP`, an executable mapping being created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mmap {
+    pub misc: u16,
+    pub pid: u32,
+    pub tid: u32,
+    pub addr: u64,
+    pub len: u64,
+    pub pgoff: u64,
+    pub filename: String,
+    pub sample_id: SampleId,
+}
+
+/// A `PERF_RECORD_MMAP2`, a mapping being created, with details of the
+/// file it maps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mmap2 {
+    pub misc: u16,
+    pub pid: u32,
+    pub tid: u32,
+    pub addr: u64,
+    pub len: u64,
+    pub pgoff: u64,
+    pub file_id: MmapFileId,
+    pub prot: u32,
+    pub flags: u32,
+    pub filename: String,
+    pub sample_id: SampleId,
+}
+
+impl Mmap2 {
+    /// Whether the mapping is executable code rather than data.
+    pub fn is_executable(&self) -> bool {
+        self.misc & PERF_RECORD_MISC_MMAP_DATA == 0
+    }
+}
+
+/// How an [`Mmap2`] identifies its file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MmapFileId {
+    Inode {
+        maj: u32,
+        min: u32,
+        ino: u64,
+        ino_generation: u64,
+    },
+    BuildId(Vec<u8>),
+}
+
+/// A `PERF_RECORD_COMM`, a thread changing its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comm {
+    pub misc: u16,
+    pub pid: u32,
+    pub tid: u32,
+    pub comm: String,
+    pub sample_id: SampleId,
+}
+
+impl Comm {
+    /// Whether the name changed because the process called exec.
+    pub fn is_exec(&self) -> bool {
+        self.misc & PERF_RECORD_MISC_COMM_EXEC != 0
+    }
+}
+
+/// A `PERF_RECORD_FORK` or `PERF_RECORD_EXIT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Task {
+    pub misc: u16,
+    pub pid: u32,
+    pub ppid: u32,
+    pub tid: u32,
+    pub ptid: u32,
+    pub time: u64,
+    pub sample_id: SampleId,
+}
+
+/// A `PERF_RECORD_LOST`, records dropped because the ring buffer was
+/// full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lost {
+    pub misc: u16,
+    pub id: u64,
+    pub lost: u64,
+    pub sample_id: SampleId,
+}
+
+/// Errors decoding records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordError {
+    /// The record ends before all of its fields.
+    Truncated { type_: u32 },
+    /// The size in the header is smaller than the header or larger than
+    /// the bytes given.
+    BadSize { size: u16, available: usize },
+}
+
+impl std::error::Error for RecordError {}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { type_ } => write!(f, "Record of type {} is truncated", type_),
+            Self::BadSize { size, available } => write!(
+                f,
+                "Record size {} is invalid with {} bytes available",
+                size, available
+            ),
+        }
+    }
+}
+
+/// What the records of an event contain, which depends on the attrs
+/// it was opened with.
+///
+/// # Example
+///
+/// Decoding a synthetic sample followed by a comm record:
+///
+/// ```
+/// use cpu_perf::perf_events::{
+///     PERF_RECORD_COMM, PERF_RECORD_SAMPLE, Record, RecordFormat, flags::SampleType,
+/// };
+///
+/// fn record(type_: u32, misc: u16, body: &[u8]) -> Vec<u8> {
+///     let mut record = type_.to_ne_bytes().to_vec();
+///     record.extend(misc.to_ne_bytes());
+///     record.extend((8 + body.len() as u16).to_ne_bytes());
+///     record.extend(body);
+///     record
+/// }
+///
+/// let format = RecordFormat::new(SampleType::IP | SampleType::TID | SampleType::CALLCHAIN);
+///
+/// let mut sample = Vec::new();
+/// sample.extend(0x4010u64.to_ne_bytes()); // ip
+/// sample.extend(100u32.to_ne_bytes()); // pid
+/// sample.extend(101u32.to_ne_bytes()); // tid
+/// sample.extend(2u64.to_ne_bytes()); // callchain length
+/// sample.extend(0x4010u64.to_ne_bytes());
+/// sample.extend(0x4321u64.to_ne_bytes());
+///
+/// let mut comm = Vec::new();
+/// comm.extend(100u32.to_ne_bytes());
+/// comm.extend(101u32.to_ne_bytes());
+/// comm.extend(b"worker\0\0");
+///
+/// let mut buffer = record(PERF_RECORD_SAMPLE, 2, &sample);
+/// buffer.extend(record(PERF_RECORD_COMM, 0, &comm));
+///
+/// let records: Vec<Record> = format.records(&buffer).collect::<Result<_, _>>().unwrap();
+/// let Record::Sample(sample) = &records[0] else { panic!() };
+/// assert_eq!(sample.ip, Some(0x4010));
+/// assert_eq!((sample.pid, sample.tid), (Some(100), Some(101)));
+/// assert_eq!(sample.callchain, [0x4010, 0x4321]);
+/// let Record::Comm(comm) = &records[1] else { panic!() };
+/// assert_eq!(comm.comm, "worker");
+///
+/// // A record cut short is an error rather than a panic
+/// assert!(format.parse(&buffer[..20]).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordFormat {
+    pub sample_type: SampleType,
+    /// The `PERF_FORMAT_*` flags for [`SampleType::READ`].
+    pub read_format: u64,
+    /// Whether records other than samples end with a [`SampleId`].
+    pub sample_id_all: bool,
+    pub branch_sample_type: BranchSampleType,
+    pub sample_regs_user: u64,
+    pub sample_regs_intr: u64,
+}
+
+impl RecordFormat {
+    pub fn new(sample_type: SampleType) -> Self {
+        Self {
+            sample_type,
+            ..Self::default()
+        }
+    }
+
+    /// The format of records from an event opened with `attr`.
+    pub fn from_attr(attr: &PerfEventAttr) -> Self {
+        Self {
+            sample_type: SampleType::from_bits(attr.sample_type),
+            read_format: attr.read_format,
+            sample_id_all: attr.flags & PerfEventFlags::SAMPLE_ID_ALL.bits() != 0,
+            branch_sample_type: BranchSampleType::from_bits(attr.branch_sample_type),
+            sample_regs_user: attr.sample_regs_user,
+            sample_regs_intr: attr.sample_regs_intr,
+        }
+    }
+
+    pub fn with_read_format(self, read_format: u64) -> Self {
+        Self {
+            read_format,
+            ..self
+        }
+    }
+
+    pub fn with_sample_id_all(self) -> Self {
+        Self {
+            sample_id_all: true,
+            ..self
+        }
+    }
+
+    /// Decode each of the records laid end to end in `bytes`.
+    pub fn records<'a>(&'a self, bytes: &'a [u8]) -> Records<'a> {
+        Records {
+            format: self,
+            raw: RawRecords::new(bytes),
+        }
+    }
+
+    /// Decode the single record in `record`, including its header.
+    pub fn parse(&self, record: &[u8]) -> Result<Record, RecordError> {
+        let header = RecordHeader::parse(record).ok_or(RecordError::BadSize {
+            size: 0,
+            available: record.len(),
+        })?;
+        let size = header.size as usize;
+        if size < RECORD_HEADER_SIZE || size > record.len() {
+            return Err(RecordError::BadSize {
+                size: header.size,
+                available: record.len(),
+            });
+        }
+        let body = &record[RECORD_HEADER_SIZE..size];
+        let truncated = RecordError::Truncated {
+            type_: header.type_,
+        };
+        match header.type_ {
+            PERF_RECORD_SAMPLE => return self.parse_sample(header, body).ok_or(truncated),
+            PERF_RECORD_MMAP | PERF_RECORD_MMAP2 | PERF_RECORD_COMM | PERF_RECORD_FORK
+            | PERF_RECORD_EXIT | PERF_RECORD_LOST => {}
+            _ => {
+                return Ok(Record::Unknown {
+                    header,
+                    data: body.to_vec(),
+                });
+            }
+        }
+
+        // Records other than samples may end with a sample id
+        let (body, sample_id) = match self.sample_id_all {
+            true => {
+                let split = body
+                    .len()
+                    .checked_sub(self.sample_id_size())
+                    .ok_or(truncated.clone())?;
+                let (body, sample_id) = body.split_at(split);
+                (
+                    body,
+                    self.parse_sample_id(sample_id).ok_or(truncated.clone())?,
+                )
+            }
+            false => (body, SampleId::default()),
+        };
+        parse_other(header, body, sample_id).ok_or(truncated)
+    }
+
+    /// Size of the [`SampleId`] at the end of records other than
+    /// samples, when [`Self::sample_id_all`] is set.
+    pub fn sample_id_size(&self) -> usize {
+        [
+            SampleType::TID,
+            SampleType::TIME,
+            SampleType::ID,
+            SampleType::STREAM_ID,
+            SampleType::CPU,
+            SampleType::IDENTIFIER,
+        ]
+        .into_iter()
+        .filter(|&field| self.sample_type.contains(field))
+        .count()
+            * 8
+    }
+
+    fn parse_sample_id(&self, bytes: &[u8]) -> Option<SampleId> {
+        let mut fields = Fields::new(bytes);
+        let mut sample_id = SampleId::default();
+        if self.has(SampleType::TID) {
+            sample_id.pid = Some(fields.u32()?);
+            sample_id.tid = Some(fields.u32()?);
+        }
+        if self.has(SampleType::TIME) {
+            sample_id.time = Some(fields.u64()?);
+        }
+        if self.has(SampleType::ID) {
+            sample_id.id = Some(fields.u64()?);
+        }
+        if self.has(SampleType::STREAM_ID) {
+            sample_id.stream_id = Some(fields.u64()?);
+        }
+        if self.has(SampleType::CPU) {
+            sample_id.cpu = Some(fields.u32()?);
+            fields.u32()?;
+        }
+        if self.has(SampleType::IDENTIFIER) {
+            sample_id.identifier = Some(fields.u64()?);
+        }
+        Some(sample_id)
+    }
+
+    fn parse_sample(&self, header: RecordHeader, body: &[u8]) -> Option<Record> {
+        let mut fields = Fields::new(body);
+        let mut sample = Sample {
+            misc: header.misc,
+            ..Sample::default()
+        };
+        if self.has(SampleType::IDENTIFIER) {
+            sample.identifier = Some(fields.u64()?);
+        }
+        if self.has(SampleType::IP) {
+            sample.ip = Some(fields.u64()?);
+        }
+        if self.has(SampleType::TID) {
+            sample.pid = Some(fields.u32()?);
+            sample.tid = Some(fields.u32()?);
+        }
+        if self.has(SampleType::TIME) {
+            sample.time = Some(fields.u64()?);
+        }
+        if self.has(SampleType::ADDR) {
+            sample.addr = Some(fields.u64()?);
+        }
+        if self.has(SampleType::ID) {
+            sample.id = Some(fields.u64()?);
+        }
+        if self.has(SampleType::STREAM_ID) {
+            sample.stream_id = Some(fields.u64()?);
+        }
+        if self.has(SampleType::CPU) {
+            sample.cpu = Some(fields.u32()?);
+            fields.u32()?;
+        }
+        if self.has(SampleType::PERIOD) {
+            sample.period = Some(fields.u64()?);
+        }
+        if self.has(SampleType::READ) {
+            sample.read = Some(self.parse_read(&mut fields)?);
+        }
+        if self.has(SampleType::CALLCHAIN) {
+            let nr = fields.u64()?;
+            sample.callchain = fields.u64s(nr)?;
+        }
+        if self.has(SampleType::RAW) {
+            let size = fields.u32()?;
+            sample.raw = fields.take(size as usize)?.to_vec();
+            fields.align()?;
+        }
+        if self.has(SampleType::BRANCH_STACK) {
+            let nr = fields.u64()?;
+            if self.branch_sample_type.contains(BranchSampleType::HW_INDEX) {
+                sample.branch_hw_index = Some(fields.u64()?);
+            }
+            for _ in 0..nr {
+                sample.branch_stack.push(BranchEntry {
+                    from: fields.u64()?,
+                    to: fields.u64()?,
+                    flags: fields.u64()?,
+                });
+            }
+        }
+        if self.has(SampleType::REGS_USER) {
+            sample.regs_user = parse_regs(&mut fields, self.sample_regs_user)?;
+        }
+        if self.has(SampleType::STACK_USER) {
+            let size = fields.u64()?;
+            let stack = fields.take(size as usize)?;
+            if size != 0 {
+                let dyn_size = fields.u64()?.min(size);
+                sample.stack_user = stack[..dyn_size as usize].to_vec();
+            }
+        }
+        if self.has(SampleType::WEIGHT) || self.has(SampleType::WEIGHT_STRUCT) {
+            sample.weight = Some(fields.u64()?);
+        }
+        if self.has(SampleType::DATA_SRC) {
+            sample.data_src = Some(fields.u64()?);
+        }
+        if self.has(SampleType::TRANSACTION) {
+            sample.transaction = Some(fields.u64()?);
+        }
+        if self.has(SampleType::REGS_INTR) {
+            sample.regs_intr = parse_regs(&mut fields, self.sample_regs_intr)?;
+        }
+        if self.has(SampleType::PHYS_ADDR) {
+            sample.phys_addr = Some(fields.u64()?);
+        }
+        if self.has(SampleType::CGROUP) {
+            sample.cgroup = Some(fields.u64()?);
+        }
+        if self.has(SampleType::DATA_PAGE_SIZE) {
+            sample.data_page_size = Some(fields.u64()?);
+        }
+        if self.has(SampleType::CODE_PAGE_SIZE) {
+            sample.code_page_size = Some(fields.u64()?);
+        }
+        if self.has(SampleType::AUX) {
+            let size = fields.u64()?;
+            sample.aux = fields.take(size as usize)?.to_vec();
+        }
+        Some(Record::Sample(Box::new(sample)))
+    }
+
+    fn parse_read(&self, fields: &mut Fields) -> Option<SampleRead> {
+        let mut read = SampleRead::default();
+        let nr = match self.read_format & PERF_FORMAT_GROUP != 0 {
+            true => fields.u64()?,
+            false => 1,
+        };
+        // Without a group the value comes before the times
+        let single_value = match nr == 1 && self.read_format & PERF_FORMAT_GROUP == 0 {
+            true => Some(fields.u64()?),
+            false => None,
+        };
+        if self.read_format & PERF_FORMAT_TOTAL_TIME_ENABLED != 0 {
+            read.time_enabled = Some(fields.u64()?);
+        }
+        if self.read_format & PERF_FORMAT_TOTAL_TIME_RUNNING != 0 {
+            read.time_running = Some(fields.u64()?);
+        }
+        for _ in 0..nr {
+            let value = match single_value {
+                Some(value) => value,
+                None => fields.u64()?,
+            };
+            read.values.push(ReadValue {
+                value,
+                id: (self.read_format & PERF_FORMAT_ID != 0)
+                    .then(|| fields.u64())
+                    .flatten(),
+                lost: (self.read_format & PERF_FORMAT_LOST != 0)
+                    .then(|| fields.u64())
+                    .flatten(),
+            });
+        }
+        Some(read)
+    }
+
+    fn has(&self, field: SampleType) -> bool {
+        self.sample_type.contains(field)
+    }
+}
+
+/// Iterator over records laid end to end, see [`RecordFormat::records`].
+///
+/// Stops after the first record with an invalid size, as the start of
+/// the next record is then unknown.
+pub struct Records<'a> {
+    format: &'a RecordFormat,
+    raw: RawRecords<'a>,
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<Record, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.raw
+                .next()?
+                .and_then(|record| self.format.parse(record)),
+        )
+    }
+}
+
+/// Iterator over the bytes of records laid end to end, each including
+/// its header, for when the format of a record depends on the record.
+///
+/// Stops after the first record with an invalid size, as the start of
+/// the next record is then unknown.
+pub struct RawRecords<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawRecords<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Skip `len` bytes which are not records, such as data following
+    /// the last record. Returns `false` and stops if fewer are left.
+    pub fn skip(&mut self, len: usize) -> bool {
+        match self.bytes.get(len..) {
+            Some(rest) => {
+                self.bytes = rest;
+                true
+            }
+            None => {
+                self.bytes = &[];
+                false
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for RawRecords<'a> {
+    type Item = Result<&'a [u8], RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let size = match RecordHeader::parse(self.bytes) {
+            Some(header) if header.size as usize >= RECORD_HEADER_SIZE => header.size as usize,
+            _ => 0,
+        };
+        if size == 0 || size > self.bytes.len() {
+            let available = self.bytes.len();
+            self.bytes = &[];
+            return Some(Err(RecordError::BadSize {
+                size: size as u16,
+                available,
+            }));
+        }
+        let (record, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        Some(Ok(record))
+    }
+}
+
+/// Decode a known record other than a sample, with any sample id
+/// already split off the end of `body`.
+fn parse_other(header: RecordHeader, body: &[u8], sample_id: SampleId) -> Option<Record> {
+    let mut fields = Fields::new(body);
+    let misc = header.misc;
+    match header.type_ {
+        PERF_RECORD_MMAP => Some(Record::Mmap(Mmap {
+            misc,
+            pid: fields.u32()?,
+            tid: fields.u32()?,
+            addr: fields.u64()?,
+            len: fields.u64()?,
+            pgoff: fields.u64()?,
+            filename: c_string(fields.rest()),
+            sample_id,
+        })),
+        PERF_RECORD_MMAP2 => {
+            let (pid, tid) = (fields.u32()?, fields.u32()?);
+            let (addr, len, pgoff) = (fields.u64()?, fields.u64()?, fields.u64()?);
+            let file_id = if misc & PERF_RECORD_MISC_MMAP_BUILD_ID != 0 {
+                let id = fields.take(24)?;
+                let build_id_size = (id[0] as usize).min(20);
+                MmapFileId::BuildId(id[4..4 + build_id_size].to_vec())
+            } else {
+                MmapFileId::Inode {
+                    maj: fields.u32()?,
+                    min: fields.u32()?,
+                    ino: fields.u64()?,
+                    ino_generation: fields.u64()?,
+                }
+            };
+            Some(Record::Mmap2(Mmap2 {
+                misc,
+                pid,
+                tid,
+                addr,
+                len,
+                pgoff,
+                file_id,
+                prot: fields.u32()?,
+                flags: fields.u32()?,
+                filename: c_string(fields.rest()),
+                sample_id,
+            }))
+        }
+        PERF_RECORD_COMM => Some(Record::Comm(Comm {
+            misc,
+            pid: fields.u32()?,
+            tid: fields.u32()?,
+            comm: c_string(fields.rest()),
+            sample_id,
+        })),
+        PERF_RECORD_FORK | PERF_RECORD_EXIT => {
+            let task = Task {
+                misc,
+                pid: fields.u32()?,
+                ppid: fields.u32()?,
+                tid: fields.u32()?,
+                ptid: fields.u32()?,
+                time: fields.u64()?,
+                sample_id,
+            };
+            Some(match header.type_ {
+                PERF_RECORD_FORK => Record::Fork(task),
+                _ => Record::Exit(task),
+            })
+        }
+        PERF_RECORD_LOST => Some(Record::Lost(Lost {
+            misc,
+            id: fields.u64()?,
+            lost: fields.u64()?,
+            sample_id,
+        })),
+        _ => None,
+    }
+}
+
+/// Registers present in `mask`, or `None` if the sample has none.
+fn parse_regs(fields: &mut Fields, mask: u64) -> Option<Option<SampleRegs>> {
+    let abi = fields.u64()?;
+    if abi == 0 {
+        return Some(None);
+    }
+    Some(Some(SampleRegs {
+        abi,
+        regs: fields.u64s(mask.count_ones() as u64)?,
+    }))
+}
+
+/// A string padded with nul bytes.
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Reads native endian fields from the body of a record.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_ne_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_ne_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn u64s(&mut self, count: u64) -> Option<Vec<u64>> {
+        // Bounds the allocation by what is actually present
+        if count > (self.bytes.len() - self.pos) as u64 / 8 {
+            return None;
+        }
+        (0..count).map(|_| self.u64()).collect()
+    }
+
+    /// Skip padding up to the next multiple of 8 bytes.
+    fn align(&mut self) -> Option<()> {
+        let padding = self.pos.next_multiple_of(8) - self.pos;
+        self.take(padding).map(|_| ())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+}
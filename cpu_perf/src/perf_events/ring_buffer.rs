@@ -0,0 +1,203 @@
+use std::{
+    io,
+    os::unix::io::RawFd,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{RECORD_HEADER_SIZE, RecordHeader};
+
+/// The first page of a perf mmap, `struct perf_event_mmap_page`. The
+/// kernel updates it concurrently, so fields must be read through raw
+/// pointers rather than references.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PerfEventMmapPage {
+    pub version: u32,
+    pub compat_version: u32,
+    pub lock: u32,
+    pub index: u32,
+    pub offset: i64,
+    pub time_enabled: u64,
+    pub time_running: u64,
+    pub capabilities: u64,
+    pub pmc_width: u16,
+    pub time_shift: u16,
+    pub time_mult: u32,
+    pub time_offset: u64,
+    pub time_zero: u64,
+    pub size: u32,
+    pub __reserved_1: u32,
+    pub time_cycles: u64,
+    pub time_mask: u64,
+    pub __reserved: [u8; 116 * 8],
+    /// Where the kernel has written up to.
+    pub data_head: u64,
+    /// Where userspace has read up to.
+    pub data_tail: u64,
+    pub data_offset: u64,
+    pub data_size: u64,
+    pub aux_head: u64,
+    pub aux_tail: u64,
+    pub aux_offset: u64,
+    pub aux_size: u64,
+}
+
+/// The size of a page of memory.
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// The ring buffer a sampling event writes records into, mapped into
+/// memory from its file descriptor.
+///
+/// The kernel advances `data_head` as it writes records and only
+/// overwrites data behind `data_tail`, which is advanced as records are
+/// read. Records which run off the end of the buffer wrap around to the
+/// start.
+pub struct RingBuffer {
+    page: *mut PerfEventMmapPage,
+    mmap_len: usize,
+    data: *const u8,
+    data_size: usize,
+    /// Holds records which wrap around the end of the buffer.
+    scratch: Vec<u8>,
+}
+
+// The mapping is owned by the buffer and only read through raw pointers
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    /// Map the ring buffer of the event `fd` with `data_pages` pages of
+    /// data, which must be a power of two.
+    pub fn new(fd: RawFd, data_pages: usize) -> io::Result<Self> {
+        if !data_pages.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Ring buffer pages must be a power of two, got {}",
+                    data_pages
+                ),
+            ));
+        }
+        let page_size = page_size();
+        let mmap_len = (1 + data_pages) * page_size;
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let page = base as *mut PerfEventMmapPage;
+        // Kernels before 4.1 leave these zero, with the data directly
+        // after the first page
+        let (data_offset, data_size) = unsafe {
+            match (
+                ptr::addr_of!((*page).data_offset).read_volatile(),
+                ptr::addr_of!((*page).data_size).read_volatile(),
+            ) {
+                (0, _) | (_, 0) => (page_size, data_pages * page_size),
+                (offset, size) => (offset as usize, size as usize),
+            }
+        };
+        Ok(Self {
+            page,
+            mmap_len,
+            data: unsafe { (base as *const u8).add(data_offset) },
+            data_size,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// The control page at the start of the mapping.
+    pub fn control_page(&self) -> *const PerfEventMmapPage {
+        self.page
+    }
+
+    /// Size of the data area in bytes.
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    fn data_head(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(ptr::addr_of_mut!((*self.page).data_head)) }
+    }
+
+    fn data_tail(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(ptr::addr_of_mut!((*self.page).data_tail)) }
+    }
+
+    /// Whether there are records waiting to be read.
+    pub fn is_empty(&self) -> bool {
+        self.data_head().load(Ordering::Acquire) == self.data_tail().load(Ordering::Relaxed)
+    }
+
+    /// Call `f` with each record written since the last call, including
+    /// its header, then release the space to the kernel. Returns the
+    /// number of records read.
+    pub fn for_each_record(&mut self, mut f: impl FnMut(&[u8])) -> usize {
+        // Acquire pairs with the kernel publishing head after writing
+        // the records, so they are visible once head is
+        let head = self.data_head().load(Ordering::Acquire);
+        let mut tail = self.data_tail().load(Ordering::Relaxed);
+        let mut count = 0;
+        while tail < head {
+            let offset = (tail % self.data_size as u64) as usize;
+            // Records are 8 byte aligned, so headers never wrap
+            let header_bytes =
+                unsafe { std::slice::from_raw_parts(self.data.add(offset), RECORD_HEADER_SIZE) };
+            let size = match RecordHeader::parse(header_bytes) {
+                Some(header)
+                    if header.size as usize >= RECORD_HEADER_SIZE
+                        && header.size as usize <= self.data_size
+                        && tail + header.size as u64 <= head =>
+                {
+                    header.size as usize
+                }
+                // The buffer is corrupt, or the record would run past
+                // what the kernel has written, skip everything so far
+                _ => {
+                    tail = head;
+                    break;
+                }
+            };
+            if offset + size <= self.data_size {
+                f(unsafe { std::slice::from_raw_parts(self.data.add(offset), size) });
+            } else {
+                let first = self.data_size - offset;
+                let mut scratch = std::mem::take(&mut self.scratch);
+                scratch.clear();
+                unsafe {
+                    scratch.extend_from_slice(std::slice::from_raw_parts(
+                        self.data.add(offset),
+                        first,
+                    ));
+                    scratch.extend_from_slice(std::slice::from_raw_parts(self.data, size - first));
+                }
+                f(&scratch);
+                self.scratch = scratch;
+            }
+            tail += size as u64;
+            count += 1;
+        }
+        // Release so the reads above finish before the kernel may
+        // overwrite the space
+        self.data_tail().store(tail, Ordering::Release);
+        count
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut _, self.mmap_len);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::{io, time::Duration};
+
+use super::{
+    EventSpec, PerfEvent, PerfEventAttr, Pmus, Record, RecordError, RecordFormat, RingBuffer,
+    flags::{BranchSampleType, PerfEventFlags, SampleType},
+    online_cpus,
+};
+
+/// How often a sampling event takes a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRate {
+    /// Every this many occurrences of the event.
+    Period(u64),
+    /// Around this many times a second, with the kernel adjusting the
+    /// period to match.
+    Frequency(u64),
+}
+
+/// An event to sample along with what to record in each sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingConfig {
+    pub spec: EventSpec,
+    pub rate: SampleRate,
+    pub sample_type: SampleType,
+    /// Which branches to record, with [`SampleType::BRANCH_STACK`].
+    pub branch_sample_type: BranchSampleType,
+    /// Whether to also record mmaps, thread names, forks and exits,
+    /// which are needed to make sense of sampled addresses.
+    pub tracking: bool,
+    /// Pages of data in each ring buffer, a power of two.
+    pub data_pages: usize,
+    /// Samples to take before waking readers, or 0 to wake them when
+    /// a buffer is half full.
+    pub wakeup_events: u32,
+}
+
+impl SamplingConfig {
+    /// Sample `spec` at [`Self::DEFAULT_FREQUENCY`], recording the ip,
+    /// thread, time, CPU and period of each sample.
+    pub fn new(spec: EventSpec) -> Self {
+        Self {
+            spec,
+            rate: SampleRate::Frequency(Self::DEFAULT_FREQUENCY),
+            sample_type: SampleType::IP
+                | SampleType::TID
+                | SampleType::TIME
+                | SampleType::CPU
+                | SampleType::PERIOD,
+            branch_sample_type: BranchSampleType::default(),
+            tracking: true,
+            data_pages: Self::DEFAULT_DATA_PAGES,
+            wakeup_events: 0,
+        }
+    }
+
+    /// The default frequency of `perf record`.
+    pub const DEFAULT_FREQUENCY: u64 = 4000;
+    pub const DEFAULT_DATA_PAGES: usize = 64;
+
+    pub fn with_rate(self, rate: SampleRate) -> Self {
+        Self { rate, ..self }
+    }
+
+    pub fn with_sample_type(self, sample_type: SampleType) -> Self {
+        Self {
+            sample_type,
+            ..self
+        }
+    }
+
+    /// Also record the callchain of each sample, for stacks of user and
+    /// kernel return addresses. User stacks are found by following frame
+    /// pointers, so code built without them gives short stacks.
+    pub fn with_callchain(self) -> Self {
+        let sample_type = self.sample_type | SampleType::CALLCHAIN;
+        self.with_sample_type(sample_type)
+    }
+
+    /// Also record the stack of most recently taken branches matching
+    /// `branch_sample_type`, such as from the Last Branch Record of
+    /// Intel CPUs. Needs a hardware event and support from the CPU.
+    pub fn with_branch_stack(self, branch_sample_type: BranchSampleType) -> Self {
+        Self {
+            sample_type: self.sample_type | SampleType::BRANCH_STACK,
+            branch_sample_type,
+            ..self
+        }
+    }
+
+    pub fn with_tracking(self, tracking: bool) -> Self {
+        Self { tracking, ..self }
+    }
+
+    pub fn with_data_pages(self, data_pages: usize) -> Self {
+        Self { data_pages, ..self }
+    }
+
+    pub fn with_wakeup_events(self, wakeup_events: u32) -> Self {
+        Self {
+            wakeup_events,
+            ..self
+        }
+    }
+
+    /// The attributes to open the event with.
+    pub fn attr(&self) -> PerfEventAttr {
+        let mut flags = self.spec.flags | PerfEventFlags::SAMPLE_ID_ALL;
+        if self.tracking {
+            flags |= PerfEventFlags::MMAP
+                | PerfEventFlags::MMAP2
+                | PerfEventFlags::COMM
+                | PerfEventFlags::COMM_EXEC
+                | PerfEventFlags::TASK;
+        }
+        let attr = self
+            .spec
+            .clone()
+            .with_flags(flags)
+            .attr()
+            .with_sample_type(self.sample_type)
+            .with_branch_sample_type(self.branch_sample_type)
+            .with_wakeup_events(self.wakeup_events);
+        match self.rate {
+            SampleRate::Period(period) => attr.with_sample_period(period),
+            SampleRate::Frequency(frequency) => attr.with_sample_freq(frequency),
+        }
+    }
+
+    /// The format of the records the event will produce.
+    pub fn record_format(&self) -> RecordFormat {
+        RecordFormat::from_attr(&self.attr())
+    }
+}
+
+/// Samples an event on a set of CPUs or threads, each with its own
+/// ring buffer.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use cpu_perf::perf_events::{EventSpec, EventType, Record, Sampler, SamplingConfig};
+///
+/// let config = SamplingConfig::new(EventSpec::new(EventType::CpuClock));
+/// let mut sampler = Sampler::for_process(config, std::process::id()).unwrap();
+/// sampler.enable().unwrap();
+/// sampler.poll(Some(Duration::from_millis(100))).unwrap();
+/// for record in sampler.read_records().unwrap() {
+///     if let Record::Sample(sample) = record {
+///         println!("{:x?}", sample.ip);
+///     }
+/// }
+/// ```
+pub struct Sampler {
+    format: RecordFormat,
+    buffers: Vec<SampledEvent>,
+}
+
+struct SampledEvent {
+    /// `None` when following a thread across CPUs.
+    cpu: Option<u32>,
+    ring_buffer: RingBuffer,
+    // Dropped after the ring buffer is unmapped
+    perf_event: PerfEvent,
+}
+
+impl Sampler {
+    /// Sample every process on every online CPU.
+    ///
+    /// Needs `perf_event_paranoid` of 0 or lower, or `CAP_PERFMON`.
+    pub fn system_wide(config: SamplingConfig) -> io::Result<Self> {
+        Self::on_cpus(config, &online_cpus()?)
+    }
+
+    /// Sample every process on each of `cpus`.
+    pub fn on_cpus(config: SamplingConfig, cpus: &[u32]) -> io::Result<Self> {
+        let targets: Vec<_> = cpus.iter().map(|&cpu| (None, Some(cpu))).collect();
+        Self::open(config, &targets)
+    }
+
+    /// Sample the thread `pid` wherever it runs. Add
+    /// [`PerfEventFlags::INHERIT`] to the flags of the spec to also
+    /// sample threads and processes it creates from now on.
+    // The kernel refuses to mmap inherited events opened on any CPU,
+    // so there is one event and buffer per CPU
+    pub fn for_process(config: SamplingConfig, pid: u32) -> io::Result<Self> {
+        let targets: Vec<_> = online_cpus()?
+            .into_iter()
+            .map(|cpu| (Some(pid), Some(cpu)))
+            .collect();
+        Self::open(config, &targets)
+    }
+
+    /// Sample each of the existing threads `tids` wherever they run,
+    /// e.g. every thread of a process from [`super::thread_ids`].
+    /// Threads they create later are not sampled.
+    pub fn for_threads(config: SamplingConfig, tids: &[u32]) -> io::Result<Self> {
+        let targets: Vec<_> = tids.iter().map(|&tid| (Some(tid), None)).collect();
+        Self::open(config, &targets)
+    }
+
+    /// Open an event and buffer for each `(pid, cpu)` target.
+    fn open(config: SamplingConfig, targets: &[(Option<u32>, Option<u32>)]) -> io::Result<Self> {
+        if targets.is_empty() {
+            return Err(io::Error::other("Sampler requires at least one target"));
+        }
+        let pmus = Pmus::discover()?;
+        let mut buffers = Vec::with_capacity(targets.len());
+        for &(pid, cpu) in targets {
+            let config = match cpu.and_then(|cpu| pmus.core_pmu_for_cpu(cpu)) {
+                Some(pmu) => SamplingConfig {
+                    spec: config.spec.clone().on_pmu(pmu.type_),
+                    ..config.clone()
+                },
+                None => config.clone(),
+            };
+            let perf_event = PerfEvent::open(
+                config.attr(),
+                None,
+                pid.map_or(-1, |pid| pid as i32),
+                cpu.map_or(-1, |cpu| cpu as i32),
+                0,
+            )
+            .inspect_err(|_| match (pid, cpu) {
+                (_, Some(cpu)) => eprintln!("Error opening sampling event on CPU {}", cpu),
+                (pid, None) => eprintln!("Error opening sampling event on thread {:?}", pid),
+            })?;
+            let ring_buffer = RingBuffer::new(perf_event.fd, config.data_pages)?;
+            buffers.push(SampledEvent {
+                cpu,
+                ring_buffer,
+                perf_event,
+            });
+        }
+        Ok(Self {
+            format: config.record_format(),
+            buffers,
+        })
+    }
+
+    /// The format of the records being produced.
+    pub fn format(&self) -> &RecordFormat {
+        &self.format
+    }
+
+    /// The distinct attributes of the events being sampled, each with
+    /// the ids of the events opened with them. There is more than one
+    /// on hybrid CPUs, where each kind of core has its own PMU.
+    pub fn attrs(&self) -> io::Result<Vec<(PerfEventAttr, Vec<u64>)>> {
+        let mut attrs: Vec<(PerfEventAttr, Vec<u64>)> = Vec::new();
+        for buffer in &self.buffers {
+            let attr = *buffer.perf_event.attr();
+            let id = buffer.perf_event.id()?;
+            match attrs.iter_mut().find(|(other, _)| *other == attr) {
+                Some((_, ids)) => ids.push(id),
+                None => attrs.push((attr, vec![id])),
+            }
+        }
+        Ok(attrs)
+    }
+
+    /// The CPUs being sampled on, when sampling per CPU.
+    pub fn cpus(&self) -> impl Iterator<Item = u32> + '_ {
+        self.buffers.iter().filter_map(|buffer| buffer.cpu)
+    }
+
+    /// Start sampling. Events are opened disabled.
+    pub fn enable(&self) -> io::Result<()> {
+        for buffer in &self.buffers {
+            buffer.perf_event.enable()?;
+        }
+        Ok(())
+    }
+
+    /// Stop sampling. Records already written can still be read.
+    pub fn disable(&self) -> io::Result<()> {
+        for buffer in &self.buffers {
+            buffer.perf_event.disable()?;
+        }
+        Ok(())
+    }
+
+    /// Enable the events for `overflows` more samples each, after which
+    /// they disable themselves. See [`PerfEvent::refresh`].
+    pub fn refresh(&self, overflows: u32) -> io::Result<()> {
+        for buffer in &self.buffers {
+            buffer.perf_event.refresh(overflows)?;
+        }
+        Ok(())
+    }
+
+    /// Change the sample period, or the frequency when sampling by
+    /// [`SampleRate::Frequency`], without reopening the events. See
+    /// [`PerfEvent::set_period`].
+    pub fn set_period(&self, period: u64) -> io::Result<()> {
+        for buffer in &self.buffers {
+            buffer.perf_event.set_period(period)?;
+        }
+        Ok(())
+    }
+
+    /// Send `signal` to thread `tid` on every sample. See
+    /// [`PerfEvent::notify_by_signal`].
+    pub fn notify_by_signal(&self, signal: i32, tid: u32) -> io::Result<()> {
+        for buffer in &self.buffers {
+            buffer.perf_event.notify_by_signal(signal, tid)?;
+        }
+        Ok(())
+    }
+
+    /// Wait until any of the ring buffers has data for the kernel to
+    /// wake readers, or `timeout` passes. Returns whether there is data.
+    pub fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut fds: Vec<libc::pollfd> = self
+            .buffers
+            .iter()
+            .map(|buffer| libc::pollfd {
+                fd: buffer.perf_event.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(i32::MAX as u128) as i32
+        });
+        loop {
+            let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if res >= 0 {
+                return Ok(res > 0);
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Call `f` with each record written since the last read, as raw
+    /// bytes including the header, along with the CPU of its buffer if
+    /// sampling per CPU.
+    pub fn for_each_raw_record(&mut self, mut f: impl FnMut(Option<u32>, &[u8])) {
+        for buffer in &mut self.buffers {
+            let cpu = buffer.cpu;
+            buffer.ring_buffer.for_each_record(|record| f(cpu, record));
+        }
+    }
+
+    /// Decode the records written since the last read. Records are in
+    /// order within each buffer, but the buffers follow one another.
+    ///
+    /// # Errors
+    ///
+    /// Fails on the first record which cannot be decoded. The buffers
+    /// are still emptied.
+    pub fn read_records(&mut self) -> Result<Vec<Record>, RecordError> {
+        let format = self.format;
+        let mut records = Vec::new();
+        let mut first_err = None;
+        self.for_each_raw_record(|_, record| match format.parse(record) {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        });
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(records),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{fs, io, path::Path};
+
+use super::{
+    Cgroup, CoreType, EventCounts, EventIOState, EventSet, EventSpec, Pmus, parse_cpu_list,
+};
+
+/// Where the kernel lists the CPUs which are currently online.
+pub const SYSFS_ONLINE_CPUS: &str = "/sys/devices/system/cpu/online";
+
+/// The CPUs which are currently online.
+pub fn online_cpus() -> io::Result<Vec<u32>> {
+    online_cpus_in(SYSFS_ONLINE_CPUS)
+}
+
+/// The CPUs listed in a file formatted like [`SYSFS_ONLINE_CPUS`].
+pub fn online_cpus_in(path: impl AsRef<Path>) -> io::Result<Vec<u32>> {
+    let path = path.as_ref();
+    parse_cpu_list(&fs::read_to_string(path)?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected contents in {}", path.display()),
+        )
+    })
+}
+
+/// Counts from a [`SystemEventSet`], for each CPU and for the whole
+/// machine.
+#[derive(Default, Clone, Debug)]
+pub struct SystemCounts {
+    /// Counts for each CPU, in CPU order.
+    pub per_cpu: Vec<CpuCounts>,
+    /// Counts summed over every CPU. See [`EventCounts::accumulate`] for
+    /// how scaling applies.
+    pub total: EventCounts,
+}
+
+impl SystemCounts {
+    /// Counts summed over the CPUs with the given kind of core.
+    pub fn total_for(&self, core_type: CoreType) -> EventCounts {
+        let mut total = EventCounts::default();
+        for cpu_counts in &self.per_cpu {
+            if cpu_counts.core_type == Some(core_type) {
+                total.accumulate(&cpu_counts.counts);
+            }
+        }
+        total
+    }
+}
+
+/// The counts of a single CPU.
+#[derive(Clone, Debug)]
+pub struct CpuCounts {
+    pub cpu: u32,
+    /// The kind of core on hybrid CPUs, `None` otherwise.
+    pub core_type: Option<CoreType>,
+    pub counts: EventCounts,
+}
+
+/// Counts the same groups of events on every online CPU, with one
+/// [`EventSet`] per CPU, for a view of the whole machine.
+///
+/// On hybrid CPUs hardware events of each CPU are routed to the PMU of
+/// its kind of core, as a single PMU cannot count on every core.
+///
+/// Counting all processes on a CPU needs `perf_event_paranoid` of 0 or
+/// lower, or `CAP_PERFMON`.
+pub struct SystemEventSet {
+    sets: Vec<CpuEventSet>,
+}
+
+struct CpuEventSet {
+    cpu: u32,
+    core_type: Option<CoreType>,
+    set: EventSet,
+}
+
+impl SystemEventSet {
+    /// Open `groups` on every online CPU. See [`EventSet::from_groups`]
+    /// for which events are counted together.
+    pub fn new<G: AsRef<[EventSpec]>>(groups: &[G]) -> io::Result<Self> {
+        Self::on_cpus(groups, &online_cpus()?)
+    }
+
+    /// Open `groups` on each of `cpus`.
+    pub fn on_cpus<G: AsRef<[EventSpec]>>(groups: &[G], cpus: &[u32]) -> io::Result<Self> {
+        Self::with_pmus(groups, cpus, &Pmus::discover()?)
+    }
+
+    /// Open `groups` on each of `cpus`, routing hardware events to the
+    /// core PMUs in `pmus` if the CPU is hybrid.
+    pub fn with_pmus<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        cpus: &[u32],
+        pmus: &Pmus,
+    ) -> io::Result<Self> {
+        Self::open(groups, cpus, pmus, None)
+    }
+
+    /// Open `groups` on every online CPU, counting only the tasks in
+    /// `cgroup`, e.g. a single container on a shared host.
+    pub fn for_cgroup<G: AsRef<[EventSpec]>>(groups: &[G], cgroup: &Cgroup) -> io::Result<Self> {
+        Self::for_cgroup_on_cpus(groups, cgroup, &online_cpus()?, &Pmus::discover()?)
+    }
+
+    /// Open `groups` on each of `cpus` counting only the tasks in
+    /// `cgroup`, routing hardware events as in [`Self::with_pmus`].
+    pub fn for_cgroup_on_cpus<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        cgroup: &Cgroup,
+        cpus: &[u32],
+        pmus: &Pmus,
+    ) -> io::Result<Self> {
+        Self::open(groups, cpus, pmus, Some(cgroup))
+    }
+
+    fn open<G: AsRef<[EventSpec]>>(
+        groups: &[G],
+        cpus: &[u32],
+        pmus: &Pmus,
+        cgroup: Option<&Cgroup>,
+    ) -> io::Result<Self> {
+        if cpus.is_empty() {
+            return Err(io::Error::other("SystemEventSet requires at least one CPU"));
+        }
+        let mut sets = Vec::with_capacity(cpus.len());
+        for &cpu in cpus {
+            let core_pmu = pmus.core_pmu_for_cpu(cpu);
+            let groups: Vec<Vec<EventSpec>> = groups
+                .iter()
+                .map(|group| {
+                    group
+                        .as_ref()
+                        .iter()
+                        .map(|spec| match core_pmu {
+                            Some(pmu) => spec.clone().on_pmu(pmu.type_),
+                            None => spec.clone(),
+                        })
+                        .collect()
+                })
+                .collect();
+            let set = match cgroup {
+                Some(cgroup) => EventSet::for_cgroup(&groups, cgroup, cpu),
+                None => EventSet::from_groups(&groups, Some(cpu), None),
+            }
+            .inspect_err(|_| eprintln!("Error opening events on CPU {}", cpu))?;
+            sets.push(CpuEventSet {
+                cpu,
+                core_type: core_pmu.and_then(|pmu| pmu.core_type()),
+                set,
+            });
+        }
+        Ok(Self { sets })
+    }
+
+    /// The CPUs being counted on.
+    pub fn cpus(&self) -> impl Iterator<Item = u32> + '_ {
+        self.sets.iter().map(|cpu_set| cpu_set.cpu)
+    }
+
+    /// The [`EventSet`] for each CPU.
+    pub fn sets(&self) -> impl Iterator<Item = (u32, &EventSet)> {
+        self.sets.iter().map(|cpu_set| (cpu_set.cpu, &cpu_set.set))
+    }
+
+    /// Optional events which are unsupported on any of the CPUs.
+    pub fn unavailable(&self) -> Vec<EventSpec> {
+        let mut unavailable: Vec<EventSpec> = Vec::new();
+        for CpuEventSet { set, .. } in &self.sets {
+            for event in set.unavailable() {
+                if !unavailable.iter().any(|spec| spec.same_event(event)) {
+                    unavailable.push(event.clone());
+                }
+            }
+        }
+        unavailable
+    }
+
+    /// Enable tracking of a set of events on every CPU.
+    ///
+    /// See [`EventSet::enable`].
+    pub fn enable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        for CpuEventSet { set, .. } in &mut self.sets {
+            set.enable(events)?;
+        }
+        Ok(())
+    }
+
+    /// Disable tracking of a set of events on every CPU.
+    ///
+    /// See [`EventSet::disable`].
+    pub fn disable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        for CpuEventSet { set, .. } in &mut self.sets {
+            set.disable(events)?;
+        }
+        Ok(())
+    }
+
+    /// Update the state of the group on every CPU.
+    ///
+    /// See [`EventSet::update_file_state`].
+    pub fn update_file_state(&self, state: EventIOState) -> io::Result<()> {
+        for CpuEventSet { set, .. } in &self.sets {
+            set.update_file_state(state)?;
+        }
+        Ok(())
+    }
+
+    /// Get the counts of every CPU along with their total.
+    pub fn get_counts(&self) -> io::Result<SystemCounts> {
+        let mut counts = SystemCounts::default();
+        for cpu_set in &self.sets {
+            let cpu_counts = cpu_set.set.get_counts()?;
+            counts.total.accumulate(&cpu_counts);
+            counts.per_cpu.push(CpuCounts {
+                cpu: cpu_set.cpu,
+                core_type: cpu_set.core_type,
+                counts: cpu_counts,
+            });
+        }
+        Ok(counts)
+    }
+}
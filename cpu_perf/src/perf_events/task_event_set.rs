@@ -0,0 +1,125 @@
+use std::io;
+
+use super::{EventCounts, EventIOState, EventSet, EventSpec, Pmus};
+
+/// A group of events following a single task on whichever CPU it runs,
+/// as for a command or the threads of a process.
+///
+/// On hybrid CPUs each kind of core has its own PMU, and an event on one
+/// of them only counts while the task runs on that kind of core. As
+/// `perf` does for `cpu_core` and `cpu_atom`, a group with generic
+/// hardware or cache events is then opened as one [`EventSet`] per core
+/// PMU, and their counts summed. Software events of the group are only
+/// opened with the first of these sets which opens, so they are counted
+/// once. Elsewhere this is a single [`EventSet`].
+pub struct TaskEventSet {
+    /// One set per core PMU on hybrid CPUs, a single set otherwise.
+    sets: Vec<EventSet>,
+}
+
+impl TaskEventSet {
+    /// Open `specs` as a group on the task `pid`, routing hardware
+    /// events to the core PMUs in `pmus` if the CPU is hybrid.
+    ///
+    /// # Errors
+    ///
+    /// As for [`EventSet::new`]. On hybrid CPUs a group is only
+    /// unsupported if it is unsupported on every core PMU.
+    pub fn new(specs: &[EventSpec], pid: u32, pmus: &Pmus) -> io::Result<Self> {
+        if !pmus.is_hybrid() || !specs.iter().any(EventSpec::is_generic_hardware) {
+            return Ok(Self {
+                sets: vec![EventSet::from_specs(specs, None, Some(pid))?],
+            });
+        }
+        let mut sets = Vec::new();
+        let mut unsupported = None;
+        for pmu in pmus.core_pmus() {
+            // Until a set opens, carry the software events over to the
+            // next PMU rather than dropping them
+            let routed: Vec<EventSpec> = specs
+                .iter()
+                .filter(|spec| sets.is_empty() || spec.is_generic_hardware())
+                .map(|spec| {
+                    if spec.is_generic_hardware() {
+                        spec.clone().on_pmu(pmu.type_)
+                    } else {
+                        spec.clone()
+                    }
+                })
+                .collect();
+            match EventSet::from_specs(&routed, None, Some(pid)) {
+                Ok(set) => sets.push(set),
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => unsupported = Some(err),
+                Err(err) => {
+                    eprintln!("Error opening events on PMU {}", pmu.name);
+                    return Err(err);
+                }
+            }
+        }
+        match (sets.is_empty(), unsupported) {
+            (true, Some(err)) => Err(err),
+            _ => Ok(Self { sets }),
+        }
+    }
+
+    /// The [`EventSet`] of each core PMU, or the only one.
+    pub fn sets(&self) -> impl Iterator<Item = &EventSet> {
+        self.sets.iter()
+    }
+
+    /// Optional events which were requested but which none of the core
+    /// PMUs support.
+    pub fn unavailable(&self) -> Vec<EventSpec> {
+        let mut unavailable: Vec<EventSpec> = Vec::new();
+        for event in self.sets.iter().flat_map(EventSet::unavailable) {
+            let opened = self
+                .sets
+                .iter()
+                .any(|set| set.events().any(|spec| spec.same_event(event)));
+            if !opened && !unavailable.iter().any(|spec| spec.same_event(event)) {
+                unavailable.push(event.clone());
+            }
+        }
+        unavailable
+    }
+
+    /// Enable tracking of a set of events on every PMU.
+    ///
+    /// See [`EventSet::enable`].
+    pub fn enable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        for set in &mut self.sets {
+            set.enable(events)?;
+        }
+        Ok(())
+    }
+
+    /// Disable tracking of a set of events on every PMU.
+    ///
+    /// See [`EventSet::disable`].
+    pub fn disable<E: Clone + Into<EventSpec>>(&mut self, events: &[E]) -> io::Result<()> {
+        for set in &mut self.sets {
+            set.disable(events)?;
+        }
+        Ok(())
+    }
+
+    /// Update the state of the group on every PMU.
+    ///
+    /// See [`EventSet::update_file_state`].
+    pub fn update_file_state(&self, state: EventIOState) -> io::Result<()> {
+        for set in &self.sets {
+            set.update_file_state(state)?;
+        }
+        Ok(())
+    }
+
+    /// The counts of the group, summed over the core PMUs as in
+    /// [`EventCounts::accumulate_core_pmu`].
+    pub fn get_counts(&self) -> io::Result<EventCounts> {
+        let mut counts = self.sets[0].get_counts()?;
+        for set in &self.sets[1..] {
+            counts.accumulate_core_pmu(&set.get_counts()?);
+        }
+        Ok(counts)
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io, ptr,
+    sync::atomic::{Ordering, compiler_fence},
+};
+
+use super::{EventIOState, EventSpec, PerfEvent, PerfEventMmapPage, page_size};
+
+// Bits of the capabilities of the control page
+const CAP_BIT0_IS_DEPRECATED: u64 = 1 << 1;
+const CAP_USER_RDPMC: u64 = 1 << 2;
+
+/// An event counting the calling thread, read from userspace without a
+/// system call, for measuring tight regions of code.
+///
+/// The first page of the event is mapped, and while the event is on
+/// the PMU the kernel publishes which hardware counter it is on and
+/// the count so far in the control page. On x86_64 the counter is then
+/// read with `rdpmc`, at the cost of a few dozen cycles. Otherwise, as
+/// for software events, events the kernel has taken off the PMU to
+/// multiplex others, or when `rdpmc` is disabled in
+/// `/sys/bus/event_source/devices/cpu/rdpmc`, it falls back to `read`.
+///
+/// Counts are only right on the thread which opened the counter.
+///
+/// # Example
+///
+/// ```
+/// use cpu_perf::perf_events::{EventSpec, EventType, UserCounter};
+///
+/// // Falls back to a software event where there is no PMU
+/// let counter = UserCounter::new(EventSpec::new(EventType::Instructions))
+///     .or_else(|_| UserCounter::new(EventSpec::new(EventType::TaskClock)))
+///     .unwrap();
+/// counter.enable().unwrap();
+/// let before = counter.read().unwrap();
+/// let sum: u64 = (0..10_000u64).map(std::hint::black_box).sum();
+/// let after = counter.read().unwrap();
+/// assert!(after > before);
+/// # assert_eq!(sum, 49_995_000);
+/// ```
+pub struct UserCounter {
+    page: *const PerfEventMmapPage,
+    mmap_len: usize,
+    // Dropped after the page is unmapped
+    perf_event: PerfEvent,
+}
+
+impl UserCounter {
+    /// Open `spec` on the calling thread, disabled.
+    pub fn new(spec: EventSpec) -> io::Result<Self> {
+        let perf_event = PerfEvent::open(spec.attr(), None, 0, -1, 0)?;
+        // Just the control page, there are no samples to buffer
+        let mmap_len = page_size();
+        let page = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                perf_event.fd,
+                0,
+            )
+        };
+        if page == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            page: page as *const PerfEventMmapPage,
+            mmap_len,
+            perf_event,
+        })
+    }
+
+    pub fn enable(&self) -> io::Result<()> {
+        self.perf_event.enable()
+    }
+
+    pub fn disable(&self) -> io::Result<()> {
+        self.perf_event.disable()
+    }
+
+    /// Set the count back to zero.
+    pub fn reset(&self) -> io::Result<()> {
+        self.perf_event.update_file_state(EventIOState::Reset)?;
+        Ok(())
+    }
+
+    /// Whether the kernel allows reading the counter with `rdpmc`.
+    /// Even then [`Self::read`] falls back to `read` while the event is
+    /// off the PMU.
+    pub fn has_rdpmc(&self) -> bool {
+        let capabilities = unsafe { ptr::addr_of!((*self.page).capabilities).read_volatile() };
+        cfg!(target_arch = "x86_64")
+            && capabilities & CAP_BIT0_IS_DEPRECATED != 0
+            && capabilities & CAP_USER_RDPMC != 0
+    }
+
+    /// The current count, read with `rdpmc` when possible.
+    pub fn read(&self) -> io::Result<u64> {
+        match self.read_rdpmc() {
+            Some(count) => Ok(count),
+            None => self.perf_event.get_count(),
+        }
+    }
+
+    /// The count from the control page and hardware counter, or `None`
+    /// if the event is not on the PMU or `rdpmc` cannot be used. The
+    /// kernel updates the page under a sequence lock, so it is read
+    /// until the lock is unchanged across the read.
+    #[cfg(target_arch = "x86_64")]
+    fn read_rdpmc(&self) -> Option<u64> {
+        let page = self.page;
+        loop {
+            let seq = unsafe { ptr::addr_of!((*page).lock).read_volatile() };
+            compiler_fence(Ordering::SeqCst);
+            let capabilities = unsafe { ptr::addr_of!((*page).capabilities).read_volatile() };
+            let index = unsafe { ptr::addr_of!((*page).index).read_volatile() };
+            if capabilities & CAP_BIT0_IS_DEPRECATED == 0
+                || capabilities & CAP_USER_RDPMC == 0
+                || index == 0
+            {
+                return None;
+            }
+            let offset = unsafe { ptr::addr_of!((*page).offset).read_volatile() };
+            let width = unsafe { ptr::addr_of!((*page).pmc_width).read_volatile() } as u32;
+            // Sign extend the counter from its width
+            let shift = 64 - width.clamp(1, 64);
+            let pmc = ((rdpmc(index - 1) << shift) as i64) >> shift;
+            compiler_fence(Ordering::SeqCst);
+            if unsafe { ptr::addr_of!((*page).lock).read_volatile() } == seq {
+                return Some(offset.wrapping_add(pmc) as u64);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn read_rdpmc(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Drop for UserCounter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut _, self.mmap_len);
+        }
+    }
+}
+
+/// Read hardware performance counter `counter`.
+#[cfg(target_arch = "x86_64")]
+fn rdpmc(counter: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        std::arch::asm!(
+            "rdpmc",
+            in("ecx") counter,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    (high as u64) << 32 | low as u64
+}
@@ -6,4 +6,6 @@ pub enum Colour {
     GREY = 0xff2e2e2e,
     GREEN = 0xff00ff00,
     BLUE = 0xff0000ff,
+    RED = 0xffff0000,
+    YELLOW = 0xffffff00,
 }
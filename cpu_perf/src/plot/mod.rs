@@ -4,13 +4,22 @@ mod digits;
 use two_dim_array::TwoDimensionalArray;
 
 use crate::{
-    perf_events::EventCounts,
+    perf_events::{CacheEvent, CacheId, CacheOp, CacheResult, EventCounts, EventType},
     plot::{
         colours::Colour,
         digits::{DECIMAL_POINT, ONE, ORDERED_DIGITS, ZERO},
     },
 };
 
+pub const L1D_READ_ACCESS: CacheEvent =
+    CacheEvent::new(CacheId::L1D, CacheOp::Read, CacheResult::Access);
+pub const L1D_READ_MISS: CacheEvent =
+    CacheEvent::new(CacheId::L1D, CacheOp::Read, CacheResult::Miss);
+pub const DTLB_READ_ACCESS: CacheEvent =
+    CacheEvent::new(CacheId::DTLB, CacheOp::Read, CacheResult::Access);
+pub const DTLB_READ_MISS: CacheEvent =
+    CacheEvent::new(CacheId::DTLB, CacheOp::Read, CacheResult::Miss);
+
 pub fn plot_square(
     buffer: &mut TwoDimensionalArray<u32>,
     centre_x: usize,
@@ -45,22 +54,47 @@ pub fn plot_data_from_buffer(
     let point_separation = buffer_width / num_counts;
     let mut x = 0;
     for counts in value_buffer.iter() {
-        if counts.num_cache_references > 0 {
-            let y = buffer_height
-                - buffer_height * counts.num_cache_misses as usize
-                    / counts.num_cache_references as usize;
+        if let Some(ratio) = count_ratio(counts, EventType::CacheMisses, EventType::CacheReferences)
+        {
+            let y = buffer_height - (buffer_height as f64 * ratio) as usize;
             plot_square(plot_buffer, x, y, width, colour);
         }
-        if counts.num_branch_instructions > 0 {
-            let y = buffer_height
-                - buffer_height * counts.num_branch_misses as usize
-                    / counts.num_branch_instructions as usize;
-            plot_square(plot_buffer, x, y, width, 0xff0000ff);
+        if let Some(ratio) = count_ratio(
+            counts,
+            EventType::BranchMisses,
+            EventType::BranchInstructions,
+        ) {
+            let y = buffer_height - (buffer_height as f64 * ratio) as usize;
+            plot_square(plot_buffer, x, y, width, Colour::BLUE as u32);
+        }
+        if let Some(ratio) = count_ratio(
+            counts,
+            EventType::HwCache(L1D_READ_MISS),
+            EventType::HwCache(L1D_READ_ACCESS),
+        ) {
+            let y = buffer_height - (buffer_height as f64 * ratio) as usize;
+            plot_square(plot_buffer, x, y, width, Colour::RED as u32);
+        }
+        if let Some(ratio) = count_ratio(
+            counts,
+            EventType::HwCache(DTLB_READ_MISS),
+            EventType::HwCache(DTLB_READ_ACCESS),
+        ) {
+            let y = buffer_height - (buffer_height as f64 * ratio) as usize;
+            plot_square(plot_buffer, x, y, width, Colour::YELLOW as u32);
         }
         x += point_separation;
     }
 }
 
+/// `numerator / denominator` clamped to `[0, 1]`, or `None` if either
+/// event is missing or the denominator is zero.
+fn count_ratio(counts: &EventCounts, numerator: EventType, denominator: EventType) -> Option<f64> {
+    let denominator = counts.get(denominator).filter(|count| *count > 0)?;
+    let numerator = counts.get(numerator)?;
+    Some((numerator as f64 / denominator as f64).min(1.0))
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn decorate_plot(
     window_buffer: &mut TwoDimensionalArray<u32>,
@@ -173,8 +207,7 @@ fn render_digit(
     digit: &[u8; 128],
     colour: Colour,
 ) {
-    let mut y = y;
-    for row in digit.chunks_exact(8) {
+    for (y, row) in (y..).zip(digit.chunks_exact(8)) {
         let slice = window_buffer.get_mut_panic(y, x..x + 8);
         for i in 0..8 {
             // Could rewrite branchless, but leave it for now
@@ -182,6 +215,5 @@ fn render_digit(
                 slice[i] = colour as u32;
             }
         }
-        y += 1;
     }
 }
@@ -11,17 +11,14 @@ pub struct SlidingBuffer<T> {
     buffer: Box<[T]>,
 }
 
-impl<T: Copy> SlidingBuffer<T> {
+impl<T: Clone> SlidingBuffer<T> {
     // Implementation has current_index range from 0 to window_size.
     // When we access the buffer, we step ahead of current size which
     // recently wrote a value and read the remaining buffer.
     // To update, we increment current_index, or set it to zero if it
     // would cross into the copy of the buffer.
 
-    pub fn new(initial_value: T, window_size: usize) -> Self
-    where
-        T: Copy,
-    {
+    pub fn new(initial_value: T, window_size: usize) -> Self {
         let buffer = vec![initial_value; window_size * 2].into_boxed_slice();
 
         Self {
@@ -44,7 +41,7 @@ impl<T: Copy> SlidingBuffer<T> {
         } else {
             self.current_index += 1;
         }
+        self.buffer[self.current_index + self.window_size] = value.clone();
         self.buffer[self.current_index] = value;
-        self.buffer[self.current_index + self.window_size] = value;
     }
 }
@@ -0,0 +1,213 @@
+use std::{fs, io, path::Path};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+
+const STT_FUNC: u8 = 2;
+const STT_GNU_IFUNC: u8 = 10;
+const SHN_UNDEF: u16 = 0;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+/// A function symbol, at an address relative to the file's own
+/// virtual addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub start: u64,
+    pub size: u64,
+    pub name: String,
+}
+
+impl Symbol {
+    /// Whether `addr` falls within the symbol.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr - self.start < self.size
+    }
+}
+
+/// A `PT_LOAD` segment, mapping part of the file into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadSegment {
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub executable: bool,
+}
+
+/// The function symbols and load segments of a 64-bit little endian
+/// ELF file, from `.symtab` and `.dynsym`.
+#[derive(Debug, Clone, Default)]
+pub struct ElfSymbols {
+    symbols: Vec<Symbol>,
+    segments: Vec<LoadSegment>,
+}
+
+impl ElfSymbols {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let header = bytes
+            .get(..EHDR_SIZE)
+            .ok_or_else(|| malformed("file too short"))?;
+        if &header[..4] != ELF_MAGIC {
+            return Err(malformed("not an ELF file"));
+        }
+        if header[4] != ELFCLASS64 || header[5] != ELFDATA2LSB {
+            return Err(malformed("only 64-bit little endian ELF is supported"));
+        }
+        let phoff = read_u64(header, 32)? as usize;
+        let shoff = read_u64(header, 40)? as usize;
+        let phnum = read_u16(header, 56)? as usize;
+        let shnum = read_u16(header, 60)? as usize;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let phdr = table_entry(bytes, phoff, i, PHDR_SIZE)?;
+            if read_u32(phdr, 0)? == PT_LOAD {
+                segments.push(LoadSegment {
+                    offset: read_u64(phdr, 8)?,
+                    vaddr: read_u64(phdr, 16)?,
+                    filesz: read_u64(phdr, 32)?,
+                    executable: read_u32(phdr, 4)? & PF_X != 0,
+                });
+            }
+        }
+
+        let mut symbols = Vec::new();
+        // .symtab first, so its names win over .dynsym for the same address
+        for wanted in [SHT_SYMTAB, SHT_DYNSYM] {
+            for i in 0..shnum {
+                let shdr = table_entry(bytes, shoff, i, SHDR_SIZE)?;
+                if read_u32(shdr, 4)? != wanted {
+                    continue;
+                }
+                let strtab = table_entry(bytes, shoff, read_u32(shdr, 40)? as usize, SHDR_SIZE)?;
+                let strings = section_data(bytes, strtab)?;
+                let table = section_data(bytes, shdr)?;
+                for sym in table.chunks_exact(SYM_SIZE) {
+                    let sym_type = sym[4] & 0xf;
+                    let value = read_u64(sym, 8)?;
+                    if !matches!(sym_type, STT_FUNC | STT_GNU_IFUNC)
+                        || read_u16(sym, 6)? == SHN_UNDEF
+                        || value == 0
+                    {
+                        continue;
+                    }
+                    let mut size = read_u64(sym, 16)?;
+                    // Symbols without a size, such as hand written
+                    // assembly, run to the end of their section, and are
+                    // cut short at the next symbol below
+                    let shndx = read_u16(sym, 6)? as usize;
+                    if size == 0
+                        && let Ok(section) = table_entry(bytes, shoff, shndx, SHDR_SIZE)
+                    {
+                        let section_end = read_u64(section, 16)? + read_u64(section, 32)?;
+                        size = section_end.saturating_sub(value);
+                    }
+                    symbols.push(Symbol {
+                        start: value,
+                        size,
+                        name: string_at(strings, read_u32(sym, 0)? as usize),
+                    });
+                }
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.start);
+        symbols.dedup_by_key(|symbol| symbol.start);
+        for i in 1..symbols.len() {
+            let next_start = symbols[i].start;
+            let symbol = &mut symbols[i - 1];
+            symbol.size = symbol.size.min(next_start - symbol.start);
+        }
+        Ok(Self { symbols, segments })
+    }
+
+    /// The function symbols, sorted by address.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn segments(&self) -> &[LoadSegment] {
+        &self.segments
+    }
+
+    /// The virtual address in the file of the byte at `offset` in the
+    /// file, through the load segment containing it.
+    pub fn vaddr_for_offset(&self, offset: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .find(|segment| offset >= segment.offset && offset - segment.offset < segment.filesz)
+            .map(|segment| offset - segment.offset + segment.vaddr)
+    }
+
+    /// The symbol containing the file virtual address `vaddr`.
+    pub fn lookup(&self, vaddr: u64) -> Option<&Symbol> {
+        let index = self.symbols.partition_point(|symbol| symbol.start <= vaddr);
+        let symbol = &self.symbols[index.checked_sub(1)?];
+        symbol.contains(vaddr).then_some(symbol)
+    }
+}
+
+fn table_entry(bytes: &[u8], table: usize, index: usize, entry_size: usize) -> io::Result<&[u8]> {
+    index
+        .checked_mul(entry_size)
+        .and_then(|offset| offset.checked_add(table))
+        .and_then(|start| bytes.get(start..start.checked_add(entry_size)?))
+        .ok_or_else(|| malformed("table out of range"))
+}
+
+fn section_data<'a>(bytes: &'a [u8], shdr: &[u8]) -> io::Result<&'a [u8]> {
+    let offset = read_u64(shdr, 24)? as usize;
+    let size = read_u64(shdr, 32)? as usize;
+    bytes
+        .get(offset..offset.saturating_add(size))
+        .ok_or_else(|| malformed("section out of range"))
+}
+
+fn string_at(strings: &[u8], offset: usize) -> String {
+    let bytes = strings.get(offset..).unwrap_or_default();
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| malformed("field out of range"))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| malformed("field out of range"))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> io::Result<u64> {
+    bytes
+        .get(offset..offset + 8)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| malformed("field out of range"))
+}
+
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed ELF: {}", reason),
+    )
+}
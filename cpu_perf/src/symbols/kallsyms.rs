@@ -0,0 +1,66 @@
+use std::{fs, io};
+
+/// Where the kernel lists its symbols.
+pub const PROC_KALLSYMS: &str = "/proc/kallsyms";
+
+/// The text symbols of the running kernel and its modules.
+#[derive(Debug, Clone, Default)]
+pub struct KernelSymbols {
+    /// Sorted by address.
+    symbols: Vec<(u64, String)>,
+}
+
+impl KernelSymbols {
+    /// Read [`PROC_KALLSYMS`]. Returns `None` when addresses are hidden
+    /// by `kptr_restrict`, in which case every address reads as zero.
+    pub fn read() -> io::Result<Option<Self>> {
+        Ok(Self::parse(&fs::read_to_string(PROC_KALLSYMS)?))
+    }
+
+    /// Parse the contents of a file laid out like [`PROC_KALLSYMS`].
+    ///
+    /// ```
+    /// use cpu_perf::symbols::KernelSymbols;
+    ///
+    /// let kallsyms = "\
+    /// ffffffff81000000 T _stext
+    /// ffffffff81001000 t do_one_initcall
+    /// ffffffff81002000 D some_data
+    /// ffffffffc0001000 t ext4_read [ext4]
+    /// ";
+    /// let symbols = KernelSymbols::parse(kallsyms).unwrap();
+    /// assert_eq!(symbols.lookup(0xffffffff81001234), Some("do_one_initcall"));
+    /// assert_eq!(symbols.lookup(0xffffffffc0001008), Some("ext4_read"));
+    ///
+    /// assert!(KernelSymbols::parse("0000000000000000 T _stext\n").is_none());
+    /// ```
+    pub fn parse(kallsyms: &str) -> Option<Self> {
+        let mut symbols = Vec::new();
+        for line in kallsyms.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(addr), Some(kind), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if !matches!(kind, "t" | "T" | "w" | "W") {
+                continue;
+            }
+            if let Ok(addr) = u64::from_str_radix(addr, 16) {
+                symbols.push((addr, name.to_string()));
+            }
+        }
+        if symbols.iter().all(|(addr, _)| *addr == 0) {
+            return None;
+        }
+        symbols.sort_by_key(|(addr, _)| *addr);
+        Some(Self { symbols })
+    }
+
+    /// The name of the symbol containing `addr`, taken to run up to the
+    /// next symbol.
+    pub fn lookup(&self, addr: u64) -> Option<&str> {
+        let index = self.symbols.partition_point(|(start, _)| *start <= addr);
+        Some(self.symbols[index.checked_sub(1)?].1.as_str())
+    }
+}
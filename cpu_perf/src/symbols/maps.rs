@@ -0,0 +1,72 @@
+use std::{fs, io};
+
+/// An executable region of a process' address space mapped from a
+/// file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    /// Offset into the file of `start`.
+    pub pgoff: u64,
+    /// The mapped file, or a pseudo path such as `[vdso]`.
+    pub path: String,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// The offset into the file of `addr`.
+    pub fn file_offset(&self, addr: u64) -> u64 {
+        addr - self.start + self.pgoff
+    }
+}
+
+/// The executable mappings of process `pid`, from `/proc/<pid>/maps`.
+pub fn read_maps(pid: u32) -> io::Result<Vec<Mapping>> {
+    Ok(parse_maps(&fs::read_to_string(format!(
+        "/proc/{}/maps",
+        pid
+    ))?))
+}
+
+/// Parse the executable, named mappings from the contents of a
+/// `/proc/<pid>/maps` file. Lines which do not parse are skipped.
+///
+/// ```
+/// use cpu_perf::symbols::parse_maps;
+///
+/// let maps = "\
+/// 55d0c8a00000-55d0c8a21000 r--p 00000000 fd:01 1835017 /usr/bin/app
+/// 55d0c8a21000-55d0c8b40000 r-xp 00021000 fd:01 1835017 /usr/bin/app
+/// 7ffd1b5f0000-7ffd1b611000 rw-p 00000000 00:00 0       [stack]
+/// 7ffd1b7c4000-7ffd1b7c6000 r-xp 00000000 00:00 0       [vdso]
+/// ";
+/// let mappings = parse_maps(maps);
+/// assert_eq!(mappings.len(), 2);
+/// assert_eq!(mappings[0].path, "/usr/bin/app");
+/// assert_eq!(mappings[0].file_offset(0x55d0c8a21010), 0x21010);
+/// assert_eq!(mappings[1].path, "[vdso]");
+/// ```
+pub fn parse_maps(maps: &str) -> Vec<Mapping> {
+    maps.lines().filter_map(parse_maps_line).collect()
+}
+
+fn parse_maps_line(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, char::is_whitespace);
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?;
+    let pgoff = fields.next()?;
+    let (_dev, _inode) = (fields.next()?, fields.next()?);
+    let path = fields.next()?.trim();
+    if !perms.contains('x') || path.is_empty() {
+        return None;
+    }
+    Some(Mapping {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        pgoff: u64::from_str_radix(pgoff, 16).ok()?,
+        path: path.to_string(),
+    })
+}
@@ -0,0 +1,297 @@
+mod elf;
+mod kallsyms;
+mod maps;
+
+pub use elf::*;
+pub use kallsyms::*;
+pub use maps::*;
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::perf_events::{CpuMode, Record};
+
+/// Addresses at or above this are in the kernel on 64-bit Linux.
+const KERNEL_START: u64 = 0xffff_0000_0000_0000;
+
+/// Where a sampled address was resolved to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// The function name, if a symbol was found.
+    pub symbol: Option<String>,
+    /// The file the address is mapped from, `[kernel]` for the kernel,
+    /// if known.
+    pub object: Option<String>,
+}
+
+impl Location {
+    /// The symbol name, or `[unknown]`.
+    pub fn symbol_or_unknown(&self) -> &str {
+        self.symbol.as_deref().unwrap_or("[unknown]")
+    }
+}
+
+/// Turns sampled addresses into function names.
+///
+/// Tracks the executable mappings of each process, read from
+/// `/proc/<pid>/maps` the first time a process is seen and updated from
+/// mmap, comm and fork records. Addresses are translated back to the
+/// virtual addresses of the mapped ELF file, accounting for the load
+/// bias of position independent executables and shared libraries, and
+/// looked up in its `.symtab` and `.dynsym`. Kernel addresses are
+/// looked up in `/proc/kallsyms` when readable.
+///
+/// # Example
+///
+/// Resolving a function of the running process:
+///
+/// ```
+/// use cpu_perf::{perf_events::CpuMode, symbols::Resolver};
+///
+/// #[inline(never)]
+/// fn some_function_to_find() {}
+///
+/// let mut resolver = Resolver::new();
+/// let addr = some_function_to_find as usize as u64;
+/// let location = resolver.resolve(std::process::id(), addr, CpuMode::User);
+/// assert!(location.symbol_or_unknown().contains("some_function_to_find"));
+/// ```
+#[derive(Default)]
+pub struct Resolver {
+    /// Executable mappings of each process, sorted by address.
+    processes: HashMap<u32, Vec<Mapping>>,
+    /// Parsed files by path, `None` if they could not be read.
+    files: HashMap<String, Option<Rc<ElfSymbols>>>,
+    /// Names of processes, from comm records or `/proc/<pid>/comm`.
+    names: HashMap<u32, String>,
+    kernel: Option<KernelSymbols>,
+    /// Whether processes are only known from records, as when reading a
+    /// recording, rather than also from `/proc`.
+    recorded: bool,
+}
+
+impl Resolver {
+    /// A resolver with kernel symbols if `/proc/kallsyms` is readable.
+    pub fn new() -> Self {
+        Self {
+            kernel: KernelSymbols::read().ok().flatten(),
+            ..Self::default()
+        }
+    }
+
+    /// A resolver for the records of a recording such as a `perf.data`
+    /// file, which only knows processes from their records, as those in
+    /// `/proc` with the same pids are not the ones recorded. Kernel
+    /// symbols are still those of the running kernel.
+    pub fn for_recording() -> Self {
+        Self {
+            recorded: true,
+            ..Self::new()
+        }
+    }
+
+    /// Whether kernel addresses can be resolved.
+    pub fn has_kernel_symbols(&self) -> bool {
+        self.kernel.is_some()
+    }
+
+    /// Start tracking `pid` from its current `/proc/<pid>/maps`,
+    /// replacing anything known about it.
+    pub fn add_process(&mut self, pid: u32) -> std::io::Result<()> {
+        let mut mappings = read_maps(pid)?;
+        mappings.sort_by_key(|mapping| mapping.start);
+        self.processes.insert(pid, mappings);
+        Ok(())
+    }
+
+    /// Add a mapping of `pid`, replacing any it overlaps.
+    pub fn add_mapping(&mut self, pid: u32, mapping: Mapping) {
+        let mappings = self.processes.entry(pid).or_default();
+        mappings.retain(|old| old.end <= mapping.start || old.start >= mapping.end);
+        let index = mappings.partition_point(|old| old.start < mapping.start);
+        mappings.insert(index, mapping);
+    }
+
+    /// Update the tracked mappings from a record of a sampling event
+    /// with tracking enabled. Other records are ignored.
+    pub fn handle_record(&mut self, record: &Record) {
+        match record {
+            Record::Mmap(mmap) => self.add_mapping(
+                mmap.pid,
+                Mapping {
+                    start: mmap.addr,
+                    end: mmap.addr + mmap.len,
+                    pgoff: mmap.pgoff,
+                    path: mmap.filename.clone(),
+                },
+            ),
+            Record::Mmap2(mmap) if mmap.is_executable() => self.add_mapping(
+                mmap.pid,
+                Mapping {
+                    start: mmap.addr,
+                    end: mmap.addr + mmap.len,
+                    pgoff: mmap.pgoff,
+                    path: mmap.filename.clone(),
+                },
+            ),
+            Record::Comm(comm) if comm.pid == comm.tid => {
+                self.names.insert(comm.pid, comm.comm.clone());
+                // After exec the old mappings are gone
+                if comm.is_exec() {
+                    self.processes.insert(comm.pid, Vec::new());
+                }
+            }
+            // A new process starts with a copy of its parent's mappings
+            Record::Fork(task) if task.pid != task.ppid => {
+                if let Some(mappings) = self.processes.get(&task.ppid).cloned() {
+                    self.processes.insert(task.pid, mappings);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The name of process `pid`, `[unknown]` if it exited before it
+    /// was seen.
+    pub fn process_name(&mut self, pid: u32) -> &str {
+        let recorded = self.recorded;
+        self.names.entry(pid).or_insert_with(|| {
+            if recorded {
+                return "[unknown]".to_string();
+            }
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .map(|comm| comm.trim_end().to_string())
+                .unwrap_or_else(|_| "[unknown]".to_string())
+        })
+    }
+
+    /// Resolve `addr` sampled in process `pid` while the CPU was in
+    /// `mode`.
+    pub fn resolve(&mut self, pid: u32, addr: u64, mode: CpuMode) -> Location {
+        if mode == CpuMode::Kernel || addr >= KERNEL_START {
+            return Location {
+                symbol: self
+                    .kernel
+                    .as_ref()
+                    .and_then(|kernel| kernel.lookup(addr))
+                    .map(str::to_string),
+                object: Some("[kernel]".to_string()),
+            };
+        }
+        if !self.recorded && !self.processes.contains_key(&pid) {
+            // The process may have exited, leaving it without mappings
+            let _ = self.add_process(pid);
+        }
+        let Some(mapping) = self
+            .processes
+            .get(&pid)
+            .and_then(|mappings| mappings.iter().find(|mapping| mapping.contains(addr)))
+            .cloned()
+        else {
+            return Location {
+                symbol: None,
+                object: None,
+            };
+        };
+        let symbol = self.file(pid, &mapping.path).and_then(|elf| {
+            let vaddr = elf.vaddr_for_offset(mapping.file_offset(addr))?;
+            Some(elf.lookup(vaddr)?.name.clone())
+        });
+        Location {
+            symbol,
+            object: Some(mapping.path),
+        }
+    }
+
+    /// The symbols of the file at `path`, read on first use. Files of
+    /// processes in other mount namespaces are found through
+    /// `/proc/<pid>/root`.
+    fn file(&mut self, pid: u32, path: &str) -> Option<Rc<ElfSymbols>> {
+        if !path.starts_with('/') {
+            return None;
+        }
+        self.files
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                ElfSymbols::open(path)
+                    .or_else(|_| ElfSymbols::open(format!("/proc/{}/root{}", pid, path)))
+                    .ok()
+                    .map(Rc::new)
+            })
+            .clone()
+    }
+}
+
+/// Sample counts per function, for a table of the hottest functions.
+///
+/// ```
+/// use cpu_perf::symbols::{FunctionProfile, Location};
+///
+/// let location = |symbol: &str| Location {
+///     symbol: Some(symbol.to_string()),
+///     object: Some("/usr/bin/app".to_string()),
+/// };
+/// let mut profile = FunctionProfile::default();
+/// profile.add(location("parse"), 3);
+/// profile.add(location("render"), 1);
+/// profile.add(location("parse"), 4);
+///
+/// let hottest = profile.hottest(1);
+/// assert_eq!(hottest[0].location.symbol_or_unknown(), "parse");
+/// assert_eq!(hottest[0].weight, 7);
+/// assert_eq!(hottest[0].percent(profile.total()), 87.5);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProfile {
+    weights: HashMap<Location, u64>,
+    total: u64,
+}
+
+/// A row of [`FunctionProfile::hottest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotFunction {
+    pub location: Location,
+    pub weight: u64,
+}
+
+impl HotFunction {
+    /// The share of `total` this function accounts for.
+    pub fn percent(&self, total: u64) -> f64 {
+        match total {
+            0 => 0.0,
+            total => self.weight as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+impl FunctionProfile {
+    /// Add `weight` to `location`, e.g. 1 per sample or the sample
+    /// period.
+    pub fn add(&mut self, location: Location, weight: u64) {
+        *self.weights.entry(location).or_default() += weight;
+        self.total += weight;
+    }
+
+    /// The total weight of every function.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The `count` functions with the most weight, heaviest first.
+    pub fn hottest(&self, count: usize) -> Vec<HotFunction> {
+        let mut functions: Vec<HotFunction> = self
+            .weights
+            .iter()
+            .map(|(location, &weight)| HotFunction {
+                location: location.clone(),
+                weight,
+            })
+            .collect();
+        functions.sort_by(|a, b| {
+            b.weight
+                .cmp(&a.weight)
+                .then_with(|| a.location.symbol.cmp(&b.location.symbol))
+        });
+        functions.truncate(count);
+        functions
+    }
+}
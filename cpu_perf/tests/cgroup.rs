@@ -0,0 +1,83 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use cpu_perf::perf_events::{Cgroup, cgroup2_root_in};
+
+const DOCKER_ID: &str = "d0c4e1aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+const NESTED_NAME_ID: &str = "c0ffeecccccccccccccccccccccccccccccccccccccccccccccccccccccccccc";
+const PODMAN_ID: &str = "5e2f01bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+fn mountinfo(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/mountinfo")
+        .join(name)
+}
+
+/// A cgroup hierarchy with Docker containers named `proxy` and `db`, the
+/// latter with a label called `Name` in its config, and a Podman
+/// container with host name `web`, and the system root holding
+/// Docker's metadata and `/proc` for them.
+fn containers() -> (PathBuf, PathBuf) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/containers");
+    (root.join("cgroup"), root.join("sysroot"))
+}
+
+#[test]
+fn finds_unified_hierarchy() {
+    let root = cgroup2_root_in(mountinfo("unified")).unwrap();
+    assert_eq!(root, PathBuf::from("/sys/fs/cgroup"));
+}
+
+#[test]
+fn finds_cgroup2_beside_v1_controllers() {
+    let root = cgroup2_root_in(mountinfo("hybrid")).unwrap();
+    assert_eq!(root, PathBuf::from("/sys/fs/cgroup/unified"));
+}
+
+#[test]
+fn rejects_v1_only_hierarchies() {
+    let err = cgroup2_root_in(mountinfo("v1")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains("No cgroup v2 hierarchy"));
+}
+
+#[test]
+fn unescapes_mount_points() {
+    let root = cgroup2_root_in(mountinfo("escaped")).unwrap();
+    assert_eq!(root, PathBuf::from("/mnt/cgroup v2"));
+}
+
+#[test]
+fn finds_containers_by_docker_name() {
+    let (root, sysroot) = containers();
+    let cgroup = Cgroup::find_named_container_in(&root, &sysroot, "proxy").unwrap();
+    let scope = format!("system.slice/docker-{}.scope", DOCKER_ID);
+    assert_eq!(cgroup.path(), root.join(scope));
+    // Volume names are not container names
+    let err = Cgroup::find_named_container_in(&root, &sysroot, "cache").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn reads_the_top_level_docker_name() {
+    let (root, sysroot) = containers();
+    let cgroup = Cgroup::find_named_container_in(&root, &sysroot, "db").unwrap();
+    let scope = format!("system.slice/docker-{}.scope", NESTED_NAME_ID);
+    assert_eq!(cgroup.path(), root.join(scope));
+    let err = Cgroup::find_named_container_in(&root, &sysroot, "decoy").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn finds_containers_by_hostname() {
+    let (root, sysroot) = containers();
+    let cgroup = Cgroup::find_named_container_in(&root, &sysroot, "web").unwrap();
+    let scope = format!("machine.slice/libpod-{}.scope", PODMAN_ID);
+    assert_eq!(cgroup.path(), root.join(scope));
+
+    let cgroup = Cgroup::find_named_container_in(&root, &sysroot, &DOCKER_ID[..12]).unwrap();
+    assert!(
+        cgroup
+            .path()
+            .ends_with(format!("docker-{}.scope", DOCKER_ID))
+    );
+}
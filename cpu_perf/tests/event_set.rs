@@ -0,0 +1,95 @@
+use std::{
+    hint::black_box,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use cpu_perf::perf_events::{
+    EventCounts, EventIOState, EventParser, EventSet, EventSpec, EventType,
+};
+
+/// Keep the thread on the CPU for `duration`, so its events count.
+fn spin(duration: Duration) {
+    let started = Instant::now();
+    let mut i = 0u64;
+    while started.elapsed() < duration {
+        i = black_box(i.wrapping_add(1));
+    }
+}
+
+fn count(set: &EventSet) {
+    set.update_file_state(EventIOState::Enable).unwrap();
+    spin(Duration::from_millis(100));
+    set.update_file_state(EventIOState::Disable).unwrap();
+}
+
+#[test]
+fn finds_parsed_events_by_type() {
+    let parser = EventParser::default();
+    let clock = parser.parse_event("task-clock").unwrap();
+    let user_faults = parser.parse_event("page-faults:u").unwrap();
+    let counts = EventCounts::new(vec![(clock, 10), (user_faults.clone(), 20)], 100, 100);
+    assert_eq!(counts.get(EventType::TaskClock), Some(10));
+    // Other flags make another event
+    assert_eq!(counts.get(EventType::PageFaults), None);
+    assert_eq!(counts.get(user_faults_spec(&parser)), Some(20));
+}
+
+fn user_faults_spec(parser: &EventParser) -> EventSpec {
+    parser
+        .parse_event("page-faults:u")
+        .unwrap()
+        .with_name("faults")
+}
+
+#[test]
+fn counts_each_group_separately() {
+    let groups = [
+        vec![
+            EventSpec::new(EventType::TaskClock),
+            EventSpec::new(EventType::PageFaults),
+        ],
+        vec![EventSpec::new(EventType::ContextSwitches)],
+    ];
+    let set = EventSet::from_groups(&groups, None, Some(0)).unwrap();
+    let opened: Vec<usize> = set.groups().map(|group| group.len()).collect();
+    assert_eq!(opened, [2, 1]);
+
+    count(&set);
+    let counts = set.get_counts().unwrap();
+    assert!(counts.get(EventType::TaskClock).unwrap() > 0);
+    assert!(counts.get(EventType::ContextSwitches).is_some());
+    // Software events are never multiplexed
+    assert!(!counts.is_scaled());
+    assert_eq!(counts.running_ratio_of(EventType::PageFaults), Some(1.0));
+}
+
+#[test]
+fn scales_groups_which_do_not_all_fit() {
+    // Far more hardware events than any PMU has counters, in groups
+    // which each fit on their own
+    let groups: Vec<Vec<EventSpec>> = (0..16)
+        .map(|_| {
+            vec![
+                EventSpec::new(EventType::CpuCycles).optional(),
+                EventSpec::new(EventType::Instructions).optional(),
+                EventSpec::new(EventType::BranchInstructions).optional(),
+            ]
+        })
+        .collect();
+    let set = match EventSet::from_groups(&groups, None, Some(0)) {
+        Err(err) if err.kind() == ErrorKind::Unsupported => {
+            eprintln!("Skipping, there are no hardware counters: {}", err);
+            return;
+        }
+        set => set.unwrap(),
+    };
+    assert_eq!(set.groups().count(), 16);
+
+    count(&set);
+    let counts = set.get_counts().unwrap();
+    assert!(counts.is_scaled());
+    assert!(counts.running_ratio() < 1.0);
+    let cycles = counts.get(EventType::CpuCycles).unwrap();
+    assert!(counts.get_scaled(EventType::CpuCycles).unwrap() >= cycles);
+}
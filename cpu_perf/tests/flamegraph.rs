@@ -0,0 +1,50 @@
+//! Golden tests of the rendered SVGs against `testdata/flamegraph`.
+//!
+//! After an intended change to the output, regenerate the fixtures with
+//! `UPDATE_GOLDEN=1 cargo test --test flamegraph` and review the diff.
+
+use std::{env, fs, path::PathBuf};
+
+use cpu_perf::flamegraph::{FlameGraph, FoldedStacks};
+
+fn testdata(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/flamegraph")
+        .join(name)
+}
+
+fn read_stacks(name: &str) -> FoldedStacks {
+    FoldedStacks::parse(&fs::read_to_string(testdata(name)).unwrap()).unwrap()
+}
+
+/// Compare `svg` with the fixture `name`, or overwrite the fixture if
+/// `UPDATE_GOLDEN` is set.
+fn assert_golden(svg: &str, name: &str) {
+    let path = testdata(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, svg).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap();
+    assert!(
+        svg == golden,
+        "{} differs from the rendered SVG, rerun with UPDATE_GOLDEN=1 if the change is intended",
+        path.display()
+    );
+}
+
+#[test]
+fn renders_golden_flamegraph() {
+    let svg = FlameGraph::new()
+        .with_title("Golden & <escaped>")
+        .render(&read_stacks("before.folded"));
+    assert_golden(&svg, "flamegraph.svg");
+}
+
+#[test]
+fn renders_golden_diff() {
+    let svg = FlameGraph::new()
+        .with_title("Golden diff")
+        .render_diff(&read_stacks("before.folded"), &read_stacks("after.folded"));
+    assert_golden(&svg, "diff.svg");
+}
@@ -0,0 +1,119 @@
+use std::io::{Cursor, ErrorKind};
+
+use cpu_perf::{
+    perf_data::{
+        Features, HEADER_COMPRESSED, PERF_RECORD_AUXTRACE, PERF_RECORD_ID_INDEX, PerfData,
+        PerfDataEvent, PerfDataWriter,
+    },
+    perf_events::{EventType, PERF_RECORD_SAMPLE, PerfEventAttr, Record, flags::SampleType},
+};
+
+fn record(type_: u32, body: &[u8]) -> Vec<u8> {
+    let mut record = type_.to_ne_bytes().to_vec();
+    record.extend(0u16.to_ne_bytes());
+    record.extend((8 + body.len() as u16).to_ne_bytes());
+    record.extend(body);
+    record
+}
+
+fn u64s(fields: &[u64]) -> Vec<u8> {
+    fields
+        .iter()
+        .flat_map(|field| field.to_ne_bytes())
+        .collect()
+}
+
+/// Two events with different formats, the ids of the second only given
+/// by a `PERF_RECORD_ID_INDEX`, as perf writes for pipes and AUX area
+/// recordings.
+fn write_file(records: &[Vec<u8>]) -> Vec<u8> {
+    let sample_type = SampleType::IDENTIFIER | SampleType::IP | SampleType::PERIOD;
+    let events = vec![
+        PerfDataEvent {
+            attr: PerfEventAttr::new(EventType::CpuClock).with_sample_type(sample_type),
+            ids: vec![1],
+            name: "cpu-clock".to_string(),
+        },
+        PerfDataEvent {
+            attr: PerfEventAttr::new(EventType::TaskClock)
+                .with_sample_type(sample_type | SampleType::CALLCHAIN),
+            ids: Vec::new(),
+            name: "task-clock".to_string(),
+        },
+    ];
+    let mut writer = PerfDataWriter::new(Cursor::new(Vec::new()), events).unwrap();
+    for record in records {
+        writer.write_record(record).unwrap();
+    }
+    writer.finish(&Features::default()).unwrap().into_inner()
+}
+
+fn id_index() -> Vec<u8> {
+    // One entry: id 7 is of event 1, opened on CPU 2 for thread 100
+    record(PERF_RECORD_ID_INDEX, &u64s(&[1, 7, 1, 2, 100]))
+}
+
+fn callchain_sample(id: u64) -> Vec<u8> {
+    record(
+        PERF_RECORD_SAMPLE,
+        &u64s(&[id, 0x4010, 3, 2, 0x4010, 0x5000]),
+    )
+}
+
+#[test]
+fn matches_ids_from_the_id_index() {
+    let perf_data = PerfData::parse(write_file(&[id_index(), callchain_sample(7)])).unwrap();
+    let records: Vec<Record> = perf_data.records().map(Result::unwrap).collect();
+    let Record::Sample(sample) = &records[1] else {
+        panic!("expected a sample, got {:?}", records);
+    };
+    assert_eq!(sample.callchain, [0x4010, 0x5000]);
+    assert_eq!(perf_data.sample_event(sample).unwrap().name, "task-clock");
+    assert_eq!(perf_data.event_for_id(7).unwrap().name, "task-clock");
+}
+
+#[test]
+fn steps_over_auxtrace_data() {
+    let trace = [0xff; 16];
+    // Size, offset and reference, then index, thread, CPU and padding
+    let mut body = u64s(&[trace.len() as u64, 0, 0]);
+    body.extend(
+        [0u32, 100, 2, 0]
+            .iter()
+            .flat_map(|field| field.to_ne_bytes()),
+    );
+    let mut auxtrace = record(PERF_RECORD_AUXTRACE, &body);
+    auxtrace.extend(trace);
+
+    let file = write_file(&[id_index(), auxtrace, callchain_sample(7)]);
+    let perf_data = PerfData::parse(file).unwrap();
+    let records: Vec<Record> = perf_data.records().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 3);
+    assert!(
+        matches!(&records[1], Record::Unknown { header, .. } if header.type_ == PERF_RECORD_AUXTRACE)
+    );
+    assert!(matches!(&records[2], Record::Sample(sample) if sample.callchain.len() == 2));
+}
+
+#[test]
+fn rejects_truncated_auxtrace_data() {
+    let mut body = u64s(&[64, 0, 0]);
+    body.extend([0u8; 16]);
+    let file = write_file(&[record(PERF_RECORD_AUXTRACE, &body)]);
+    let perf_data = PerfData::parse(file).unwrap();
+    let records: Vec<_> = perf_data.records().collect();
+    assert!(matches!(&records[..], [Err(_)]));
+}
+
+#[test]
+fn rejects_compressed_files() {
+    let mut file = write_file(&[callchain_sample(7)]);
+    // Set the feature bit in the header, as perf record -z does
+    let mut features = u64::from_ne_bytes(file[72..80].try_into().unwrap());
+    features |= 1 << HEADER_COMPRESSED;
+    file[72..80].copy_from_slice(&features.to_ne_bytes());
+
+    let err = PerfData::parse(file).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(err.to_string().contains("perf inject"));
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use cpu_perf::perf_events::{CoreType, Pmus};
+
+/// A hybrid CPU laid out like `/sys/bus/event_source/devices`, with a
+/// `config3` format field and a PMU whose type does not parse.
+fn fixture() -> Pmus {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/sysfs/devices");
+    Pmus::discover_in(root).unwrap()
+}
+
+#[test]
+fn discovers_readable_pmus() {
+    let pmus = fixture();
+    let names: Vec<&str> = pmus.iter().map(|pmu| pmu.name.as_str()).collect();
+    assert_eq!(names, ["cpu_atom", "cpu_core", "software"]);
+
+    let core = pmus.get("cpu_core").unwrap();
+    assert_eq!(core.type_, 4);
+    assert_eq!(core.cpus, Some((0..8).collect()));
+    assert_eq!(core.core_type(), Some(CoreType::Performance));
+    assert!(core.event("cpu-cycles.unit").is_none());
+    assert_eq!(pmus.get("software").unwrap().cpus, None);
+}
+
+#[test]
+fn skips_unsupported_format_fields() {
+    let pmus = fixture();
+    let core = pmus.get("cpu_core").unwrap();
+    assert!(core.format("snoop").is_none());
+    assert!(core.format("offcore_rsp").is_some());
+    assert!(pmus.parse_event("cpu_core/snoop=1/").is_err());
+}
+
+#[test]
+fn routes_cpus_to_core_pmus() {
+    let pmus = fixture();
+    assert!(pmus.is_hybrid());
+    assert_eq!(pmus.core_pmu_for_cpu(3).unwrap().name, "cpu_core");
+    assert_eq!(pmus.core_pmu_for_cpu(9).unwrap().name, "cpu_atom");
+    assert!(pmus.core_pmu_for_cpu(12).is_none());
+}
+
+#[test]
+fn encodes_terms_and_aliases() {
+    let pmus = fixture();
+    let raw = pmus
+        .parse_event("cpu_core/event=0xc2,umask=0x1,edge/")
+        .unwrap();
+    assert_eq!((raw.type_, raw.config), (4, 0x401c2));
+
+    let raw = pmus
+        .parse_event("cpu_core/cpu-cycles,offcore_rsp=0x10001/")
+        .unwrap();
+    assert_eq!((raw.config, raw.config1), (0x3c, 0x10001));
+
+    let raw = pmus.parse_event("cpu_atom/cpu-cycles/").unwrap();
+    assert_eq!((raw.type_, raw.config), (10, 0x3c));
+
+    assert!(pmus.parse_event("cpu_core/bogus=1/").is_err());
+    assert!(pmus.parse_event("cpu_core/umask=0x100/").is_err());
+    assert!(pmus.parse_event("broken/event=1/").is_err());
+}
@@ -0,0 +1,94 @@
+use std::{
+    fs::File,
+    mem::offset_of,
+    os::unix::{fs::FileExt, io::FromRawFd},
+};
+
+use cpu_perf::perf_events::{PERF_RECORD_SAMPLE, PerfEventMmapPage, RingBuffer, page_size};
+
+/// A ring buffer of one data page backed by a memfd, which the test
+/// writes records into in place of the kernel.
+fn ring_buffer() -> (File, RingBuffer) {
+    let fd = unsafe { libc::memfd_create(c"ring_buffer".as_ptr(), libc::MFD_CLOEXEC) };
+    assert!(fd >= 0);
+    let file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(2 * page_size() as u64).unwrap();
+    let buffer = RingBuffer::new(fd, 1).unwrap();
+    (file, buffer)
+}
+
+/// Write a record header claiming `size` bytes at `offset` in the data.
+fn write_header(file: &File, offset: usize, size: u16) {
+    let mut header = Vec::new();
+    header.extend_from_slice(&PERF_RECORD_SAMPLE.to_ne_bytes());
+    header.extend_from_slice(&0u16.to_ne_bytes());
+    header.extend_from_slice(&size.to_ne_bytes());
+    file.write_at(&header, (page_size() + offset) as u64)
+        .unwrap();
+}
+
+fn write_head(file: &File, head: u64) {
+    let offset = offset_of!(PerfEventMmapPage, data_head);
+    file.write_at(&head.to_ne_bytes(), offset as u64).unwrap();
+}
+
+fn write_tail(file: &File, tail: u64) {
+    let offset = offset_of!(PerfEventMmapPage, data_tail);
+    file.write_at(&tail.to_ne_bytes(), offset as u64).unwrap();
+}
+
+fn records(buffer: &mut RingBuffer) -> Vec<usize> {
+    let mut sizes = Vec::new();
+    buffer.for_each_record(|record| sizes.push(record.len()));
+    sizes
+}
+
+#[test]
+fn reads_records_up_to_head() {
+    let (file, mut buffer) = ring_buffer();
+    write_header(&file, 0, 16);
+    write_header(&file, 16, 24);
+    write_head(&file, 40);
+    assert_eq!(records(&mut buffer), [16, 24]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn skips_records_larger_than_the_buffer() {
+    let (file, mut buffer) = ring_buffer();
+    write_header(&file, 0, 16);
+    write_header(&file, 16, (page_size() + 8) as u16);
+    write_head(&file, 16 + page_size() as u64 + 8);
+    assert_eq!(records(&mut buffer), [16]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn skips_records_past_head() {
+    let (file, mut buffer) = ring_buffer();
+    write_header(&file, 0, 16);
+    write_header(&file, 16, 64);
+    write_head(&file, 48);
+    assert_eq!(records(&mut buffer), [16]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn joins_records_which_wrap_around() {
+    let (file, mut buffer) = ring_buffer();
+    // The header fills the end of the data and the body starts it again
+    let tail = page_size() - 8;
+    write_header(&file, tail, 24);
+    let body: Vec<u8> = (1..=16).collect();
+    file.write_at(&body, page_size() as u64).unwrap();
+    write_tail(&file, tail as u64);
+    write_head(&file, tail as u64 + 24);
+
+    let mut records = Vec::new();
+    buffer.for_each_record(|record| records.push(record.to_vec()));
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0][..4], PERF_RECORD_SAMPLE.to_ne_bytes());
+    assert_eq!(records[0][6..8], 24u16.to_ne_bytes());
+    assert_eq!(records[0][8..], body);
+    assert!(buffer.is_empty());
+}